@@ -0,0 +1,288 @@
+use crate::api_key::ApiKeyError;
+use crate::api_types::FieldError;
+use crate::fetcher::FetchError;
+use crate::persist::random_hex_id;
+
+use hyper::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+
+// すべてのエンドポイントで共通のエラー（JSONでは {"success": false, "error": {...}}、HTMLではエラーページ）
+// 以前からある従来のパスでは、以前の形 {"success": false, "error": "メッセージ"} で返す
+
+// ========== リクエストID ==========
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+// リクエストの処理中に作るエラーへリクエストIDを付ける
+pub async fn with_request_id<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+// リクエストの外（非同期ジョブのワーカー等）ではNone
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|request_id| request_id.clone()).ok()
+}
+
+// ========== 従来の形式 ==========
+// /api/v1より前からあるJSON APIのパス（これ以外のパスは/api/v1と同じ形で返す）
+const LEGACY_PATHS: &[&str] = &["/api/process", "/api/statistics", "/api/admin/login"];
+const LEGACY_PATH_PREFIXES: &[&str] = &["/api/keys/"];
+
+pub fn is_legacy_path(path: &str) -> bool {
+    LEGACY_PATHS.contains(&path) || LEGACY_PATH_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+tokio::task_local! {
+    static LEGACY_ENVELOPE: bool;
+}
+
+// 従来のパスへのリクエストの処理中は、JSONのエラーを以前の形で返す
+pub async fn with_envelope<F: Future>(legacy: bool, future: F) -> F::Output {
+    LEGACY_ENVELOPE.scope(legacy, future).await
+}
+
+// リクエストの外ではfalse（/api/v1と同じ形）
+pub fn uses_legacy_envelope() -> bool {
+    LEGACY_ENVELOPE.try_with(|legacy| *legacy).unwrap_or(false)
+}
+
+// /api/v1の形のJSONを以前の形に直す
+// errorオブジェクトはメッセージの文字列にする（入力検証エラーはメッセージに含める）。成功時のerrorはnull
+pub fn to_legacy_json(body: &str) -> String {
+    let mut value = match serde_json::from_str::<Value>(body) {
+        Ok(Value::Object(map)) => map,
+        _ => return body.to_string(),
+    };
+    let message = match value.remove("error") {
+        Some(Value::Object(error)) => Value::String(legacy_message(&error)),
+        Some(error) => error,
+        None => Value::Null,
+    };
+    value.insert("error".to_string(), message);
+    serde_json::to_string(&value).unwrap_or_else(|_| body.to_string())
+}
+
+fn legacy_message(error: &serde_json::Map<String, Value>) -> String {
+    let message = error.get("message").and_then(|v| v.as_str()).unwrap_or_default();
+    let field_errors: Vec<String> = error
+        .get("field_errors")
+        .and_then(|v| v.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| Some(format!("{}: {}", item.get("field")?.as_str()?, item.get("message")?.as_str()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    if field_errors.is_empty() {
+        message.to_string()
+    } else {
+        format!("{}（{}）", message, field_errors.join("、"))
+    }
+}
+
+pub fn generate_request_id() -> String {
+    random_hex_id(8)
+}
+
+// ========== エラーコード ==========
+// クライアントが判定に使う安定した識別子（メッセージは変わることがある）
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // リクエストの不備
+    MissingUrl,
+    InvalidUrl,
+    InvalidOption,
+    InvalidJson,
+    InvalidRequest,
+    UnsupportedMediaType,
+    // APIキーごとの上限（ジョブ・監視・保存したページ・ブックマークの数）を超えた
+    QuotaExceeded,
+    // 認証・権限
+    InvalidApiKey,
+    AdminRequired,
+    PostNotAllowed,
+    // リソース
+    NotFound,
+    KeyNotFound,
+    KeyAlreadyExists,
+    FingerprintNotFound,
+    FingerprintAlreadyRegistered,
+    JobNotFound,
+    WatchNotFound,
+    SnapshotNotFound,
+    SavedPageNotFound,
+    BookmarkNotFound,
+    // 取得したコンテンツを処理できない
+    UnsupportedContent,
+    // 上流サイトからの取得
+    UpstreamBlocked,
+    UpstreamDns,
+    UpstreamConnect,
+    UpstreamTls,
+    UpstreamTimeout,
+    UpstreamRedirect,
+    UpstreamHttpStatus,
+    UpstreamTooLarge,
+    UpstreamDecode,
+    UpstreamNetwork,
+    // サーバー側
+    StorageError,
+    InternalError,
+    JobQueueUnavailable,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::MissingUrl
+            | ErrorCode::InvalidUrl
+            | ErrorCode::InvalidOption
+            | ErrorCode::InvalidJson
+            | ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InvalidApiKey | ErrorCode::AdminRequired => StatusCode::UNAUTHORIZED,
+            ErrorCode::PostNotAllowed | ErrorCode::UpstreamBlocked => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound | ErrorCode::KeyNotFound | ErrorCode::FingerprintNotFound | ErrorCode::JobNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::WatchNotFound | ErrorCode::SnapshotNotFound | ErrorCode::SavedPageNotFound | ErrorCode::BookmarkNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::KeyAlreadyExists | ErrorCode::FingerprintAlreadyRegistered => StatusCode::CONFLICT,
+            ErrorCode::UnsupportedContent => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::UpstreamDns
+            | ErrorCode::UpstreamConnect
+            | ErrorCode::UpstreamTls
+            | ErrorCode::UpstreamRedirect
+            | ErrorCode::UpstreamHttpStatus
+            | ErrorCode::UpstreamTooLarge
+            | ErrorCode::UpstreamDecode
+            | ErrorCode::UpstreamNetwork => StatusCode::BAD_GATEWAY,
+            ErrorCode::StorageError | ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::JobQueueUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(|s| s.to_string()))
+            .unwrap_or_default()
+    }
+}
+
+// ========== エラー ==========
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    /// HTTPステータス（レスポンスのステータスと同じ）
+    pub status: u16,
+    /// 表示用のメッセージ
+    pub message: String,
+    /// サーバーのログと照合するためのID（X-Request-Idヘッダーと同じ。非同期ジョブの結果ではnull）
+    pub request_id: Option<String>,
+    /// 入力検証エラー（codeがinvalid_requestの場合のみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_errors: Option<Vec<FieldError>>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            status: code.status().as_u16(),
+            message: message.into(),
+            request_id: current_request_id(),
+            field_errors: None,
+        }
+    }
+
+    pub fn invalid_request(field_errors: Vec<FieldError>) -> Self {
+        let mut error = Self::new(ErrorCode::InvalidRequest, "リクエストの内容が不正です");
+        error.field_errors = Some(field_errors);
+        error
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<&FetchError> for ApiError {
+    fn from(error: &FetchError) -> Self {
+        Self::new(error.code(), error.to_string())
+    }
+}
+
+impl From<ApiKeyError> for ApiError {
+    fn from(error: ApiKeyError) -> Self {
+        let code = match error {
+            ApiKeyError::AdminRequired => ErrorCode::AdminRequired,
+            ApiKeyError::KeyNotFound => ErrorCode::KeyNotFound,
+            ApiKeyError::KeyAlreadyExists => ErrorCode::KeyAlreadyExists,
+            ApiKeyError::FingerprintAlreadyRegistered => ErrorCode::FingerprintAlreadyRegistered,
+            ApiKeyError::FingerprintNotFound => ErrorCode::FingerprintNotFound,
+            ApiKeyError::FileError(_) => ErrorCode::StorageError,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+// ========== エラーレスポンス ==========
+// APIレスポンスの型を持たないエンドポイントのエラー（ApiResponseのエラーと同じ形）
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub error: ApiError,
+}
+
+impl ErrorResponse {
+    pub fn new(error: ApiError) -> Self {
+        Self { success: false, error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn legacy_json_flattens_the_error() {
+        let error = ApiError::invalid_request(vec![FieldError { field: "images".to_string(), message: "不正な値です".to_string() }]);
+        let body = serde_json::to_string(&ErrorResponse::new(error)).unwrap();
+        let legacy: Value = serde_json::from_str(&to_legacy_json(&body)).unwrap();
+        assert_eq!(legacy, json!({
+            "success": false,
+            "error": "リクエストの内容が不正です（images: 不正な値です）",
+        }));
+    }
+
+    #[test]
+    fn legacy_json_keeps_a_null_error_on_success() {
+        let body = json!({ "success": true, "data": "本文", "error": null, "processed_at": "2024-01-01T00:00:00Z" });
+        let legacy: Value = serde_json::from_str(&to_legacy_json(&body.to_string())).unwrap();
+        assert_eq!(legacy, body);
+
+        let legacy: Value = serde_json::from_str(&to_legacy_json(r#"{"success":true,"keys":[]}"#)).unwrap();
+        assert_eq!(legacy, json!({ "success": true, "keys": [], "error": null }));
+    }
+
+    #[test]
+    fn only_baseline_paths_use_the_legacy_shape() {
+        assert!(is_legacy_path("/api/process"));
+        assert!(is_legacy_path("/api/keys/list"));
+        assert!(!is_legacy_path("/api/batch"));
+        assert!(!is_legacy_path("/api/v1/process"));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::api_types::ApiKeyData;
+use crate::api_types::{ApiKeyData, ApiKeySettingsRequest};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -16,6 +17,8 @@ pub enum ApiKeyError {
     AdminRequired,
     KeyNotFound,
     KeyAlreadyExists,
+    FingerprintAlreadyRegistered,
+    FingerprintNotFound,
     FileError(String),
 }
 
@@ -25,6 +28,8 @@ impl std::fmt::Display for ApiKeyError {
             ApiKeyError::AdminRequired => write!(f, "管理者権限が必要です"),
             ApiKeyError::KeyNotFound => write!(f, "APIキーが見つかりません"),
             ApiKeyError::KeyAlreadyExists => write!(f, "APIキーが既に存在します"),
+            ApiKeyError::FingerprintAlreadyRegistered => write!(f, "この証明書指紋は既に登録されています"),
+            ApiKeyError::FingerprintNotFound => write!(f, "証明書指紋が見つかりません"),
             ApiKeyError::FileError(msg) => write!(f, "ファイルエラー: {}", msg),
         }
     }
@@ -130,6 +135,92 @@ impl ApiKeyStore {
         }
     }
 
+    // ========== キーごとの設定 ==========
+
+    pub fn update_settings(&mut self, settings: &ApiKeySettingsRequest) -> Result<(), ApiKeyError> {
+        if !self.validate_admin_key(&settings.admin_key) {
+            return Err(ApiKeyError::AdminRequired);
+        }
+
+        let api_key_data = self.keys.get_mut(&settings.key).ok_or(ApiKeyError::KeyNotFound)?;
+        if let Some(profile) = &settings.default_profile {
+            api_key_data.default_profile = if profile.is_empty() { None } else { Some(profile.clone()) };
+        }
+        if let Some(allow_post) = settings.allow_post {
+            api_key_data.allow_post = allow_post;
+        }
+        if let Some(cookie_jar) = settings.cookie_jar {
+            api_key_data.cookie_jar = cookie_jar;
+        }
+        if let Some(user_agent) = &settings.user_agent {
+            api_key_data.user_agent = if user_agent.is_empty() { None } else { Some(user_agent.clone()) };
+        }
+        if let Some(language) = &settings.accept_language {
+            api_key_data.accept_language = if language.is_empty() { None } else { Some(language.clone()) };
+        }
+        self.save_to_file()
+    }
+
+    pub fn get_default_profile(&self, key: &str) -> Option<String> {
+        self.keys.get(key).and_then(|data| data.default_profile.clone())
+    }
+
+    pub fn is_post_allowed(&self, key: &str) -> bool {
+        self.keys.get(key).map(|data| data.allow_post).unwrap_or(false)
+    }
+
+    pub fn is_cookie_jar_enabled(&self, key: &str) -> bool {
+        self.keys.get(key).map(|data| data.cookie_jar).unwrap_or(false)
+    }
+
+    pub fn get_user_agent(&self, key: &str) -> Option<String> {
+        self.keys.get(key).and_then(|data| data.user_agent.clone())
+    }
+
+    pub fn get_accept_language(&self, key: &str) -> Option<String> {
+        self.keys.get(key).and_then(|data| data.accept_language.clone())
+    }
+
+    // ========== クライアント証明書 ==========
+
+    pub fn add_client_cert_fingerprint(&mut self, admin_key: &str, key: &str, fingerprint: &str) -> Result<(), ApiKeyError> {
+        if !self.validate_admin_key(admin_key) {
+            return Err(ApiKeyError::AdminRequired);
+        }
+
+        let fingerprint = normalize_fingerprint(fingerprint);
+        if self.find_key_by_fingerprint(&fingerprint).is_some() {
+            return Err(ApiKeyError::FingerprintAlreadyRegistered);
+        }
+
+        let api_key_data = self.keys.get_mut(key).ok_or(ApiKeyError::KeyNotFound)?;
+        api_key_data.client_cert_fingerprints.push(fingerprint);
+        self.save_to_file()
+    }
+
+    pub fn remove_client_cert_fingerprint(&mut self, admin_key: &str, key: &str, fingerprint: &str) -> Result<(), ApiKeyError> {
+        if !self.validate_admin_key(admin_key) {
+            return Err(ApiKeyError::AdminRequired);
+        }
+
+        let fingerprint = normalize_fingerprint(fingerprint);
+        let api_key_data = self.keys.get_mut(key).ok_or(ApiKeyError::KeyNotFound)?;
+        let before = api_key_data.client_cert_fingerprints.len();
+        api_key_data.client_cert_fingerprints.retain(|f| *f != fingerprint);
+        if api_key_data.client_cert_fingerprints.len() == before {
+            return Err(ApiKeyError::FingerprintNotFound);
+        }
+        self.save_to_file()
+    }
+
+    pub fn find_key_by_fingerprint(&self, fingerprint: &str) -> Option<String> {
+        let fingerprint = normalize_fingerprint(fingerprint);
+        self.keys
+            .values()
+            .find(|data| data.client_cert_fingerprints.contains(&fingerprint))
+            .map(|data| data.key.clone())
+    }
+
     // ========== 使用量管理 ==========
 
     pub fn add_usage(&mut self, key: &str, original_bytes: u64, processed_bytes: u64) -> Result<(), ApiKeyError> {
@@ -142,6 +233,16 @@ impl ApiKeyStore {
         }
     }
 
+    pub fn add_image_usage(&mut self, key: &str, original_bytes: u64, processed_bytes: u64) -> Result<(), ApiKeyError> {
+        if let Some(api_key_data) = self.keys.get_mut(key) {
+            api_key_data.add_image_usage(original_bytes, processed_bytes);
+            self.save_to_file()?;
+            Ok(())
+        } else {
+            Err(ApiKeyError::KeyNotFound)
+        }
+    }
+
     pub fn get_usage(&self, key: &str) -> Option<u64> {
         self.keys.get(key).map(|data| data.total_bytes_processed)
     }
@@ -191,7 +292,7 @@ impl ApiKeyStore {
 }
 
 // ========== 統計データ構造 ==========
-#[derive(Debug, Clone)]
+#[derive(Serialize, Debug, Clone, JsonSchema)]
 pub struct StatisticsData {
     pub total_original_bytes: u64,
     pub total_processed_bytes: u64,
@@ -200,16 +301,15 @@ pub struct StatisticsData {
     pub total_keys: usize,
 }
 
-impl StatisticsData {
-    pub fn as_tuple(&self) -> (u64, u64, u64, u64, usize) {
-        (
-            self.total_original_bytes,
-            self.total_processed_bytes,
-            self.total_compressions,
-            self.compression_ratio,
-            self.total_keys,
-        )
-    }
+// ========== ヘルパー関数 ==========
+
+// "AB:CD:..." 形式も受け付け、小文字16進数に揃える
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_lowercase()
 }
 
 // ========== 型エイリアス ==========
@@ -226,6 +326,15 @@ impl ApiKeyData {
             compression_count: 0,
             created_at,
             last_used: None,
+            client_cert_fingerprints: Vec::new(),
+            default_profile: None,
+            image_original_bytes: 0,
+            image_processed_bytes: 0,
+            image_count: 0,
+            allow_post: false,
+            cookie_jar: false,
+            user_agent: None,
+            accept_language: None,
         }
     }
 
@@ -237,6 +346,13 @@ impl ApiKeyData {
         self.last_used = Some(chrono::Utc::now().to_rfc3339());
     }
 
+    pub fn add_image_usage(&mut self, original_bytes: u64, processed_bytes: u64) {
+        self.image_original_bytes += original_bytes;
+        self.image_processed_bytes += processed_bytes;
+        self.image_count += 1;
+        self.last_used = Some(chrono::Utc::now().to_rfc3339());
+    }
+
     pub fn compression_ratio(&self) -> f64 {
         if self.total_original_bytes > 0 {
             ((self.total_original_bytes - self.total_processed_bytes) as f64 / self.total_original_bytes as f64) * 100.0
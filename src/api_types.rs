@@ -1,6 +1,11 @@
+use crate::api_error::ApiError;
+use crate::api_key::StatisticsData;
+use crate::jobs::JobStatus;
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct ApiKeyData {
     pub key: String,
     pub total_bytes_processed: u64,
@@ -9,24 +14,453 @@ pub struct ApiKeyData {
     pub compression_count: u64,
     pub created_at: String,
     pub last_used: Option<String>,
+    #[serde(default)]
+    pub client_cert_fingerprints: Vec<String>,
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub image_original_bytes: u64,
+    #[serde(default)]
+    pub image_processed_bytes: u64,
+    #[serde(default)]
+    pub image_count: u64,
+    /// POST /proxyによるフォーム送信の転送を許可するか
+    #[serde(default)]
+    pub allow_post: bool,
+    /// 上流サイトのCookieをサーバー側で保持するか
+    #[serde(default)]
+    pub cookie_jar: bool,
+    /// 上流へのリクエストに使うUser-Agentプロファイル名とAccept-Language
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub accept_language: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct ApiResponse {
     pub success: bool,
     pub data: Option<String>,
-    pub error: Option<String>,
+    /// 失敗時のエラー（成功時はnull）
+    pub error: Option<ApiError>,
     pub original_url: Option<String>,
     pub processed_at: String,
     pub original_size_bytes: Option<u64>,
     pub processed_size_bytes: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// POST /api/processのリクエスト
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiProcessRequest {
+    /// 取得するURL（必須）
+    pub url: Option<String>,
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    #[serde(flatten)]
+    pub options: ApiProcessOptions,
+}
+
+/// POST /api/batchのリクエスト（オプションはすべてのURLに適用する）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiBatchRequest {
+    /// 処理するURL（上限は設定のbatch.max_urls）
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    /// trueの場合、完了した順にNDJSONで返す
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(flatten)]
+    pub options: ApiProcessOptions,
+}
+
+/// POST /api/jobsのリクエスト（callback_urlを指定すると完了時に結果をPOSTする）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiJobRequest {
+    /// 取得するURL（必須）
+    pub url: Option<String>,
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    /// 完了時に結果のApiResponseをPOSTするURL
+    pub callback_url: Option<String>,
+    #[serde(flatten)]
+    pub options: ApiProcessOptions,
+}
+
+/// JSONボディで指定する軽量化オプション（値はGETのクエリパラメータと同じ。OpenAPIのクエリパラメータもここから作る）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiProcessOptions {
+    /// `html`（既定） / `text`。JSON APIの出力形式
+    pub format: Option<String>,
+    /// `minimal` / `article`（既定） / `tables` / `code`。軽量化プロファイル
+    pub profile: Option<String>,
+    /// `inline`（既定） / `footnotes` / `sections`。`footnotes` はリンクを番号付きの印にしてページ末尾に一覧を表示し、`sections` は見出しごとに一覧を表示する
+    pub links: Option<String>,
+    /// `drop` でメニューやフッターのように連続するリンクの塊を除去する
+    pub nav: Option<String>,
+    /// `keep` で表・整形済みテキスト・コード・引用を残す
+    pub structure: Option<String>,
+    /// `none`（既定） / `alt` / `link` / `thumb`。画像を代替テキスト・画像へのリンク・縮小サムネイルとして表示する
+    pub images: Option<String>,
+    /// `none`（既定） / `get` / `post`。フォームを残し、プロキシ経由で送信できるようにする（`post` はPOSTが許可されたAPIキーのみ）
+    pub forms: Option<String>,
+    /// `desktop` / `mobile` / `text-browser`。上流サイトへのリクエストに使うUser-Agentプロファイル
+    pub ua: Option<String>,
+    /// `shift_jis` / `euc-jp` 等。上流ページの文字コード（`Content-Type` のcharsetより優先）
+    pub encoding: Option<String>,
+    /// trueの場合、上流へ `Cache-Control: no-cache` を送る（クエリパラメータでは `cache=bypass`）
+    #[serde(default)]
+    pub no_cache: bool,
+}
+
+/// バッチ処理の1件分の結果（indexはリクエストのurlsでの位置）
+#[derive(Serialize, JsonSchema)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub url: String,
+    #[serde(flatten)]
+    pub response: ApiResponse,
+}
+
+/// ジョブの状態（完了後はresultに処理結果が入る）
+#[derive(Serialize, JsonSchema)]
+pub struct JobInfo {
+    pub id: String,
+    pub url: String,
+    pub status: JobStatus,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub callback_url: Option<String>,
+    pub callback_status: Option<String>,
+    pub result: Option<ApiResponse>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct JobResponse {
+    pub success: bool,
+    pub job: JobInfo,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct BatchResponse {
+    pub success: bool,
+    pub results: Vec<BatchItemResult>,
+}
+
+/// POST /api/v1/watchesのリクエスト
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiWatchRequest {
+    /// 監視するURL（必須）
+    pub url: Option<String>,
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    /// 取得間隔（分）。省略時と下限は設定のwatch.default_interval_minutes・watch.min_interval_minutes
+    pub interval_minutes: Option<u64>,
+    /// 軽量化プロファイル（省略時はAPIキーの既定プロファイル）
+    pub profile: Option<String>,
+    /// User-Agentプロファイル
+    pub ua: Option<String>,
+    /// 上流ページの文字コード
+    pub encoding: Option<String>,
+}
+
+/// 保存したスナップショット（内容が変わった取得ごとに1つ）
+#[derive(Serialize, JsonSchema)]
+pub struct SnapshotInfo {
+    /// 監視ごとの連番
+    pub id: u64,
+    pub fetched_at: String,
+    pub title: Option<String>,
+    pub block_count: usize,
+}
+
+/// 監視の状態
+#[derive(Serialize, JsonSchema)]
+pub struct WatchInfo {
+    pub id: String,
+    pub url: String,
+    pub interval_minutes: u64,
+    pub profile: Option<String>,
+    pub ua: Option<String>,
+    pub encoding: Option<String>,
+    pub created_at: String,
+    pub last_checked_at: Option<String>,
+    /// 最後に内容が変わった（スナップショットを追加した）日時
+    pub last_changed_at: Option<String>,
+    /// 直前の取得が失敗した場合のエラー（request_idはnull）
+    pub last_error: Option<ApiError>,
+    /// 古いものから並べる
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct WatchResponse {
+    pub success: bool,
+    pub watch: WatchInfo,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct WatchListResponse {
+    pub success: bool,
+    pub watches: Vec<WatchInfo>,
+}
+
+/// スナップショットのテキスト（blocksは見出し・段落・リスト項目等の1つずつ）
+#[derive(Serialize, JsonSchema)]
+pub struct SnapshotResponse {
+    pub success: bool,
+    pub watch_id: String,
+    pub snapshot: SnapshotInfo,
+    pub blocks: Vec<String>,
+}
+
+/// 2つのスナップショットの差分
+#[derive(Serialize, JsonSchema)]
+pub struct WatchDiffResponse {
+    pub success: bool,
+    pub watch_id: String,
+    pub url: String,
+    /// 比較元（比較先が最初のスナップショットの場合はnull。すべてのブロックを追加として扱う）
+    pub from: Option<SnapshotInfo>,
+    pub to: SnapshotInfo,
+    /// 比較先で追加されたブロック（比較先での順）
+    pub added: Vec<String>,
+    /// 比較元から削除されたブロック（比較元での順）
+    pub removed: Vec<String>,
+}
+
+/// POST /api/savedのリクエスト（軽量化オプションは保存するページの表示に使う）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiSaveRequest {
+    /// 保存するURL（必須）
+    pub url: Option<String>,
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    #[serde(flatten)]
+    pub options: ApiProcessOptions,
+}
+
+/// 保存したページ
+#[derive(Serialize, JsonSchema)]
+pub struct SavedPageInfo {
+    pub id: String,
+    /// 取得したURL（リダイレクト後）
+    pub url: String,
+    pub title: String,
+    pub saved_at: String,
+    pub original_size_bytes: u64,
+    /// 保存した軽量化ページのバイト数
+    pub processed_size_bytes: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SavedPageResponse {
+    pub success: bool,
+    pub page: SavedPageInfo,
+}
+
+/// 保存したページの内容（formatに応じて軽量化したHTMLまたはプレーンテキスト）
+#[derive(Serialize, JsonSchema)]
+pub struct SavedPageContentResponse {
+    pub success: bool,
+    pub page: SavedPageInfo,
+    pub format: String,
+    pub content: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SavedPageListResponse {
+    pub success: bool,
+    /// 新しいものから並べる
+    pub pages: Vec<SavedPageInfo>,
+}
+
+/// POST /api/epubのリクエスト（urlまたはsaved_idsのどちらか一方を指定する）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiEpubRequest {
+    /// EPUBにするURL（軽量化オプションはこのページの処理に使う。images=thumbで縮小画像を埋め込む）
+    pub url: Option<String>,
+    /// 保存したページのID（指定した順に章にする。保存時にimages=thumbだったページは縮小画像を埋め込む）
+    #[serde(default)]
+    pub saved_ids: Vec<String>,
+    /// 本のタイトル（省略時は1章ならそのページのタイトル、複数なら「保存したページ」と日付）
+    pub title: Option<String>,
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    #[serde(flatten)]
+    pub options: ApiProcessOptions,
+}
+
+/// 閲覧履歴の1件（/proxyで表示したページ）
+#[derive(Serialize, JsonSchema)]
+pub struct HistoryEntryInfo {
+    pub url: String,
+    pub title: String,
+    pub visited_at: String,
+    pub original_size_bytes: u64,
+    pub processed_size_bytes: u64,
+    /// 軽量化で減ったバイト数
+    pub bytes_saved: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HistoryResponse {
+    pub success: bool,
+    /// このAPIキーで閲覧履歴を記録しているか
+    pub enabled: bool,
+    /// 閲覧履歴を保持する日数（0は期限なし）
+    pub retention_days: u32,
+    /// 新しいものから並べる
+    pub entries: Vec<HistoryEntryInfo>,
+}
+
+/// 閲覧履歴の記録の切り替え
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiHistorySettingsRequest {
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    /// falseにすると記録を止め、これまでの閲覧履歴を削除する（必須）
+    pub enabled: Option<bool>,
+}
+
+/// ブックマークの登録・更新（更新では指定したフィールドのみ変更する）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiBookmarkRequest {
+    /// X-API-Keyヘッダーでも指定できる（ボディを優先する）
+    pub api_key: Option<String>,
+    /// 登録時は必須
+    pub url: Option<String>,
+    /// 省略時は閲覧履歴のタイトル（なければURL）
+    pub title: Option<String>,
+    /// フォルダ名（空文字列はフォルダなし）
+    pub folder: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct BookmarkInfo {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    /// フォルダなしは空文字列
+    pub folder: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct BookmarkResponse {
+    pub success: bool,
+    pub bookmark: BookmarkInfo,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct BookmarkListResponse {
+    pub success: bool,
+    /// ブックマークのあるフォルダ（名前順。フォルダなしは含めない）
+    pub folders: Vec<String>,
+    /// フォルダ順、同じフォルダでは登録順
+    pub bookmarks: Vec<BookmarkInfo>,
+}
+
+/// 全文検索で一致した保存したページ
+#[derive(Serialize, JsonSchema)]
+pub struct SearchResultItem {
+    pub page: SavedPageInfo,
+    /// 関連度（大きいほど上位。同じ検索結果の中でのみ比較できる）
+    pub score: f32,
+    /// 一致した部分を含む本文の抜粋
+    pub snippet: String,
+    /// snippetの一致部分を<b>で囲んだHTML
+    pub snippet_html: String,
+    /// 保存した軽量化ページの表示URL
+    pub link: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SearchResponse {
+    pub success: bool,
+    pub query: String,
+    /// 関連度の高い順
+    pub results: Vec<SearchResultItem>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct UsageResponse {
     pub success: bool,
     pub key: Option<String>,
     pub total_bytes_processed: Option<u64>,
     pub keys: Option<Vec<ApiKeyData>>,
-    pub error: Option<String>,
-} 
\ No newline at end of file
+}
+
+/// APIキー設定の更新リクエスト（指定したフィールドのみ更新）
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiKeySettingsRequest {
+    pub admin_key: String,
+    /// /api/v1/keys/{key}ではパスで指定する
+    #[serde(default)]
+    pub key: String,
+    /// 空文字列で既定プロファイルの設定を解除する
+    pub default_profile: Option<String>,
+    pub allow_post: Option<bool>,
+    pub cookie_jar: Option<bool>,
+    /// 空文字列で設定を解除する
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+}
+
+/// 上流サイトのCookie（サーバー側のCookieジャーに保持しているもの）
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct CookieInfo {
+    pub domain: String,
+    pub path: String,
+    pub name: String,
+    pub value: String,
+    /// 期限のないセッションCookieはNone（ジャー自体の期限まで保持する）
+    pub expires: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct CookieListResponse {
+    pub success: bool,
+    pub cookies: Vec<CookieInfo>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SimpleResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StatisticsResponse {
+    pub success: bool,
+    pub statistics: StatisticsData,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CreateKeyRequest {
+    pub key: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AdminLoginRequest {
+    pub admin_key: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct FingerprintRequest {
+    pub admin_key: String,
+    /// /api/v1/keys/{key}/fingerprintsではパスで指定する
+    #[serde(default)]
+    pub key: String,
+    /// クライアント証明書のSHA-256指紋
+    pub fingerprint: String,
+}
@@ -0,0 +1,66 @@
+use crate::api_key::SharedApiKeyStore;
+use crate::bookmarks::SharedBookmarkStore;
+use crate::cookie_jar::SharedCookieJarStore;
+use crate::fetcher::FetchContext;
+use crate::generated_feed::SharedFeedLinkStore;
+use crate::history::SharedHistoryStore;
+use crate::jobs::SharedJobQueue;
+use crate::process_options::ProcessOptions;
+use crate::saved::{SharedSavedPageStore, SharedViewedPages};
+use crate::search::SharedSearchIndex;
+use crate::watch::SharedWatchStore;
+
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+// ========== サーバー全体で共有する状態 ==========
+#[derive(Clone)]
+pub struct AppState {
+    pub api_key_store: SharedApiKeyStore,
+    pub cookie_jars: SharedCookieJarStore,
+    pub feed_links: SharedFeedLinkStore,
+    pub jobs: SharedJobQueue,
+    pub watches: SharedWatchStore,
+    pub saved_pages: SharedSavedPageStore,
+    // 保存ボタンのために控えておくブラウザで表示したページ
+    pub viewed_pages: SharedViewedPages,
+    pub search_index: SharedSearchIndex,
+    pub history: SharedHistoryStore,
+    pub bookmarks: SharedBookmarkStore,
+    // バッチ処理の同時取得数（サーバー全体で共有する）
+    pub batch_permits: Arc<Semaphore>,
+}
+
+impl AppState {
+    // APIキーの設定とリクエストの軽量化オプションから上流リクエストの状態を用意する
+    // （User-Agentはリクエストの指定、Accept-LanguageはAPIキーの設定を優先する）
+    pub async fn fetch_context(&self, api_key: &str, options: &ProcessOptions, client_language: Option<&str>) -> FetchContext {
+        let (cookie_jar_enabled, key_user_agent, key_language) = {
+            let store = self.api_key_store.read().await;
+            (
+                store.is_cookie_jar_enabled(api_key),
+                store.get_user_agent(api_key),
+                store.get_accept_language(api_key),
+            )
+        };
+        let cookie_jar = if cookie_jar_enabled {
+            Some(self.cookie_jars.write().await.jar_for(api_key))
+        } else {
+            None
+        };
+        FetchContext {
+            cookie_jar,
+            user_agent: options.user_agent.clone().or(key_user_agent),
+            accept_language: key_language.or_else(|| client_language.map(|lang| lang.to_string())),
+            encoding: options.encoding,
+            bypass_cache: options.bypass_cache,
+        }
+    }
+
+    // 取得中に更新されたCookieを保存する（ファイルへはまとめて書き出す）
+    pub async fn save_fetch_context(&self, context: &FetchContext) {
+        if context.cookie_jar.is_some() {
+            self.cookie_jars.read().await.mark_dirty();
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::api_types::BookmarkInfo;
+use crate::persist::{random_hex_id, JsonFile, Persisted};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// APIキーごとのブックマーク（フォルダは名前で分けるだけで入れ子にしない）
+
+// ========== 定数 ==========
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+// ========== データ構造 ==========
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Bookmark {
+    pub id: String,
+    // 登録したAPIキー（同じキーからのみ参照できる）
+    pub api_key: String,
+    pub url: String,
+    pub title: String,
+    // フォルダなしは空文字列
+    pub folder: String,
+    pub created_at: String,
+}
+
+impl Bookmark {
+    pub fn info(&self) -> BookmarkInfo {
+        BookmarkInfo {
+            id: self.id.clone(),
+            url: self.url.clone(),
+            title: self.title.clone(),
+            folder: self.folder.clone(),
+            created_at: self.created_at.clone(),
+        }
+    }
+}
+
+// ========== ブックマークストア ==========
+pub struct BookmarkStore {
+    bookmarks: HashMap<String, Bookmark>,
+    file: JsonFile,
+}
+
+impl BookmarkStore {
+    pub fn new() -> Self {
+        Self {
+            bookmarks: HashMap::new(),
+            file: JsonFile::new(BOOKMARKS_FILE),
+        }
+    }
+
+    pub fn load_from_file() -> Self {
+        let mut store = Self::new();
+        if let Some(bookmarks) = store.file.load() {
+            store.bookmarks = bookmarks;
+        }
+        store
+    }
+
+    pub fn count_for(&self, api_key: &str) -> usize {
+        self.bookmarks.values().filter(|bookmark| bookmark.api_key == api_key).count()
+    }
+
+    // APIキーの登録数が上限に達している場合は追加せずfalseを返す
+    pub fn add(&mut self, bookmark: Bookmark, max_bookmarks: usize) -> bool {
+        if self.count_for(&bookmark.api_key) >= max_bookmarks {
+            return false;
+        }
+        self.bookmarks.insert(bookmark.id.clone(), bookmark);
+        self.file.mark_dirty();
+        true
+    }
+
+    // フォルダなしを先頭に、フォルダ名順、同じフォルダでは登録順
+    pub fn list(&self, api_key: &str) -> Vec<Bookmark> {
+        let mut bookmarks: Vec<Bookmark> = self.bookmarks.values().filter(|bookmark| bookmark.api_key == api_key).cloned().collect();
+        bookmarks.sort_by(|a, b| (&a.folder, &a.created_at).cmp(&(&b.folder, &b.created_at)));
+        bookmarks
+    }
+
+    pub fn get(&self, id: &str, api_key: &str) -> Option<Bookmark> {
+        self.bookmarks.get(id).filter(|bookmark| bookmark.api_key == api_key).cloned()
+    }
+
+    // 指定したフィールドのみ変更する（見つからない場合はNone）
+    pub fn update(&mut self, id: &str, api_key: &str, url: Option<String>, title: Option<String>, folder: Option<String>) -> Option<Bookmark> {
+        let bookmark = self.bookmarks.get_mut(id).filter(|bookmark| bookmark.api_key == api_key)?;
+        if let Some(url) = url {
+            bookmark.url = url;
+        }
+        if let Some(title) = title {
+            bookmark.title = title;
+        }
+        if let Some(folder) = folder {
+            bookmark.folder = folder;
+        }
+        let updated = bookmark.clone();
+        self.file.mark_dirty();
+        Some(updated)
+    }
+
+    // 見つからない場合はfalse
+    pub fn remove(&mut self, id: &str, api_key: &str) -> bool {
+        if self.get(id, api_key).is_none() {
+            return false;
+        }
+        self.bookmarks.remove(id);
+        self.file.mark_dirty();
+        true
+    }
+}
+
+impl Persisted for BookmarkStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.bookmarks).map_err(|e| e.to_string())
+    }
+}
+
+// ========== ヘルパー関数 ==========
+
+// ブックマークのあるフォルダ（BookmarkStore::listの結果から、名前順。フォルダなしは含めない）
+pub fn folders(bookmarks: &[Bookmark]) -> Vec<String> {
+    let mut folders: Vec<String> = bookmarks.iter().map(|bookmark| bookmark.folder.clone()).filter(|folder| !folder.is_empty()).collect();
+    folders.dedup();
+    folders
+}
+
+pub fn generate_bookmark_id() -> String {
+    random_hex_id(8)
+}
+
+// ========== 型エイリアス ==========
+pub type SharedBookmarkStore = Arc<RwLock<BookmarkStore>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(api_key: &str, folder: &str, created_at: &str) -> Bookmark {
+        Bookmark {
+            id: generate_bookmark_id(),
+            api_key: api_key.to_string(),
+            url: format!("https://example.com/{}/{}", folder, created_at),
+            title: created_at.to_string(),
+            folder: folder.to_string(),
+            created_at: created_at.to_string(),
+        }
+    }
+
+    #[test]
+    fn bookmarks_without_a_folder_come_first_then_by_folder_and_age() {
+        let mut store = BookmarkStore::new();
+        for (folder, created_at) in [("読む", "2024-01-03"), ("", "2024-01-04"), ("仕事", "2024-01-02"), ("読む", "2024-01-01"), ("", "2024-01-05")] {
+            assert!(store.add(bookmark("key1", folder, created_at), 10));
+        }
+        store.add(bookmark("key2", "他人", "2024-01-01"), 10);
+
+        let listed = store.list("key1");
+        let order: Vec<(&str, &str)> = listed.iter().map(|b| (b.folder.as_str(), b.created_at.as_str())).collect();
+        assert_eq!(
+            order,
+            vec![("", "2024-01-04"), ("", "2024-01-05"), ("仕事", "2024-01-02"), ("読む", "2024-01-01"), ("読む", "2024-01-03")]
+        );
+        assert_eq!(folders(&listed), vec!["仕事", "読む"]);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::profile::ProcessingProfile;
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// ========== 定数 ==========
+const CONFIG_FILE: &str = "rigil_config.json";
+const DEFAULT_GEMINI_PORT: u16 = 1965;
+const DEFAULT_GOPHER_PORT: u16 = 70;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// ========== 設定 ==========
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub gemini: GeminiConfig,
+    pub gopher: GopherConfig,
+    // 組み込みプロファイルに追加・上書きする軽量化プロファイル
+    pub profiles: Vec<ProcessingProfile>,
+    pub images: ImageConfig,
+    pub upstream: UpstreamConfig,
+    pub cookies: CookieConfig,
+    pub content: ContentConfig,
+    pub batch: BatchConfig,
+    pub jobs: JobsConfig,
+    pub watch: WatchConfig,
+    pub saved: SavedConfig,
+    pub epub: EpubConfig,
+    pub history: HistoryConfig,
+    pub bookmarks: BookmarkConfig,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GeminiConfig {
+    pub enabled: bool,
+    pub port: u16,
+    pub cert_file: String,
+    pub key_file: String,
+}
+
+impl Default for GeminiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_GEMINI_PORT,
+            cert_file: "gemini_cert.pem".to_string(),
+            key_file: "gemini_key.pem".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GopherConfig {
+    pub enabled: bool,
+    pub port: u16,
+    // メニューに記載する自ホスト名（クライアントから到達可能な名前）
+    pub host: String,
+    // type-hエントリ（HTML版へのリンク）に使うHTTP側のベースURL
+    pub http_base_url: String,
+}
+
+impl Default for GopherConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_GOPHER_PORT,
+            host: "localhost".to_string(),
+            http_base_url: "http://localhost".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ImageConfig {
+    // 縮小後の最大幅・高さ（ピクセル）
+    pub max_width: u32,
+    pub max_height: u32,
+    // 縮小後の最大バイト数（超える場合は画質とサイズを下げる）
+    pub max_bytes: usize,
+    pub jpeg_quality: u8,
+    // 取得する元画像の最大バイト数
+    pub max_source_bytes: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            max_width: 320,
+            max_height: 320,
+            max_bytes: 30 * 1024,
+            jpeg_quality: 40,
+            max_source_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct UpstreamConfig {
+    // 内部ネットワーク（ループバック・プライベートアドレス等）への取得を許可する
+    pub allow_private_networks: bool,
+    // 組み込みのUser-Agentプロファイルに追加・上書きするもの（名前 → User-Agent）
+    pub user_agents: HashMap<String, String>,
+    // リクエストやAPIキーで指定がない場合のUser-Agentプロファイル名
+    pub default_user_agent: Option<String>,
+    // ホストごとに付加・上書きするヘッダー
+    pub host_headers: Vec<HostHeaderRule>,
+    // 上流への接続に使うプロキシ（先頭から順に、送信先ホストが一致した最初のものを使う）
+    pub proxies: Vec<ProxyRule>,
+    // プロキシを使わず直接接続するホスト
+    pub no_proxy: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct HostHeaderRule {
+    // "example.com" または "*.example.com"
+    pub host: String,
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CookieConfig {
+    // 最後に使われてからこの時間が過ぎたCookieジャーは削除する
+    pub jar_ttl_hours: u64,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self { jar_ttl_hours: 24 * 7 }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProxyRule {
+    // http://、https://、socks5://、socks5h://（名前解決もプロキシ側で行う）
+    pub url: String,
+    // 対象ホストのパターン（空の場合はすべてのホスト）
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ContentConfig {
+    // テキストを抽出するPDFの最大バイト数
+    pub max_pdf_bytes: usize,
+    // 読み込むHTML・テキストの最大バイト数
+    pub max_page_bytes: usize,
+}
+
+impl Default for ContentConfig {
+    fn default() -> Self {
+        Self {
+            max_pdf_bytes: 10 * 1024 * 1024,
+            max_page_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BatchConfig {
+    // 1回のバッチで受け付けるURLの最大数
+    pub max_urls: usize,
+    // サーバー全体で同時に取得するバッチのURLの数（すべてのバッチリクエストで共有する）
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_urls: 50,
+            concurrency: 4,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct JobsConfig {
+    // ジョブを処理するワーカーの数
+    pub workers: usize,
+    // 完了したジョブと結果を保持する時間
+    pub retention_hours: u64,
+    // APIキーごとに待機中・処理中にできるジョブの最大数
+    pub max_pending_per_key: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            retention_hours: 24,
+            max_pending_per_key: 20,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WatchConfig {
+    // APIキーごとに登録できる監視の最大数
+    pub max_watches_per_key: usize,
+    // 取得間隔の最小値と、指定がない場合の値（分）
+    pub min_interval_minutes: u64,
+    pub default_interval_minutes: u64,
+    // 監視ごとに保持するスナップショットの最大数（古いものから削除する）
+    pub max_snapshots: usize,
+    // 定期取得で同時に取得する監視の数
+    pub check_concurrency: usize,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            max_watches_per_key: 20,
+            min_interval_minutes: 15,
+            default_interval_minutes: 60,
+            max_snapshots: 30,
+            check_concurrency: 4,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SavedConfig {
+    // APIキーごとに保存できるページの最大数
+    pub max_pages_per_key: usize,
+}
+
+impl Default for SavedConfig {
+    fn default() -> Self {
+        Self { max_pages_per_key: 200 }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EpubConfig {
+    // 1冊にまとめられる保存したページの最大数
+    pub max_chapters: usize,
+    // 1冊に埋め込む縮小画像の最大数（超えた分は代替テキストにする）
+    pub max_images: usize,
+    // 画像を同時に取得する数
+    pub image_concurrency: usize,
+}
+
+impl Default for EpubConfig {
+    fn default() -> Self {
+        Self {
+            max_chapters: 50,
+            max_images: 100,
+            image_concurrency: 4,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // falseの場合はどのAPIキーの閲覧履歴も記録しない
+    pub enabled: bool,
+    // 閲覧履歴を保持する日数（0は期限なし）
+    pub retention_days: u32,
+    // APIキーごとに保持する閲覧履歴の最大数（古いものから削除する）
+    pub max_entries_per_key: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 30,
+            max_entries_per_key: 500,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BookmarkConfig {
+    // APIキーごとに登録できるブックマークの最大数
+    pub max_bookmarks_per_key: usize,
+}
+
+impl Default for BookmarkConfig {
+    fn default() -> Self {
+        Self { max_bookmarks_per_key: 500 }
+    }
+}
+
+impl Config {
+    pub fn load_from_file() -> Self {
+        if !Path::new(CONFIG_FILE).exists() {
+            return Self::default();
+        }
+
+        match fs::read_to_string(CONFIG_FILE) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("設定ファイルの解析に失敗しました（デフォルト設定を使用）: {}", e);
+                Self::default()
+            }),
+            Err(e) => {
+                eprintln!("設定ファイルの読み込みに失敗しました（デフォルト設定を使用）: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    // 初回呼び出し時に設定ファイルを読み込む
+    pub fn global() -> &'static Config {
+        CONFIG.get_or_init(Self::load_from_file)
+    }
+}
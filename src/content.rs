@@ -0,0 +1,197 @@
+use crate::feed;
+use crate::fetcher::{FetchedPage, PageBody};
+use crate::html_parser::{escape_attribute, get_base_url, parse_html_to_text, render_lightweight_page};
+use crate::persist::run_blocking;
+use crate::process_options::ProcessOptions;
+
+use quick_xml::events::Event;
+use quick_xml::{Reader, Writer};
+
+// 上流のContent-Typeに応じた表示（HTML以外のテキスト、JSON、XML、PDF、画像、その他のファイル）
+
+// ========== コンテンツの種類 ==========
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentKind {
+    Html,
+    PlainText,
+    Json,
+    Xml,
+    // RSS・Atomフィード（Content-Typeで判定できない場合はルート要素で判定する）
+    Feed,
+    Image,
+    Pdf,
+    Other,
+}
+
+impl ContentKind {
+    // Content-TypeのMIMEタイプから判定する（Content-TypeがなければHTMLとして扱う）
+    pub fn from_content_type(content_type: Option<&str>) -> Self {
+        let mime = match content_type {
+            Some(content_type) => mime_type(content_type),
+            None => return ContentKind::Html,
+        };
+
+        match mime.as_str() {
+            "" | "text/html" | "application/xhtml+xml" => ContentKind::Html,
+            "application/json" => ContentKind::Json,
+            "text/xml" | "application/xml" => ContentKind::Xml,
+            "application/pdf" => ContentKind::Pdf,
+            "application/rss+xml" | "application/atom+xml" | "application/rdf+xml" => ContentKind::Feed,
+            m if m.ends_with("+json") => ContentKind::Json,
+            m if m.ends_with("+xml") => ContentKind::Xml,
+            m if m.starts_with("text/") => ContentKind::PlainText,
+            m if m.starts_with("image/") => ContentKind::Image,
+            _ => ContentKind::Other,
+        }
+    }
+
+    // 本文をテキストとして表示する種類か
+    pub fn is_textual(&self) -> bool {
+        matches!(self, ContentKind::PlainText | ContentKind::Json | ContentKind::Xml | ContentKind::Feed | ContentKind::Pdf)
+    }
+}
+
+// ========== テキストの抽出 ==========
+
+// テキスト系の本文を表示用のテキストにする（JSON・XMLは整形し、PDFは抽出済みのテキストを使う）
+pub fn extract_text(page: &FetchedPage) -> Result<String, String> {
+    match (&page.kind, &page.body) {
+        (ContentKind::Json, PageBody::Text(text)) => Ok(pretty_json(text)),
+        (ContentKind::Xml | ContentKind::Feed, PageBody::Text(text)) => Ok(pretty_xml(text)),
+        (_, PageBody::Text(text) | PageBody::ExtractedText { text, .. }) => Ok(text.clone()),
+        _ => Err("本文を読み込んでいません".to_string()),
+    }
+}
+
+// PDFからテキストを抽出する（取得時に一度だけ行う）
+// 壊れたPDFではパニックすることもあるため捕捉する
+pub async fn extract_pdf_text(bytes: Vec<u8>) -> Result<String, String> {
+    run_blocking(move || {
+        std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes))
+            .map_err(|_| "PDFの解析中にエラーが発生しました".to_string())?
+            .map_err(|e| format!("PDFからテキストを抽出できません: {}", e))
+    })
+    .await
+    .map_err(|e| format!("PDFの処理が中断されました: {}", e))?
+}
+
+// 整形できないJSONはそのまま表示する
+fn pretty_json(text: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        .unwrap_or_else(|| text.to_string())
+}
+
+// 整形できないXMLはそのまま表示する
+fn pretty_xml(text: &str) -> String {
+    let mut reader = Reader::from_str(text);
+    reader.trim_text(true);
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Eof) => break,
+            Ok(event) => {
+                if writer.write_event(event).is_err() {
+                    return text.to_string();
+                }
+            }
+            Err(_) => return text.to_string(),
+        }
+    }
+
+    String::from_utf8(writer.into_inner()).unwrap_or_else(|_| text.to_string())
+}
+
+// ========== 表示 ==========
+
+// 取得したページをContent-Typeに応じて軽量HTMLにする
+pub fn render_page(page: &FetchedPage, options: &ProcessOptions) -> String {
+    if let Some(parsed_feed) = feed::page_feed(page) {
+        return render_lightweight_page(&feed::render_feed(&parsed_feed, &page.final_url, options));
+    }
+    if let (ContentKind::Html, PageBody::Text(html)) = (&page.kind, &page.body) {
+        let base_url = get_base_url(&page.final_url);
+        return parse_html_to_text(html, &base_url, &page.final_url, options);
+    }
+
+    let body = match page.kind {
+        ContentKind::Image => render_image_preview(page),
+        kind if kind.is_textual() => match extract_text(page) {
+            Ok(text) if kind == ContentKind::Pdf => render_paragraphs(&text),
+            Ok(text) => format!("<pre>{}</pre>", htmlescape::encode_minimal(&text)),
+            Err(e) => format!("<p>{}</p>{}", htmlescape::encode_minimal(&e), render_download_link(page)),
+        },
+        _ => render_download_link(page),
+    };
+
+    render_lightweight_page(&format!("{}{}", render_source_line(page), body))
+}
+
+// 元のURLと種類を示す見出し行
+fn render_source_line(page: &FetchedPage) -> String {
+    format!(
+        "<p><small>{} ({})</small></p>",
+        htmlescape::encode_minimal(&page.final_url),
+        htmlescape::encode_minimal(&content_label(page))
+    )
+}
+
+// PDFから抽出したテキストは空行で段落に分ける
+fn render_paragraphs(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| format!("<p>{}</p>", htmlescape::encode_minimal(&paragraph)))
+        .collect()
+}
+
+// 画像は/imageで縮小したプレビューを表示する（APIキーはページ読み込み後にスクリプトで付与する）
+fn render_image_preview(page: &FetchedPage) -> String {
+    format!(
+        "<p><img data-rigil-src=\"/image?url={}\" alt=\"[画像]\"></p>{}",
+        urlencoding::encode(&page.final_url),
+        render_download_link(page)
+    )
+}
+
+// その他のファイルは元のURLへのダウンロードリンクだけを表示する
+fn render_download_link(page: &FetchedPage) -> String {
+    format!(
+        "<p><a href=\"{}\">ダウンロード: {}</a></p>",
+        escape_attribute(&page.final_url),
+        htmlescape::encode_minimal(&content_label(page))
+    )
+}
+
+// "application/zip, 1.2 MB" のような種類とサイズの表示
+fn content_label(page: &FetchedPage) -> String {
+    let mime = page.content_type.as_deref().map(mime_type).unwrap_or_default();
+    let mime = if mime.is_empty() { "不明な形式".to_string() } else { mime };
+    match page.original_size() {
+        0 => mime,
+        size => format!("{}, {}", mime, format_size(size)),
+    }
+}
+
+// ========== ヘルパー関数 ==========
+
+// "text/plain; charset=utf-8" から "text/plain" を取り出す
+fn mime_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
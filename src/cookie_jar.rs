@@ -0,0 +1,229 @@
+use crate::api_types::CookieInfo;
+use crate::config::Config;
+use crate::persist::{JsonFile, Persisted};
+
+use chrono::{DateTime, Utc};
+use cookie_store::{Cookie, CookieDomain, CookieExpiration};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock as StdRwLock};
+use tokio::sync::RwLock;
+
+// 上流サイトのCookieをAPIキーごとに保持するサーバー側のCookieジャー
+
+// ========== 定数 ==========
+const COOKIE_JARS_FILE: &str = "cookie_jars.json";
+
+// ========== APIキーごとのジャー ==========
+
+// reqwestのCookieプロバイダとして使うため、内部はスレッド間で共有できるロックで保持する
+pub struct KeyCookieJar {
+    cookies: StdRwLock<cookie_store::CookieStore>,
+    last_used: StdRwLock<DateTime<Utc>>,
+}
+
+impl KeyCookieJar {
+    fn new(cookies: cookie_store::CookieStore, last_used: DateTime<Utc>) -> Self {
+        Self {
+            cookies: StdRwLock::new(cookies),
+            last_used: StdRwLock::new(last_used),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_used.write().unwrap() = Utc::now();
+    }
+
+    fn is_expired(&self, ttl: chrono::Duration) -> bool {
+        *self.last_used.read().unwrap() + ttl < Utc::now()
+    }
+
+    fn list(&self, domain: Option<&str>) -> Vec<CookieInfo> {
+        self.cookies
+            .read()
+            .unwrap()
+            .iter_unexpired()
+            .filter(|cookie| domain.map(|d| domain_matches(&cookie_domain(cookie), d)).unwrap_or(true))
+            .map(cookie_info)
+            .collect()
+    }
+
+    // 指定ドメイン（省略時はすべて）のCookieを削除し、削除した件数を返す
+    fn clear(&self, domain: Option<&str>) -> usize {
+        let mut cookies = self.cookies.write().unwrap();
+        let targets: Vec<(String, String, String)> = cookies
+            .iter_any()
+            .filter(|cookie| domain.map(|d| domain_matches(&cookie_domain(cookie), d)).unwrap_or(true))
+            .map(|cookie| (cookie_domain(cookie), String::from(&cookie.path), cookie.name().to_string()))
+            .collect();
+        for (cookie_domain, path, name) in &targets {
+            cookies.remove(cookie_domain, path, name);
+        }
+        targets.len()
+    }
+
+    fn to_saved(&self) -> SavedCookieJar {
+        SavedCookieJar {
+            last_used: self.last_used.read().unwrap().to_rfc3339(),
+            cookies: self.cookies.read().unwrap().iter_any().cloned().collect(),
+        }
+    }
+}
+
+impl reqwest::cookie::CookieStore for KeyCookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &url::Url) {
+        let mut cookies = self.cookies.write().unwrap();
+        for header in cookie_headers {
+            if let Ok(value) = header.to_str() {
+                // 不正なSet-Cookieは無視する
+                let _ = cookies.parse(value, url);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &url::Url) -> Option<HeaderValue> {
+        let header = self
+            .cookies
+            .read()
+            .unwrap()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+// ========== 保存形式 ==========
+#[derive(Serialize, Deserialize)]
+struct SavedCookieJar {
+    last_used: String,
+    cookies: Vec<Cookie<'static>>,
+}
+
+// ========== Cookieジャーストア ==========
+pub struct CookieJarStore {
+    jars: HashMap<String, Arc<KeyCookieJar>>,
+    file: JsonFile,
+}
+
+impl CookieJarStore {
+    pub fn new() -> Self {
+        Self {
+            jars: HashMap::new(),
+            file: JsonFile::new(COOKIE_JARS_FILE),
+        }
+    }
+
+    pub fn load_from_file() -> Self {
+        let mut store = Self::new();
+        let saved: HashMap<String, SavedCookieJar> = match store.file.load() {
+            Some(saved) => saved,
+            None => return store,
+        };
+
+        for (key, saved_jar) in saved {
+            let last_used = DateTime::parse_from_rfc3339(&saved_jar.last_used)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now());
+            // 期限切れのCookieは読み込まない
+            if let Ok(cookies) = cookie_store::CookieStore::from_cookies(saved_jar.cookies.into_iter().map(Ok::<_, ()>), false) {
+                store.jars.insert(key, Arc::new(KeyCookieJar::new(cookies, last_used)));
+            }
+        }
+
+        store.remove_expired_jars();
+        store
+    }
+
+    // 取得中にCookieが更新された可能性がある（次の書き出しで保存する）
+    pub fn mark_dirty(&self) {
+        self.file.mark_dirty();
+    }
+
+    // APIキーのジャーを返す（なければ作成する）。使用するたびに期限を延長する
+    pub fn jar_for(&mut self, key: &str) -> Arc<KeyCookieJar> {
+        self.remove_expired_jars();
+        let jar = self
+            .jars
+            .entry(key.to_string())
+            .or_insert_with(|| Arc::new(KeyCookieJar::new(cookie_store::CookieStore::default(), Utc::now())))
+            .clone();
+        jar.touch();
+        jar
+    }
+
+    pub fn list_cookies(&self, key: &str, domain: Option<&str>) -> Vec<CookieInfo> {
+        self.jars.get(key).map(|jar| jar.list(domain)).unwrap_or_default()
+    }
+
+    pub fn clear_cookies(&mut self, key: &str, domain: Option<&str>) -> usize {
+        let removed = match (self.jars.get(key), domain) {
+            (Some(jar), Some(_)) => jar.clear(domain),
+            // ドメインの指定がなければジャーごと削除する
+            (Some(jar), None) => {
+                let removed = jar.clear(None);
+                self.jars.remove(key);
+                removed
+            }
+            (None, _) => 0,
+        };
+        self.file.mark_dirty();
+        removed
+    }
+
+    fn remove_expired_jars(&mut self) {
+        let ttl = chrono::Duration::hours(Config::global().cookies.jar_ttl_hours as i64);
+        self.jars.retain(|_, jar| !jar.is_expired(ttl));
+    }
+}
+
+impl Persisted for CookieJarStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        let saved: HashMap<&String, SavedCookieJar> = self.jars.iter().map(|(key, jar)| (key, jar.to_saved())).collect();
+        serde_json::to_string_pretty(&saved).map_err(|e| e.to_string())
+    }
+}
+
+// ========== ヘルパー関数 ==========
+
+fn cookie_info(cookie: &Cookie<'static>) -> CookieInfo {
+    let expires = match &cookie.expires {
+        CookieExpiration::AtUtc(at) => DateTime::from_timestamp(at.unix_timestamp(), 0).map(|dt| dt.to_rfc3339()),
+        CookieExpiration::SessionEnd => None,
+    };
+    CookieInfo {
+        domain: cookie_domain(cookie),
+        path: String::from(&cookie.path),
+        name: cookie.name().to_string(),
+        value: cookie.value().to_string(),
+        expires,
+        secure: cookie.secure().unwrap_or(false),
+        http_only: cookie.http_only().unwrap_or(false),
+    }
+}
+
+fn cookie_domain(cookie: &Cookie<'static>) -> String {
+    match &cookie.domain {
+        CookieDomain::HostOnly(domain) | CookieDomain::Suffix(domain) => domain.clone(),
+        CookieDomain::NotPresent | CookieDomain::Empty => String::new(),
+    }
+}
+
+// 指定ドメインまたはそのサブドメインのCookieか
+fn domain_matches(cookie_domain: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    cookie_domain == domain || cookie_domain.ends_with(&format!(".{}", domain))
+}
+
+// ========== 型エイリアス ==========
+pub type SharedCookieJarStore = Arc<RwLock<CookieJarStore>>;
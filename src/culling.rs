@@ -0,0 +1,241 @@
+// 許可タグリストに基づくHTMLのカリング
+// （既定のarticleプロファイルはreader_mode_maker::cullingを使い、それ以外のプロファイルで使用する）
+
+// ========== 定数 ==========
+
+// 中身ごと除去する要素
+const REMOVED_WITH_CONTENT: &[&str] = &["script", "style", "noscript", "template", "svg", "iframe", "object", "canvas"];
+// 空白をそのまま残す要素
+const WHITESPACE_PRESERVING: &[&str] = &["pre", "code", "textarea"];
+
+// ========== カリング ==========
+
+pub fn cull_html(html: &str, allowed_tags: &[String]) -> String {
+    let contents: Vec<char> = html.chars().collect();
+    let mut culled = String::new();
+    let mut pending_space = false;
+    let mut preserve_depth = 0usize;
+    let mut i = 0;
+
+    while i < contents.len() {
+        if contents[i] != '<' {
+            if preserve_depth > 0 {
+                culled.push(contents[i]);
+            } else if contents[i].is_whitespace() {
+                // 空白の連続は1つにまとめる
+                pending_space = true;
+            } else {
+                if pending_space && !culled.is_empty() {
+                    culled.push(' ');
+                }
+                pending_space = false;
+                culled.push(contents[i]);
+            }
+            i += 1;
+            continue;
+        }
+
+        // コメント
+        if starts_with_at(&contents, i, "<!--") {
+            i = find_from(&contents, i + 4, "-->").map(|pos| pos + 3).unwrap_or(contents.len());
+            continue;
+        }
+
+        let tag = read_tag(&contents, &mut i);
+        let (name, is_closing) = tag_name(&tag);
+
+        if !is_closing && (REMOVED_WITH_CONTENT.contains(&name.as_str())
+            || (name == "title" && !is_allowed(allowed_tags, "title")))
+        {
+            skip_to_closing_tag(&contents, &mut i, &name);
+            continue;
+        }
+
+        if !is_allowed(allowed_tags, &name) {
+            // 許可されていないタグは除去し、単語がつながらないよう空白を残す
+            // （整形済みテキスト内では構文ハイライト用のspan等なので何も足さない）
+            if preserve_depth == 0 {
+                pending_space = true;
+            }
+            continue;
+        }
+
+        if WHITESPACE_PRESERVING.contains(&name.as_str()) {
+            if is_closing {
+                preserve_depth = preserve_depth.saturating_sub(1);
+            } else {
+                preserve_depth += 1;
+            }
+        }
+
+        if pending_space && !culled.is_empty() {
+            culled.push(' ');
+        }
+        pending_space = false;
+
+        if is_closing {
+            culled.push_str(&format!("</{}>", name));
+        } else {
+            culled.push_str(&rebuild_tag(&name, &tag));
+        }
+    }
+
+    culled
+}
+
+// ========== タグ解析 ==========
+
+pub fn read_tag(contents: &[char], i: &mut usize) -> String {
+    let mut tag = String::new();
+    let mut quote: Option<char> = None;
+
+    while *i < contents.len() {
+        let ch = contents[*i];
+        tag.push(ch);
+        *i += 1;
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '>' => break,
+            None => {}
+        }
+    }
+    tag
+}
+
+// タグ名（小文字）と終了タグかどうかを返す
+pub fn tag_name(tag: &str) -> (String, bool) {
+    let inner = tag.trim_start_matches('<').trim_end_matches('>').trim();
+    let is_closing = inner.starts_with('/');
+    let name: String = inner
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    (name.to_lowercase(), is_closing)
+}
+
+// タグの属性を (小文字の属性名, 値) の一覧として返す
+pub fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let inner = tag.trim_start_matches('<').trim_end_matches('>').trim_end_matches('/');
+    let chars: Vec<char> = inner.chars().collect();
+    let mut attributes = Vec::new();
+
+    // タグ名を読み飛ばす
+    let mut i = 0;
+    while i < chars.len() && !chars[i].is_whitespace() {
+        i += 1;
+    }
+
+    while i < chars.len() {
+        while i < chars.len() && (chars[i].is_whitespace() || chars[i] == '/') {
+            i += 1;
+        }
+
+        let mut name = String::new();
+        while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '=' {
+            name.push(chars[i]);
+            i += 1;
+        }
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+
+        let mut value = String::new();
+        if i < chars.len() && chars[i] == '=' {
+            i += 1;
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == '"' || chars[i] == '\'') {
+                let quote = chars[i];
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    value.push(chars[i]);
+                    i += 1;
+                }
+                i += 1;
+            } else {
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    value.push(chars[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        if !name.is_empty() {
+            attributes.push((name.to_lowercase(), value));
+        }
+    }
+
+    attributes
+}
+
+pub fn get_attribute(tag: &str, name: &str) -> Option<String> {
+    parse_attributes(tag)
+        .into_iter()
+        .find(|(attr_name, _)| attr_name == name)
+        .map(|(_, value)| value)
+}
+
+// ========== ヘルパー関数 ==========
+
+fn is_allowed(allowed_tags: &[String], name: &str) -> bool {
+    allowed_tags.iter().any(|t| t.eq_ignore_ascii_case(name))
+}
+
+// 要素ごとに残す属性
+fn allowed_attributes(name: &str) -> &'static [&'static str] {
+    match name {
+        "a" => &["href"],
+        "td" | "th" => &["colspan", "rowspan"],
+        "img" => &["src", "alt"],
+        "form" => &["action", "method", "enctype"],
+        "input" => &["type", "name", "value", "checked", "placeholder", "size", "maxlength"],
+        "select" => &["name", "multiple"],
+        "option" => &["value", "selected"],
+        "textarea" => &["name", "rows", "cols", "placeholder"],
+        "button" => &["type", "name", "value"],
+        _ => &[],
+    }
+}
+
+fn rebuild_tag(name: &str, tag: &str) -> String {
+    let mut rebuilt = format!("<{}", name);
+    let allowed = allowed_attributes(name);
+    for (attr_name, value) in parse_attributes(tag) {
+        if allowed.contains(&attr_name.as_str()) {
+            rebuilt.push_str(&format!(" {}=\"{}\"", attr_name, value.replace('"', "&quot;")));
+        }
+    }
+    rebuilt.push('>');
+    rebuilt
+}
+
+fn starts_with_at(contents: &[char], i: usize, pattern: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    contents.len() >= i + pattern.len() && contents[i..i + pattern.len()] == pattern[..]
+}
+
+fn find_from(contents: &[char], start: usize, pattern: &str) -> Option<usize> {
+    (start..contents.len()).find(|&pos| starts_with_at(contents, pos, pattern))
+}
+
+// 対応する終了タグの直後まで読み飛ばす
+// （script等の中身は'<'を含みうるため、タグとして解析せず終了タグを直接探す）
+fn skip_to_closing_tag(contents: &[char], i: &mut usize, name: &str) {
+    let closing: Vec<char> = format!("</{}", name).chars().collect();
+    while *i < contents.len() {
+        let matches = contents.len() >= *i + closing.len()
+            && contents[*i..*i + closing.len()]
+                .iter()
+                .zip(&closing)
+                .all(|(a, b)| a.to_ascii_lowercase() == *b);
+        if matches {
+            read_tag(contents, i);
+            return;
+        }
+        *i += 1;
+    }
+}
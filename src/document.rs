@@ -0,0 +1,272 @@
+use crate::api_key::SharedApiKeyStore;
+use crate::app_state::AppState;
+use crate::culling::{read_tag, tag_name};
+use crate::content::{self, ContentKind};
+use crate::feed::{self, Feed};
+use crate::fetcher::{get_page, FetchedPage, PageBody};
+use crate::html_parser::{extract_href, get_base_url, normalize_url, resolve_relative_url};
+use crate::process_options::ProcessOptions;
+use crate::profile::ProcessingProfile;
+
+// カリング済みHTMLをテキスト系フロントエンド（Gemini等）向けのブロック列に変換する
+
+// ========== データ構造 ==========
+#[derive(Debug, Clone)]
+pub enum Block {
+    Heading(u8, String),
+    Text(String),
+    ListItem(String),
+    Preformatted(String),
+    Link { url: String, text: String },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub title: Option<String>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    Normal,
+    Heading(u8),
+    ListItem,
+}
+
+// ========== 取得 ==========
+
+// URLを取得してカリングし、ブロック列と元のバイト数を返す
+pub async fn fetch_document(target_url: &str, profile: &ProcessingProfile, api_key: &str, state: &AppState) -> Result<(Document, u64), String> {
+    let normalized_url = normalize_url(target_url);
+
+    let context = state.fetch_context(api_key, &ProcessOptions::default(), None).await;
+    let result = get_page(&normalized_url, &context).await;
+    state.save_fetch_context(&context).await;
+
+    match result {
+        Ok(page) => Ok((page_document(&page, profile), page.original_size())),
+        Err(e) => Err(format!("URL取得エラー: {}", e))
+    }
+}
+
+// Content-Typeに応じてブロック列を作る（HTML以外はテキストまたはダウンロードリンクにする）
+pub fn page_document(page: &FetchedPage, profile: &ProcessingProfile) -> Document {
+    if let (ContentKind::Html, PageBody::Text(html)) = (&page.kind, &page.body) {
+        let base_url = get_base_url(&page.final_url);
+        let culled_html = profile.cull(html);
+        return build_document(&culled_html, &base_url, &page.final_url);
+    }
+
+    if let Some(parsed_feed) = feed::page_feed(page) {
+        return feed_document(parsed_feed);
+    }
+
+    let mut document = Document::default();
+    match content::extract_text(page) {
+        Ok(text) if page.kind == ContentKind::Pdf => {
+            document.blocks = text
+                .split("\n\n")
+                .map(normalize_text)
+                .filter(|paragraph| !paragraph.is_empty())
+                .map(Block::Text)
+                .collect();
+        }
+        Ok(text) if page.kind.is_textual() => document.blocks.push(Block::Preformatted(text.trim_end().to_string())),
+        _ => {
+            let size = match page.original_size() {
+                0 => String::new(),
+                size => format!(" ({})", content::format_size(size)),
+            };
+            document.blocks.push(Block::Link {
+                url: page.final_url.clone(),
+                text: format!("ダウンロード{}", size),
+            });
+        }
+    }
+    document
+}
+
+// フィードの記事をリンクと概要のブロックにする
+fn feed_document(parsed_feed: Feed) -> Document {
+    let mut document = Document::default();
+    if !parsed_feed.title.is_empty() {
+        document.blocks.push(Block::Heading(1, parsed_feed.title.clone()));
+        document.title = Some(parsed_feed.title);
+    }
+    for entry in parsed_feed.entries {
+        let title = if entry.title.is_empty() { "(無題)".to_string() } else { entry.title };
+        match entry.link {
+            Some(url) => document.blocks.push(Block::Link { url, text: title }),
+            None => document.blocks.push(Block::Heading(3, title)),
+        }
+        let summary = match entry.date {
+            Some(date) if entry.summary.is_empty() => date,
+            Some(date) => format!("{} - {}", date, entry.summary),
+            None => entry.summary,
+        };
+        if !summary.is_empty() {
+            document.blocks.push(Block::Text(summary));
+        }
+    }
+    document
+}
+
+// APIキーの既定プロファイル（未設定または不明な場合は標準プロファイル）
+pub async fn key_profile(api_key: &str, api_key_store: &SharedApiKeyStore) -> ProcessingProfile {
+    let name = api_key_store.read().await.get_default_profile(api_key);
+    ProcessingProfile::key_default(name.as_deref())
+}
+
+// ========== 解析 ==========
+pub fn build_document(culled_html: &str, base_url: &str, current_url: &str) -> Document {
+    let contents: Vec<char> = culled_html.chars().collect();
+    let mut document = Document::default();
+    let mut context = Context::Normal;
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < contents.len() {
+        if contents[i] != '<' {
+            text.push(contents[i]);
+            i += 1;
+            continue;
+        }
+
+        let tag = read_tag(&contents, &mut i);
+        let (name, is_closing) = tag_name(&tag);
+
+        match name.as_str() {
+            "title" if !is_closing => {
+                let title = read_until_closing(&contents, &mut i, "title");
+                let title = normalize_text(&title);
+                if !title.is_empty() {
+                    document.title = Some(title);
+                }
+            }
+            "pre" if !is_closing => {
+                flush_text(&mut document, &mut text, context);
+                let preformatted = read_until_closing(&contents, &mut i, "pre");
+                let preformatted = strip_tags(&preformatted);
+                if !preformatted.trim().is_empty() {
+                    document.blocks.push(Block::Preformatted(decode_entities(preformatted.trim_end())));
+                }
+            }
+            "a" if !is_closing => {
+                let href = extract_href(&tag);
+                let link_text = normalize_text(&strip_tags(&read_until_closing(&contents, &mut i, "a")));
+                if href.is_empty() {
+                    text.push_str(&link_text);
+                    continue;
+                }
+                flush_text(&mut document, &mut text, context);
+                let url = resolve_relative_url(&href, base_url, current_url);
+                let text = if link_text.is_empty() { url.clone() } else { link_text };
+                document.blocks.push(Block::Link { url, text });
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                flush_text(&mut document, &mut text, context);
+                context = if is_closing {
+                    Context::Normal
+                } else {
+                    Context::Heading(name.as_bytes()[1] - b'0')
+                };
+            }
+            "li" => {
+                flush_text(&mut document, &mut text, context);
+                context = if is_closing { Context::Normal } else { Context::ListItem };
+            }
+            "br" | "p" | "div" | "ul" | "ol" | "tr" | "table" | "blockquote" => {
+                flush_text(&mut document, &mut text, context);
+            }
+            _ => {}
+        }
+    }
+
+    flush_text(&mut document, &mut text, context);
+    document
+}
+
+// ========== プレーンテキスト ==========
+
+// JSON APIのformat=text用。リンクは「テキスト <URL>」の形で残す
+pub fn render_plain_text(document: &Document) -> String {
+    let mut text = String::new();
+
+    if let Some(title) = &document.title {
+        text.push_str(&format!("{}\n\n", title));
+    }
+
+    for block in &document.blocks {
+        match block {
+            Block::Heading(_, heading) => text.push_str(&format!("\n{}\n\n", heading)),
+            Block::Text(paragraph) => text.push_str(&format!("{}\n", paragraph)),
+            Block::ListItem(item) => text.push_str(&format!("- {}\n", item)),
+            Block::Preformatted(preformatted) => text.push_str(&format!("{}\n", preformatted)),
+            Block::Link { url, text: link_text } if *link_text == *url => text.push_str(&format!("<{}>\n", url)),
+            Block::Link { url, text: link_text } => text.push_str(&format!("{} <{}>\n", link_text, url)),
+        }
+    }
+
+    text
+}
+
+// ========== ヘルパー関数 ==========
+
+// 指定した終了タグまでの内容を読み取り、終了タグの直後まで進める
+fn read_until_closing(contents: &[char], i: &mut usize, name: &str) -> String {
+    let mut inner = String::new();
+    while *i < contents.len() {
+        if contents[*i] == '<' {
+            let start = *i;
+            let tag = read_tag(contents, i);
+            let (tag_name, is_closing) = tag_name(&tag);
+            if is_closing && tag_name == name {
+                return inner;
+            }
+            inner.extend(&contents[start..*i]);
+        } else {
+            inner.push(contents[*i]);
+            *i += 1;
+        }
+    }
+    inner
+}
+
+pub fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(ch),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn decode_entities(text: &str) -> String {
+    htmlescape::decode_html(text).unwrap_or_else(|_| text.to_string())
+}
+
+// 空白を1つにまとめ、HTMLエンティティをデコードする
+pub fn normalize_text(text: &str) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    decode_entities(&collapsed)
+}
+
+fn flush_text(document: &mut Document, text: &mut String, context: Context) {
+    let normalized = normalize_text(text);
+    text.clear();
+    if normalized.is_empty() {
+        return;
+    }
+
+    let block = match context {
+        Context::Normal => Block::Text(normalized),
+        Context::Heading(level) => Block::Heading(level, normalized),
+        Context::ListItem => Block::ListItem(normalized),
+    };
+    document.blocks.push(block);
+}
@@ -0,0 +1,514 @@
+use crate::culling::{get_attribute, read_tag, tag_name};
+use crate::html_parser::{escape_attribute, SAVE_FORM_END, SAVE_FORM_START};
+
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// 軽量化したページ（保存したページを含む）をEPUB 3にまとめる
+// 章の本文は軽量化したHTMLをXHTMLとして正しい形に整えたもの
+
+// ========== 定数 ==========
+const BOOK_LANGUAGE: &str = "ja";
+const STYLESHEET: &str = "body{line-height:1.6;} h1.chapter-title{font-size:1.4em;} p.source{font-size:small;word-break:break-all;} pre{white-space:pre-wrap;font-size:0.9em;} blockquote{margin:0.5em 0;padding-left:1em;border-left:3px solid #999;} table{border-collapse:collapse;} th,td{border:1px solid #999;padding:2px 4px;} img{max-width:100%;}";
+
+// 本文から除く要素（内容ごと）
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "form", "select", "textarea", "button", "iframe", "object", "svg"];
+// 残す要素（それ以外はタグを除いて内容だけ残す）
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "h1", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "li", "pre", "blockquote", "table", "thead", "tbody", "tfoot", "tr",
+    "td", "th", "dl", "dt", "dd",
+];
+const INLINE_ELEMENTS: &[&str] = &["a", "b", "strong", "i", "em", "small", "sup", "sub", "code"];
+const VOID_ELEMENTS: &[&str] = &["br", "hr", "img"];
+// テキストを直接置けない要素（空白だけのテキストは捨てる）
+const CONTAINER_ONLY_ELEMENTS: &[&str] = &["ul", "ol", "table", "thead", "tbody", "tfoot", "tr", "dl"];
+
+// ========== データ構造 ==========
+pub struct Chapter {
+    pub title: String,
+    // 元のURL（章の先頭にリンクを置く）
+    pub url: String,
+    // 軽量化したHTML
+    pub content: String,
+}
+
+// ========== EPUBの作成 ==========
+
+// imagesは元の画像のURLから縮小済みJPEGへの対応（ないものは代替テキストにする）
+pub fn build_epub(title: &str, chapters: &[Chapter], images: &HashMap<String, Vec<u8>>) -> Result<Vec<u8>, String> {
+    let identifier = generate_book_identifier();
+
+    // 画像は最初に出てきた順に番号を付ける
+    let mut image_paths: HashMap<String, String> = HashMap::new();
+    let mut image_files: Vec<(String, &[u8])> = Vec::new();
+    for chapter in chapters {
+        for url in image_urls(&chapter.content) {
+            if let (Some(data), false) = (images.get(&url), image_paths.contains_key(&url)) {
+                let path = format!("images/img-{}.jpg", image_files.len() + 1);
+                image_paths.insert(url, path.clone());
+                image_files.push((path, data));
+            }
+        }
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // mimetypeは先頭に無圧縮で置く
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    add_file(&mut zip, "mimetype", b"application/epub+zip", stored)?;
+    add_file(&mut zip, "META-INF/container.xml", render_container().as_bytes(), deflated)?;
+    add_file(&mut zip, "OEBPS/content.opf", render_package(&identifier, title, chapters.len(), &image_files).as_bytes(), deflated)?;
+    add_file(&mut zip, "OEBPS/nav.xhtml", render_nav(title, chapters).as_bytes(), deflated)?;
+    add_file(&mut zip, "OEBPS/toc.ncx", render_ncx(&identifier, title, chapters).as_bytes(), deflated)?;
+    add_file(&mut zip, "OEBPS/style.css", STYLESHEET.as_bytes(), deflated)?;
+    for (index, chapter) in chapters.iter().enumerate() {
+        let name = format!("OEBPS/{}", chapter_file(index));
+        add_file(&mut zip, &name, render_chapter(chapter, &image_paths).as_bytes(), deflated)?;
+    }
+    for (path, data) in &image_files {
+        // JPEGは圧縮しても小さくならない
+        add_file(&mut zip, &format!("OEBPS/{}", path), data, stored)?;
+    }
+
+    let cursor = zip.finish().map_err(|e| e.to_string())?;
+    Ok(cursor.into_inner())
+}
+
+// 縮小画像として表示している画像の元のURL（出現順、重複なし）
+pub fn image_urls(content: &str) -> Vec<String> {
+    let body = chapter_body(content);
+    let contents: Vec<char> = body.chars().collect();
+    let mut urls: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < contents.len() {
+        if !starts_tag(&contents, i) {
+            i += 1;
+            continue;
+        }
+        let tag = read_tag(&contents, &mut i);
+        if tag_name(&tag) != ("img".to_string(), false) {
+            continue;
+        }
+        if let Some(url) = thumbnail_source(&tag).filter(|url| !urls.contains(url)) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+// ========== 各ファイルの出力 ==========
+
+fn add_file(zip: &mut ZipWriter<Cursor<Vec<u8>>>, name: &str, data: &[u8], options: FileOptions) -> Result<(), String> {
+    zip.start_file(name, options).map_err(|e| e.to_string())?;
+    zip.write_all(data).map_err(|e| e.to_string())
+}
+
+fn render_container() -> String {
+    concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n",
+        "<rootfiles><rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/></rootfiles>\n",
+        "</container>\n"
+    )
+    .to_string()
+}
+
+fn render_package(identifier: &str, title: &str, chapter_count: usize, image_files: &[(String, &[u8])]) -> String {
+    let mut manifest = String::from(concat!(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+        "<item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n",
+        "<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n"
+    ));
+    let mut spine = String::new();
+    for index in 0..chapter_count {
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            index + 1,
+            chapter_file(index)
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", index + 1));
+    }
+    for (index, (path, _)) in image_files.iter().enumerate() {
+        manifest.push_str(&format!("<item id=\"img-{}\" href=\"{}\" media-type=\"image/jpeg\"/>\n", index + 1, path));
+    }
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{lang}\">\n",
+            "<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n",
+            "<dc:identifier id=\"book-id\">{identifier}</dc:identifier>\n",
+            "<dc:title>{title}</dc:title>\n",
+            "<dc:language>{lang}</dc:language>\n",
+            "<meta property=\"dcterms:modified\">{modified}</meta>\n",
+            "</metadata>\n",
+            "<manifest>\n{manifest}</manifest>\n",
+            "<spine toc=\"ncx\">\n{spine}</spine>\n",
+            "</package>\n"
+        ),
+        lang = BOOK_LANGUAGE,
+        identifier = identifier,
+        title = htmlescape::encode_minimal(title),
+        modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        manifest = manifest,
+        spine = spine
+    )
+}
+
+// EPUB 3の目次
+fn render_nav(title: &str, chapters: &[Chapter]) -> String {
+    let items: String = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| format!("<li><a href=\"{}\">{}</a></li>\n", chapter_file(index), htmlescape::encode_minimal(&chapter.title)))
+        .collect();
+    format!(
+        "{}<body>\n<nav epub:type=\"toc\" id=\"toc\"><h1>目次</h1>\n<ol>\n{}</ol></nav>\n</body>\n</html>\n",
+        xhtml_header(title),
+        items
+    )
+}
+
+// EPUB 2向けの目次（古い電子書籍リーダー用）
+fn render_ncx(identifier: &str, title: &str, chapters: &[Chapter]) -> String {
+    let points: String = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| {
+            format!(
+                "<navPoint id=\"nav-{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel><content src=\"{2}\"/></navPoint>\n",
+                index + 1,
+                htmlescape::encode_minimal(&chapter.title),
+                chapter_file(index)
+            )
+        })
+        .collect();
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n",
+            "<head><meta name=\"dtb:uid\" content=\"{}\"/></head>\n",
+            "<docTitle><text>{}</text></docTitle>\n",
+            "<navMap>\n{}</navMap>\n",
+            "</ncx>\n"
+        ),
+        identifier,
+        htmlescape::encode_minimal(title),
+        points
+    )
+}
+
+fn render_chapter(chapter: &Chapter, image_paths: &HashMap<String, String>) -> String {
+    format!(
+        "{}<body>\n<h1 class=\"chapter-title\">{}</h1>\n<p class=\"source\"><a href=\"{}\">{}</a></p>\n{}\n</body>\n</html>\n",
+        xhtml_header(&chapter.title),
+        htmlescape::encode_minimal(&chapter.title),
+        escape_attribute(&chapter.url),
+        htmlescape::encode_minimal(&chapter.url),
+        content_to_xhtml(&chapter.content, image_paths)
+    )
+}
+
+fn xhtml_header(title: &str) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE html>\n",
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{0}\" lang=\"{0}\">\n",
+            "<head><meta charset=\"UTF-8\"/><title>{1}</title><link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/></head>\n"
+        ),
+        BOOK_LANGUAGE,
+        htmlescape::encode_minimal(title)
+    )
+}
+
+// ========== XHTMLへの変換 ==========
+
+// 軽量化したHTMLの本文を、閉じ忘れや入れ子の誤りを直したXHTMLにする
+// （リンクはプロキシを経由しない元のURLにし、縮小画像はimage_pathsにあるものだけ埋め込む）
+fn content_to_xhtml(content: &str, image_paths: &HashMap<String, String>) -> String {
+    let body = chapter_body(content);
+    let contents: Vec<char> = body.chars().collect();
+    let mut writer = XhtmlWriter::default();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < contents.len() {
+        if !starts_tag(&contents, i) {
+            text.push(contents[i]);
+            i += 1;
+            continue;
+        }
+        writer.text(&std::mem::take(&mut text));
+
+        let tag = read_tag(&contents, &mut i);
+        let (name, is_closing) = tag_name(&tag);
+        let name = name.as_str();
+        if SKIPPED_ELEMENTS.contains(&name) {
+            if !is_closing {
+                skip_element(&contents, &mut i, name);
+            }
+        } else if is_closing {
+            writer.close(name);
+        } else if name == "a" {
+            // 内部リンクはテキストだけ残す
+            if let Some(href) = link_target(&tag) {
+                writer.open("a", &format!(" href=\"{}\"", escape_attribute(&href)));
+            }
+        } else if name == "img" {
+            let alt = get_attribute(&tag, "alt").unwrap_or_default();
+            match thumbnail_source(&tag).and_then(|url| image_paths.get(&url)) {
+                Some(path) => writer.void("img", &format!(" src=\"{}\" alt=\"{}\"", escape_attribute(path), escape_attribute(&decode_entities(&alt)))),
+                None => writer.text(&alt),
+            }
+        } else if name == "td" || name == "th" {
+            writer.open(name, &span_attributes(&tag));
+        } else if VOID_ELEMENTS.contains(&name) {
+            writer.void(name, "");
+        } else if BLOCK_ELEMENTS.contains(&name) || INLINE_ELEMENTS.contains(&name) {
+            writer.open(name, "");
+        }
+    }
+    writer.text(&text);
+    writer.finish()
+}
+
+#[derive(Default)]
+struct XhtmlWriter {
+    output: String,
+    // 開いている要素（外側から）
+    open: Vec<&'static str>,
+}
+
+impl XhtmlWriter {
+    fn open(&mut self, name: &str, attributes: &str) {
+        let name = element_name(name);
+        self.close_implied(name);
+        self.output.push_str(&format!("<{}{}>", name, attributes));
+        self.open.push(name);
+    }
+
+    fn void(&mut self, name: &str, attributes: &str) {
+        if name == "hr" {
+            self.close_implied("hr");
+        }
+        self.output.push_str(&format!("<{}{}/>", name, attributes));
+    }
+
+    // 開いていない要素の終了タグは無視する
+    fn close(&mut self, name: &str) {
+        if let Some(position) = self.open.iter().rposition(|open| *open == name) {
+            self.close_to(position);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let in_container = self.open.last().is_some_and(|name| CONTAINER_ONLY_ELEMENTS.contains(name));
+        if text.is_empty() || (in_container && text.trim().is_empty()) {
+            return;
+        }
+        self.output.push_str(&htmlescape::encode_minimal(&decode_entities(text)));
+    }
+
+    fn finish(mut self) -> String {
+        self.close_to(0);
+        self.output
+    }
+
+    fn close_to(&mut self, position: usize) {
+        while self.open.len() > position {
+            let name = self.open.pop().unwrap_or_default();
+            self.output.push_str(&format!("</{}>", name));
+        }
+    }
+
+    // HTMLでは省略できる終了タグを補う
+    fn close_implied(&mut self, name: &str) {
+        // (閉じる要素, それより内側にあれば閉じない要素)
+        let (implied, barriers): (&[&str], &[&str]) = match name {
+            // ブロック要素は開いている段落を閉じる
+            "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol" | "pre" | "blockquote" | "table" | "dl" | "hr" => (&["p"], BLOCK_ELEMENTS),
+            "li" => (&["li"], &["ul", "ol"]),
+            "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+            "tr" => (&["tr"], &["table"]),
+            "td" | "th" => (&["td", "th"], &["table", "tr"]),
+            "a" => (&["a"], &[]),
+            _ => return,
+        };
+        if let Some(position) = self.open.iter().rposition(|open| implied.contains(open)) {
+            if !self.open[position + 1..].iter().any(|open| barriers.contains(open)) {
+                self.close_to(position);
+            }
+        }
+    }
+}
+
+// ========== ヘルパー関数 ==========
+
+// <body>から</body>までを取り出し、保存ボタンを除く
+fn chapter_body(content: &str) -> String {
+    let lower = content.to_ascii_lowercase();
+    let start = lower.find("<body").and_then(|start| content[start..].find('>').map(|end| start + end + 1)).unwrap_or(0);
+    let end = lower.rfind("</body>").filter(|end| *end >= start).unwrap_or(content.len());
+    let body = &content[start..end];
+
+    match body.find(SAVE_FORM_START) {
+        Some(form_start) => match body[form_start..].find(SAVE_FORM_END) {
+            Some(form_end) => format!("{}{}", &body[..form_start], &body[form_start + form_end + SAVE_FORM_END.len()..]),
+            None => body.to_string(),
+        },
+        None => body.to_string(),
+    }
+}
+
+// 縮小画像（<img data-rigil-src="/image?url=...">）の元のURL
+fn thumbnail_source(tag: &str) -> Option<String> {
+    let src = get_attribute(tag, "data-rigil-src")?;
+    query_parameter(&decode_entities(&src), "/image?", "url")
+}
+
+// リンク先の元のURL（プロキシ経由のリンクは元のURLに戻す。内部リンクはNone）
+fn link_target(tag: &str) -> Option<String> {
+    let href = decode_entities(&get_attribute(tag, "href")?);
+    if href.starts_with("/proxy?") {
+        return query_parameter(&href, "/proxy?", "url");
+    }
+    let lower = href.to_ascii_lowercase();
+    if lower.starts_with("http://") || lower.starts_with("https://") || lower.starts_with("mailto:") {
+        Some(href)
+    } else {
+        None
+    }
+}
+
+fn query_parameter(href: &str, prefix: &str, name: &str) -> Option<String> {
+    let query = href.strip_prefix(prefix)?;
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+// 表のセルは数値のcolspan/rowspanのみ残す
+fn span_attributes(tag: &str) -> String {
+    ["colspan", "rowspan"]
+        .iter()
+        .filter_map(|name| {
+            get_attribute(tag, name)
+                .and_then(|value| value.trim().parse::<u32>().ok())
+                .map(|value| format!(" {}=\"{}\"", name, value))
+        })
+        .collect()
+}
+
+// 「a < b」のようなテキスト中の<はタグとして扱わない
+fn starts_tag(contents: &[char], i: usize) -> bool {
+    contents[i] == '<' && contents.get(i + 1).is_some_and(|next| next.is_ascii_alphabetic() || *next == '/' || *next == '!')
+}
+
+// 指定した要素の終了タグの直後まで読み飛ばす
+fn skip_element(contents: &[char], i: &mut usize, name: &str) {
+    while *i < contents.len() {
+        if starts_tag(contents, *i) {
+            let tag = read_tag(contents, i);
+            if tag_name(&tag) == (name.to_string(), true) {
+                return;
+            }
+        } else {
+            *i += 1;
+        }
+    }
+}
+
+// XHTMLに出力する要素名（変換対象の一覧にある名前）
+fn element_name(name: &str) -> &'static str {
+    BLOCK_ELEMENTS
+        .iter()
+        .chain(INLINE_ELEMENTS)
+        .find(|element| **element == name)
+        .copied()
+        .unwrap_or("span")
+}
+
+fn decode_entities(text: &str) -> String {
+    htmlescape::decode_html(text).unwrap_or_else(|_| text.to_string())
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{}.xhtml", index + 1)
+}
+
+// ランダムなUUID（バージョン4）
+fn generate_book_identifier() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("urn:uuid:{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    fn read_entry(archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut contents = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn epub_has_the_required_structure() {
+        let chapters = vec![
+            Chapter {
+                title: "一章 & 序".to_string(),
+                url: "https://example.com/1".to_string(),
+                content: "<html><body><p>本文<br>続き</p><img data-rigil-src=\"/image?url=https%3A%2F%2Fexample.com%2Fa.png\" alt=\"図\"></body></html>".to_string(),
+            },
+            Chapter {
+                title: "二章".to_string(),
+                url: "https://example.com/2".to_string(),
+                content: "<html><body><p>二番目</p></body></html>".to_string(),
+            },
+        ];
+        let images = HashMap::from([("https://example.com/a.png".to_string(), vec![0xff, 0xd8, 0xff])]);
+        let epub = build_epub("本", &chapters, &images).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(epub)).unwrap();
+
+        // mimetypeは先頭に無圧縮で置く
+        let mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        drop(mimetype);
+        assert_eq!(read_entry(&mut archive, "mimetype"), "application/epub+zip");
+        assert!(read_entry(&mut archive, "META-INF/container.xml").contains("full-path=\"OEBPS/content.opf\""));
+
+        let package = read_entry(&mut archive, "OEBPS/content.opf");
+        assert!(package.contains("<dc:title>本</dc:title>"));
+        assert!(package.contains("<itemref idref=\"chapter-1\"/>\n<itemref idref=\"chapter-2\"/>"));
+        assert!(package.contains("href=\"images/img-1.jpg\" media-type=\"image/jpeg\""));
+
+        assert!(read_entry(&mut archive, "OEBPS/nav.xhtml").contains("<a href=\"chapter-1.xhtml\">一章 &amp; 序</a>"));
+        let chapter = read_entry(&mut archive, "OEBPS/chapter-1.xhtml");
+        assert!(chapter.contains("<br/>"));
+        assert!(chapter.contains("images/img-1.jpg"));
+        assert!(read_entry(&mut archive, "OEBPS/chapter-2.xhtml").contains("二番目"));
+        assert_eq!(archive.by_name("OEBPS/images/img-1.jpg").unwrap().size(), 3);
+    }
+
+    #[test]
+    fn images_without_thumbnails_are_not_embedded() {
+        let content = "<body><img data-rigil-src=\"/image?url=https%3A%2F%2Fexample.com%2Fb.png\" alt=\"図\"></body>";
+        assert_eq!(image_urls(content), vec!["https://example.com/b.png"]);
+        let chapters = vec![Chapter { title: "章".to_string(), url: "https://example.com/".to_string(), content: content.to_string() }];
+        let epub = build_epub("本", &chapters, &HashMap::new()).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(epub)).unwrap();
+        assert!(!read_entry(&mut archive, "OEBPS/content.opf").contains("image/jpeg"));
+        assert!(!read_entry(&mut archive, "OEBPS/chapter-1.xhtml").contains("<img"));
+    }
+}
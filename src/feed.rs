@@ -0,0 +1,280 @@
+use crate::content::ContentKind;
+use crate::culling::get_attribute;
+use crate::document::{normalize_text, strip_tags};
+use crate::fetcher::{FetchedPage, PageBody};
+use crate::html_parser::{get_base_url, render_proxy_link, resolve_relative_url};
+use crate::process_options::ProcessOptions;
+
+use chrono::DateTime;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+// RSS 2.0 / RSS 1.0 (RDF) / Atomフィードの解析と、記事一覧としての表示
+
+// ========== 定数 ==========
+// 一覧に表示する概要の最大文字数
+const SUMMARY_MAX_CHARS: usize = 300;
+
+// ========== データ構造 ==========
+#[derive(Debug, Clone, Default)]
+pub struct Feed {
+    pub title: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeedEntry {
+    pub title: String,
+    pub link: Option<String>,
+    pub date: Option<String>,
+    pub summary: String,
+}
+
+// ========== 判定 ==========
+
+// Content-Type、またはルート要素（rss、feed、rdf:RDF）がフィードならフィードとして解析する
+pub fn page_feed(page: &FetchedPage) -> Option<Feed> {
+    let text = match &page.body {
+        PageBody::Text(text) => text,
+        _ => return None,
+    };
+    let is_feed = match page.kind {
+        ContentKind::Feed => true,
+        ContentKind::Html | ContentKind::Xml | ContentKind::PlainText => has_feed_root(text),
+        _ => false,
+    };
+    if !is_feed {
+        return None;
+    }
+    parse_feed(text, &page.final_url)
+}
+
+fn has_feed_root(text: &str) -> bool {
+    let mut reader = Reader::from_str(text);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => return is_feed_root(&local_name(&e)),
+            Ok(Event::Eof) | Err(_) => return false,
+            // XML宣言、DOCTYPE、コメント等は読み飛ばす
+            Ok(_) => {}
+        }
+    }
+}
+
+fn is_feed_root(name: &str) -> bool {
+    matches!(name, "rss" | "feed" | "rdf")
+}
+
+// ========== 解析 ==========
+
+pub fn parse_feed(xml: &str, feed_url: &str) -> Option<Feed> {
+    let base_url = get_base_url(feed_url);
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut feed = Feed::default();
+    let mut entry: Option<FeedEntry> = None;
+    let mut stack: Vec<String> = Vec::new();
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = local_name(&e);
+                if stack.is_empty() && !is_feed_root(&name) {
+                    return None;
+                }
+                if is_entry_element(&name) {
+                    entry = Some(FeedEntry::default());
+                }
+                if let (Some(entry), "link") = (entry.as_mut(), name.as_str()) {
+                    set_atom_link(entry, &e, &base_url, feed_url);
+                }
+                if is_field_element(&name) {
+                    text.clear();
+                }
+                stack.push(name);
+            }
+            Ok(Event::Empty(e)) => {
+                if let (Some(entry), "link") = (entry.as_mut(), local_name(&e).as_str()) {
+                    set_atom_link(entry, &e, &base_url, feed_url);
+                }
+            }
+            Ok(Event::Text(t)) => {
+                if let Ok(unescaped) = t.unescape() {
+                    text.push_str(&unescaped);
+                }
+            }
+            Ok(Event::CData(c)) => text.push_str(&String::from_utf8_lossy(&c.into_inner())),
+            Ok(Event::End(_)) => {
+                let name = stack.pop().unwrap_or_default();
+                let parent = stack.last().map(|s| s.as_str()).unwrap_or("");
+
+                if is_entry_element(&name) {
+                    if let Some(entry) = entry.take() {
+                        feed.entries.push(entry);
+                    }
+                } else if let Some(entry) = entry.as_mut() {
+                    set_entry_field(entry, &name, &text, &base_url, feed_url);
+                } else if name == "title" && matches!(parent, "channel" | "feed") && feed.title.is_empty() {
+                    feed.title = normalize_text(&text);
+                }
+
+                if is_field_element(&name) {
+                    text.clear();
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                eprintln!("フィード解析エラー: {}", e);
+                break;
+            }
+            Ok(_) => {}
+        }
+    }
+
+    Some(feed)
+}
+
+fn is_entry_element(name: &str) -> bool {
+    matches!(name, "item" | "entry")
+}
+
+fn is_field_element(name: &str) -> bool {
+    matches!(
+        name,
+        "title" | "link" | "pubdate" | "published" | "updated" | "date" | "description" | "summary" | "content" | "encoded"
+    )
+}
+
+fn set_entry_field(entry: &mut FeedEntry, name: &str, text: &str, base_url: &str, feed_url: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    match name {
+        "title" if entry.title.is_empty() => entry.title = normalize_text(&strip_tags(text)),
+        // RSSのリンクは要素の本文（Atomはhref属性）
+        "link" if entry.link.is_none() => entry.link = Some(resolve_relative_url(text, base_url, feed_url)),
+        "pubdate" | "published" | "updated" | "date" if entry.date.is_none() => entry.date = Some(format_date(text)),
+        "description" | "summary" | "content" | "encoded" if entry.summary.is_empty() => {
+            entry.summary = shorten(&normalize_text(&strip_tags(text)), SUMMARY_MAX_CHARS);
+        }
+        _ => {}
+    }
+}
+
+// Atomのlink要素（relがないかalternateのもの）
+fn set_atom_link(entry: &mut FeedEntry, element: &BytesStart, base_url: &str, feed_url: &str) {
+    if entry.link.is_some() {
+        return;
+    }
+    let rel = attribute(element, "rel").unwrap_or_default();
+    if !rel.is_empty() && rel != "alternate" {
+        return;
+    }
+    if let Some(href) = attribute(element, "href").filter(|href| !href.is_empty()) {
+        entry.link = Some(resolve_relative_url(&href, base_url, feed_url));
+    }
+}
+
+// ========== 表示 ==========
+
+// 記事一覧（タイトル、日時、概要、プロキシ経由のリンク）
+pub fn render_feed(feed: &Feed, feed_url: &str, options: &ProcessOptions) -> String {
+    let title = if feed.title.is_empty() { feed_url } else { feed.title.as_str() };
+    let mut body = format!(
+        "<h1>{}</h1><p><small>フィード: {} ({}件)</small></p>",
+        htmlescape::encode_minimal(title),
+        htmlescape::encode_minimal(feed_url),
+        feed.entries.len()
+    );
+
+    for entry in &feed.entries {
+        let entry_title = if entry.title.is_empty() { "(無題)" } else { entry.title.as_str() };
+        let heading = match &entry.link {
+            Some(link) => render_proxy_link(link, entry_title, options),
+            None => htmlescape::encode_minimal(entry_title),
+        };
+        body.push_str(&format!("<h3>{}</h3>", heading));
+        if let Some(date) = &entry.date {
+            body.push_str(&format!("<p><small>{}</small></p>", htmlescape::encode_minimal(date)));
+        }
+        if !entry.summary.is_empty() {
+            body.push_str(&format!("<p>{}</p>", htmlescape::encode_minimal(&entry.summary)));
+        }
+    }
+
+    body
+}
+
+// ========== フィードの発見 ==========
+
+// <link rel="alternate" type="application/rss+xml"> 等で示されたフィードの (URL, タイトル) を返す
+pub fn find_feed_links(html: &str, base_url: &str, current_url: &str) -> Vec<(String, String)> {
+    let lower = html.to_ascii_lowercase();
+    let mut links: Vec<(String, String)> = Vec::new();
+    let mut pos = 0;
+
+    // フィードのリンクはhead内にあるため、bodyが始まったら探索をやめる
+    let end = lower.find("<body").unwrap_or(lower.len());
+    while let Some(start) = lower[pos..end].find("<link").map(|offset| pos + offset) {
+        let tag_end = match lower[start..].find('>') {
+            Some(offset) => start + offset + 1,
+            None => break,
+        };
+        pos = tag_end;
+
+        let tag = &html[start..tag_end];
+        let rel = get_attribute(tag, "rel").unwrap_or_default().to_lowercase();
+        let link_type = get_attribute(tag, "type").unwrap_or_default().to_lowercase();
+        if !rel.split_whitespace().any(|r| r == "alternate") || ContentKind::from_content_type(Some(&link_type)) != ContentKind::Feed {
+            continue;
+        }
+        let href = match get_attribute(tag, "href").filter(|href| !href.is_empty()) {
+            Some(href) => resolve_relative_url(&href, base_url, current_url),
+            None => continue,
+        };
+        if links.iter().any(|(existing, _)| *existing == href) {
+            continue;
+        }
+        let title = get_attribute(tag, "title")
+            .map(|title| normalize_text(&title))
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| if link_type.contains("atom") { "Atom".to_string() } else { "RSS".to_string() });
+        links.push((href, title));
+    }
+
+    links
+}
+
+// ========== ヘルパー関数 ==========
+
+// 名前空間の接頭辞を除いた小文字の要素名（dc:date → date、rdf:RDF → rdf）
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).to_lowercase()
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attr| attr.unescape_value().ok().map(|value| value.trim().to_string()))
+}
+
+// RFC 2822（RSS）とRFC 3339（Atom）の日時を読みやすい形式にする（解析できなければそのまま）
+fn format_date(raw: &str) -> String {
+    DateTime::parse_from_rfc2822(raw)
+        .or_else(|_| DateTime::parse_from_rfc3339(raw))
+        .map(|dt| dt.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| raw.to_string())
+}
+
+fn shorten(text: &str, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        format!("{}...", text.chars().take(max_chars).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
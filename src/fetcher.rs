@@ -0,0 +1,609 @@
+use crate::api_error::ErrorCode;
+use crate::config::Config;
+use crate::content::{self, ContentKind};
+use crate::cookie_jar::KeyCookieJar;
+use crate::upstream::{apply_request_headers, build_proxy, is_proxy_host, route_for, UpstreamRoute};
+
+use hyper::client::connect::dns::Name;
+use hyper::StatusCode;
+use rand::Rng;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::error::Error as StdError;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Host, Url};
+
+// ========== 定数 ==========
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REDIRECTS: usize = 10;
+// 一時的な失敗に対する再試行（GETのみ）
+const MAX_RETRIES: u32 = 2;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+// ========== エラー型 ==========
+#[derive(Debug)]
+pub enum FetchError {
+    InvalidUrl(String),
+    // SSRF対策等で送信を拒否した
+    Blocked(String),
+    Dns(String),
+    Connect(String),
+    Tls(String),
+    Timeout,
+    Redirect(String),
+    HttpStatus(StatusCode),
+    TooLarge(usize),
+    Decode(String),
+    Network(String),
+    // 取得できたが内容を処理できない（壊れたPDF等）
+    Unsupported(String),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FetchError::InvalidUrl(msg) => write!(f, "URL解析エラー: {}", msg),
+            FetchError::Blocked(msg) => write!(f, "ブロック: {}", msg),
+            FetchError::Dns(msg) => write!(f, "名前解決エラー: {}", msg),
+            FetchError::Connect(msg) => write!(f, "接続エラー: サーバーに接続できません（{}）", msg),
+            FetchError::Tls(msg) => write!(f, "TLSエラー: {}", msg),
+            FetchError::Timeout => write!(f, "タイムアウトエラー: サーバーからの応答に時間がかかりすぎました"),
+            FetchError::Redirect(msg) => write!(f, "リダイレクトエラー: {}", msg),
+            FetchError::HttpStatus(status) => write!(f, "HTTPエラー: {} - {}", status, status.canonical_reason().unwrap_or("不明なエラー")),
+            FetchError::TooLarge(max_bytes) => write!(f, "サイズ超過: {} bytesを超えています", max_bytes),
+            FetchError::Decode(msg) => write!(f, "レスポンス読み取りエラー: {}", msg),
+            FetchError::Network(msg) => write!(f, "ネットワークエラー: {}", msg),
+            FetchError::Unsupported(msg) => write!(f, "処理できないコンテンツ: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl FetchError {
+    // エラーレスポンスのcodeに使う安定した識別子（HTTPステータスはコードから決まる）
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            FetchError::InvalidUrl(_) => ErrorCode::InvalidUrl,
+            FetchError::Blocked(_) => ErrorCode::UpstreamBlocked,
+            FetchError::Dns(_) => ErrorCode::UpstreamDns,
+            FetchError::Connect(_) => ErrorCode::UpstreamConnect,
+            FetchError::Tls(_) => ErrorCode::UpstreamTls,
+            FetchError::Timeout => ErrorCode::UpstreamTimeout,
+            FetchError::Redirect(_) => ErrorCode::UpstreamRedirect,
+            FetchError::HttpStatus(_) => ErrorCode::UpstreamHttpStatus,
+            FetchError::TooLarge(_) => ErrorCode::UpstreamTooLarge,
+            FetchError::Decode(_) => ErrorCode::UpstreamDecode,
+            FetchError::Network(_) => ErrorCode::UpstreamNetwork,
+            FetchError::Unsupported(_) => ErrorCode::UnsupportedContent,
+        }
+    }
+
+    // 時間をおけば成功しうる失敗か
+    fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Connect(_) | FetchError::Timeout => true,
+            FetchError::HttpStatus(status) => matches!(
+                *status,
+                StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+            ),
+            _ => false,
+        }
+    }
+
+    fn from_request_error(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return FetchError::Timeout;
+        }
+        if let Some(blocked) = find_source::<BlockedAddress>(&e) {
+            return FetchError::Blocked(blocked.to_string());
+        }
+        if e.is_redirect() {
+            return FetchError::Redirect(root_cause_message(&e));
+        }
+        if is_tls_error(&e) {
+            return FetchError::Tls(root_cause_message(&e));
+        }
+        if e.is_connect() {
+            // hyperは名前解決の失敗を "dns error" として報告する
+            if find_source_message(&e, "dns error") {
+                return FetchError::Dns(root_cause_message(&e));
+            }
+            return FetchError::Connect(root_cause_message(&e));
+        }
+        if e.is_builder() || e.is_request() {
+            return FetchError::InvalidUrl(root_cause_message(&e));
+        }
+        FetchError::Network(root_cause_message(&e))
+    }
+
+    fn from_body_error(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            FetchError::Timeout
+        } else if e.is_decode() {
+            FetchError::Decode(root_cause_message(&e))
+        } else {
+            // 読み取り途中の切断
+            FetchError::Network(root_cause_message(&e))
+        }
+    }
+}
+
+// 名前解決の結果が内部ネットワークのアドレスだけだった
+#[derive(Debug)]
+struct BlockedAddress(String);
+
+impl std::fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}は内部ネットワークのアドレスを指しているため接続できません", self.0)
+    }
+}
+
+impl std::error::Error for BlockedAddress {}
+
+// ========== 送信先の検証 ==========
+
+// ループバック・プライベート・リンクローカル等、外部から到達すべきでないアドレス
+fn is_private_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || octets[0] == 0
+                // キャリアグレードNAT (100.64.0.0/10)
+                || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_private_address(&IpAddr::V4(v4));
+            }
+            let first_segment = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // ユニークローカル (fc00::/7)、リンクローカル (fe80::/10)
+                || (first_segment & 0xfe00) == 0xfc00
+                || (first_segment & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+// ホスト名を使わずに判定できる範囲での検証
+fn is_blocked_host_literal(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => is_private_address(&IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_private_address(&IpAddr::V6(ip)),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        None => true,
+    }
+}
+
+// 送信先がHTTP(S)で、内部ネットワークを指していないことを確認する（SSRF対策）
+pub async fn validate_upstream_url(url: &Url) -> Result<(), FetchError> {
+    validate_direct_url(url)?;
+    if Config::global().upstream.allow_private_networks {
+        return Ok(());
+    }
+
+    match route_for(url) {
+        // ホスト名は接続時にPublicAddressResolverで確認する
+        UpstreamRoute::Direct => Ok(()),
+        // socks5hの場合、名前解決はプロキシ側で行われる（.onion等はローカルで解決できない）
+        UpstreamRoute::Proxy(proxy_url) if proxy_url.scheme() == "socks5h" => Ok(()),
+        // HTTPプロキシやsocks5では送信先の名前解決を自前のResolverで確認できないため、事前に確認する
+        UpstreamRoute::Proxy(_) => validate_resolved_addresses(url).await,
+    }
+}
+
+// プロキシを使わずguarded_client_builderのクライアントで送る場合の確認（ホスト名は接続時に確認する）
+pub fn validate_direct_url(url: &Url) -> Result<(), FetchError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(FetchError::Blocked(format!("{}スキームは使用できません", url.scheme())));
+    }
+    if !Config::global().upstream.allow_private_networks && is_blocked_host_literal(url) {
+        return Err(FetchError::Blocked("内部ネットワークへのアクセスは許可されていません".to_string()));
+    }
+    Ok(())
+}
+
+async fn validate_resolved_addresses(url: &Url) -> Result<(), FetchError> {
+    if let Some(Host::Domain(domain)) = url.host() {
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs = tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| FetchError::Dns(e.to_string()))?;
+        for addr in addrs {
+            if is_private_address(&addr.ip()) {
+                return Err(FetchError::Blocked("内部ネットワークへのアクセスは許可されていません".to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+// ========== 名前解決 ==========
+
+// 内部ネットワークのアドレスを除いて名前解決する
+// 接続のたびに使われるため、リダイレクト先も実際に接続するアドレスで確認できる
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            // ポートは接続時にURLのものに置き換えられる
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if is_proxy_host(&host) {
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+            let public: Vec<SocketAddr> = addrs.into_iter().filter(|addr| !is_private_address(&addr.ip())).collect();
+            if public.is_empty() {
+                return Err(Box::new(BlockedAddress(host)) as Box<dyn StdError + Send + Sync>);
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+// 上流やコールバック先への送信に使うクライアントの設定（allow_private_networksでなければ名前解決を制限する）
+pub fn guarded_client_builder() -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    if Config::global().upstream.allow_private_networks {
+        builder
+    } else {
+        builder.dns_resolver(Arc::new(PublicAddressResolver))
+    }
+}
+
+// ========== HTTPクライアント ==========
+
+// 利用者（APIキー）ごとに上流リクエストへ反映する状態
+#[derive(Clone, Default)]
+pub struct FetchContext {
+    // 有効な場合、Cookieを送信しSet-Cookieで更新する
+    pub cookie_jar: Option<Arc<KeyCookieJar>>,
+    // User-Agentプロファイル名（Noneの場合は設定の既定値）
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    // 上流ページの文字コード（Noneの場合はContent-Typeのcharset、なければUTF-8）
+    pub encoding: Option<&'static encoding_rs::Encoding>,
+    // Cache-Control: no-cacheを送り、キャッシュを使わずに取得する
+    pub bypass_cache: bool,
+}
+
+// 送信するリクエストの種類
+#[derive(Clone, Copy)]
+pub enum UpstreamRequest<'a> {
+    Get,
+    // application/x-www-form-urlencodedの本文を送るPOST
+    PostForm(&'a [(String, String)]),
+}
+
+fn build_client(context: &FetchContext) -> Result<reqwest::Client, FetchError> {
+    // リダイレクトはsend_requestで1回ずつ辿り、そのたびに送信先を確認する
+    // （プロキシ経由の場合、自動で辿るとリダイレクト先の名前解決をプロキシ側に任せてしまう）
+    let mut builder = guarded_client_builder()
+        .timeout(REQUEST_TIMEOUT)  // 30秒のタイムアウト
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(proxy) = build_proxy() {
+        // 環境変数のプロキシ設定ではなく、設定ファイルの経路選択を使う
+        builder = builder.no_proxy().proxy(proxy);
+    }
+    if let Some(jar) = &context.cookie_jar {
+        // リダイレクトの途中で設定されたCookieも反映される
+        builder = builder.cookie_provider(jar.clone());
+    }
+    builder
+        .build()
+        .map_err(|e| FetchError::Network(format!("HTTPクライアントの作成エラー: {}", e)))
+}
+
+// リクエストを送信し、成功ステータスのレスポンスを返す
+// GETは一時的な失敗（接続エラー、タイムアウト、502/503/504）の場合に間隔を空けて再試行する
+async fn send_request(url: &str, request: UpstreamRequest<'_>, context: &FetchContext) -> Result<reqwest::Response, FetchError> {
+    let client = build_client(context)?;
+    let parsed_url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+    let max_retries = match request {
+        UpstreamRequest::Get => MAX_RETRIES,
+        UpstreamRequest::PostForm(_) => 0,
+    };
+
+    let mut attempt = 0;
+    loop {
+        let result = match send_following_redirects(&client, &parsed_url, request, context).await {
+            // ステータスコードをチェック
+            Ok(response) if !response.status().is_success() => Err(FetchError::HttpStatus(response.status())),
+            result => result,
+        };
+
+        match result {
+            Err(e) if e.is_transient() && attempt < max_retries => {
+                let delay = retry_delay(attempt);
+                println!("再試行します（{}回目、{}ms後）: {}", attempt + 1, delay.as_millis(), e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// リダイレクトを1回ずつ辿る。送信先の確認と経路の選択はリダイレクト先ごとに行う
+async fn send_following_redirects(
+    client: &reqwest::Client,
+    url: &Url,
+    mut request: UpstreamRequest<'_>,
+    context: &FetchContext,
+) -> Result<reqwest::Response, FetchError> {
+    let mut url = url.clone();
+    for redirects in 0..=MAX_REDIRECTS {
+        validate_upstream_url(&url).await.map_err(|e| match e {
+            FetchError::Blocked(_) if redirects > 0 => {
+                FetchError::Blocked("内部ネットワークへのリダイレクトは許可されていません".to_string())
+            }
+            e => e,
+        })?;
+        println!("経路: {} ({})", route_for(&url), url.host_str().unwrap_or(""));
+
+        let builder = match request {
+            UpstreamRequest::Get => client.get(url.clone()),
+            UpstreamRequest::PostForm(fields) => client.post(url.clone()).form(fields),
+        };
+        let response = apply_request_headers(builder, &url, context)
+            .send()
+            .await
+            .map_err(FetchError::from_request_error)?;
+
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok());
+        let location = match location {
+            Some(location) if status.is_redirection() && status != StatusCode::NOT_MODIFIED => location,
+            _ => return Ok(response),
+        };
+        url = url
+            .join(location)
+            .map_err(|e| FetchError::Redirect(format!("リダイレクト先のURLが不正です: {}", e)))?;
+
+        // 307/308以外はブラウザと同様にGETで辿る
+        if !matches!(status, StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT) {
+            request = UpstreamRequest::Get;
+        }
+    }
+    Err(FetchError::Redirect("リダイレクトが多すぎます".to_string()))
+}
+
+// 指数バックオフにジッターを加えた待ち時間（基準値の0.5〜1.5倍）
+fn retry_delay(attempt: u32) -> Duration {
+    let base = RETRY_BASE_DELAY * 2u32.pow(attempt);
+    base.mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+}
+
+// エラーの原因をたどって指定した型のものを探す
+fn find_source<'a, T: StdError + 'static>(e: &'a (dyn StdError + 'static)) -> Option<&'a T> {
+    let mut current: Option<&(dyn StdError + 'static)> = Some(e);
+    while let Some(err) = current {
+        if let Some(found) = err.downcast_ref::<T>() {
+            return Some(found);
+        }
+        current = err.source();
+    }
+    None
+}
+
+fn find_source_message(e: &reqwest::Error, pattern: &str) -> bool {
+    let mut current = e.source();
+    while let Some(err) = current {
+        if err.to_string().contains(pattern) {
+            return true;
+        }
+        current = err.source();
+    }
+    false
+}
+
+fn is_tls_error(e: &reqwest::Error) -> bool {
+    let mut current: Option<&(dyn StdError + 'static)> = Some(e);
+    while let Some(err) = current {
+        if err.is::<rustls::Error>() {
+            return true;
+        }
+        // hyper-rustlsはTLSエラーをio::Errorに包む（io::Error::sourceは包んだエラー自体を返さない）
+        if let Some(io_error) = err.downcast_ref::<std::io::Error>() {
+            if io_error.get_ref().map(|inner| inner.is::<rustls::Error>()).unwrap_or(false) {
+                return true;
+            }
+        }
+        current = err.source();
+    }
+    false
+}
+
+// reqwestのエラーは上位ほど汎用的な文言になるため、最も下位の原因を表示する
+fn root_cause_message(e: &reqwest::Error) -> String {
+    let mut root: &(dyn StdError + 'static) = e;
+    while let Some(source) = root.source() {
+        root = source;
+    }
+    root.to_string()
+}
+
+// ========== 取得結果 ==========
+
+pub enum PageBody {
+    Text(String),
+    // PDFから抽出したテキスト（source_lenは元のPDFのバイト数）
+    ExtractedText { text: String, source_len: usize },
+    // 画像やダウンロード用のファイルは本文を読まない
+    NotLoaded,
+}
+
+impl PageBody {
+    pub fn len(&self) -> usize {
+        match self {
+            PageBody::Text(text) => text.len(),
+            PageBody::ExtractedText { source_len, .. } => *source_len,
+            PageBody::NotLoaded => 0,
+        }
+    }
+}
+
+pub struct FetchedPage {
+    pub final_url: String,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub kind: ContentKind,
+    pub body: PageBody,
+}
+
+impl FetchedPage {
+    // 使用量の記録に使う元のサイズ（本文を読まなかった場合はContent-Length）
+    pub fn original_size(&self) -> u64 {
+        match self.body {
+            PageBody::NotLoaded => self.content_length.unwrap_or(0),
+            _ => self.body.len() as u64,
+        }
+    }
+}
+
+// ========== 取得関数 ==========
+
+// ページを取得する関数（非同期版）
+pub async fn get_page(url: &str, context: &FetchContext) -> Result<FetchedPage, FetchError> {
+    fetch_page(url, UpstreamRequest::Get, context).await
+}
+
+// フォームをPOSTし、リダイレクトを辿った先のページを取得する
+pub async fn post_form(url: &str, fields: &[(String, String)], context: &FetchContext) -> Result<FetchedPage, FetchError> {
+    fetch_page(url, UpstreamRequest::PostForm(fields), context).await
+}
+
+// Content-Typeに応じて本文を読む（画像やその他のファイルは本文を読まない）
+async fn fetch_page(url: &str, request: UpstreamRequest<'_>, context: &FetchContext) -> Result<FetchedPage, FetchError> {
+    println!("ページを取得中: {}", url);
+
+    let mut response = send_request(url, request, context).await?;
+
+    // リダイレクト後の最終URLを取得
+    let final_url = response.url().to_string();
+    println!("最終URL: {}", final_url);
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let content_length = response.content_length();
+    let kind = ContentKind::from_content_type(content_type.as_deref());
+
+    let body = match kind {
+        ContentKind::Pdf => {
+            let bytes = read_body(&mut response, Config::global().content.max_pdf_bytes).await?;
+            let source_len = bytes.len();
+            let text = content::extract_pdf_text(bytes).await.map_err(FetchError::Unsupported)?;
+            PageBody::ExtractedText { text, source_len }
+        }
+        ContentKind::Image | ContentKind::Other => PageBody::NotLoaded,
+        _ => {
+            let bytes = read_body(&mut response, Config::global().content.max_page_bytes).await?;
+            // 指定がなければContent-Typeのcharset、それもなければUTF-8として読む
+            let encoding = context
+                .encoding
+                .or_else(|| content_type.as_deref().and_then(charset_of))
+                .unwrap_or(encoding_rs::UTF_8);
+            PageBody::Text(encoding.decode(&bytes).0.into_owned())
+        }
+    };
+
+    println!("取得完了: {} ({} bytes)", content_type.as_deref().unwrap_or("Content-Typeなし"), body.len());
+    Ok(FetchedPage {
+        final_url,
+        content_type,
+        content_length,
+        kind,
+        body,
+    })
+}
+
+// バイナリ（画像等）を取得する関数。max_bytesを超える場合はエラーにする
+pub async fn get_bytes(url: &str, max_bytes: usize, context: &FetchContext) -> Result<(Vec<u8>, Option<String>), FetchError> {
+    println!("データを取得中: {}", url);
+
+    let mut response = send_request(url, UpstreamRequest::Get, context).await?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    let body = read_body(&mut response, max_bytes).await?;
+    Ok((body, content_type))
+}
+
+// 本文を少しずつ読み、max_bytesを超えた時点でエラーにする
+async fn read_body(response: &mut reqwest::Response, max_bytes: usize) -> Result<Vec<u8>, FetchError> {
+    if response.content_length().map(|len| len as usize > max_bytes).unwrap_or(false) {
+        return Err(FetchError::TooLarge(max_bytes));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(FetchError::from_body_error)? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(FetchError::TooLarge(max_bytes));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+// Content-Typeのcharsetパラメータ
+fn charset_of(content_type: &str) -> Option<&'static encoding_rs::Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| encoding_rs::Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn private_addresses_are_detected() {
+        for ip in ["127.0.0.1", "10.1.2.3", "169.254.169.254", "100.64.0.1", "::1", "fe80::1", "fd00::1", "::ffff:192.168.0.1"] {
+            assert!(is_private_address(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(!is_private_address(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn charset_is_read_from_the_content_type() {
+        assert_eq!(charset_of("text/html; charset=Shift_JIS"), Some(encoding_rs::SHIFT_JIS));
+        assert_eq!(charset_of("text/html;charset=\"euc-jp\""), Some(encoding_rs::EUC_JP));
+        assert_eq!(charset_of("text/html"), None);
+    }
+
+    #[tokio::test]
+    async fn resolver_rejects_names_resolving_to_loopback() {
+        let result = PublicAddressResolver.resolve(Name::from_str("localhost").unwrap()).await;
+        let error = result.err().expect("localhostは拒否される");
+        assert!(error.is::<BlockedAddress>());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::app_state::AppState;
+use crate::config::GeminiConfig;
+use crate::document::{fetch_document, key_profile, Block, Document};
+
+use rustls::server::{ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, DistinguishedName, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+// ========== 定数 ==========
+const MAX_REQUEST_LINE_BYTES: usize = 1026;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// 本文の行として出力すると行の種類が変わってしまう行頭
+const TEXT_LINE_PREFIXES: &[&str] = &["=>", "#", "*", ">", "```"];
+// 整形済みテキストのブロックを終わらせてしまう行頭
+const PREFORMATTED_LINE_PREFIXES: &[&str] = &["```"];
+
+// ========== クライアント証明書 ==========
+
+// Geminiのクライアント証明書は自己署名が前提のため、署名の検証のみ行い
+// 信頼チェーンは問わない（認可は指紋とAPIキーの対応で行う）
+struct AcceptAnyClientCert;
+
+impl ClientCertVerifier for AcceptAnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+// 証明書のSHA-256指紋（小文字16進数）を計算する
+pub fn certificate_fingerprint(cert: &Certificate) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.0);
+    digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ========== サーバー ==========
+
+pub async fn run_gemini_server(config: GeminiConfig, state: AppState) {
+    let tls_config = match load_tls_config(&config) {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            eprintln!("Geminiサーバーを起動できません: {}", e);
+            return;
+        }
+    };
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Geminiサーバーのポート{}を開けません: {}", config.port, e);
+            return;
+        }
+    };
+
+    println!("Rigil Gemini server running on port {}", config.port);
+
+    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Gemini接続の受け付けエラー: {}", e);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            match acceptor.accept(stream).await {
+                Ok(tls_stream) => handle_connection(tls_stream, state).await,
+                Err(e) => eprintln!("Gemini TLSハンドシェイクエラー: {}", e),
+            }
+        });
+    }
+}
+
+fn load_tls_config(config: &GeminiConfig) -> Result<ServerConfig, String> {
+    let cert_file = File::open(&config.cert_file)
+        .map_err(|e| format!("証明書ファイル '{}' を開けません: {}", config.cert_file, e))?;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|e| format!("証明書の読み込みに失敗しました: {}", e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    let key_file = File::open(&config.key_file)
+        .map_err(|e| format!("秘密鍵ファイル '{}' を開けません: {}", config.key_file, e))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(key_file))
+        .map_err(|e| format!("秘密鍵の読み込みに失敗しました: {}", e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| "秘密鍵が見つかりません".to_string())?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(Arc::new(AcceptAnyClientCert))
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("TLS設定エラー: {}", e))
+}
+
+async fn handle_connection(mut stream: tokio_rustls::server::TlsStream<tokio::net::TcpStream>, state: AppState) {
+    let request_line = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_line(&mut stream)).await {
+        Ok(Some(line)) => line,
+        _ => {
+            let _ = stream.write_all(b"59 Bad request\r\n").await;
+            let _ = stream.shutdown().await;
+            return;
+        }
+    };
+
+    let fingerprint = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(certificate_fingerprint);
+
+    let response = handle_gemini_request(&request_line, fingerprint, &state).await;
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+async fn read_request_line<S: AsyncReadExt + Unpin>(stream: &mut S) -> Option<String> {
+    let mut buffer = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while buffer.len() < MAX_REQUEST_LINE_BYTES {
+        if stream.read(&mut byte).await.ok()? == 0 {
+            return None;
+        }
+        buffer.push(byte[0]);
+        if buffer.ends_with(b"\r\n") {
+            buffer.truncate(buffer.len() - 2);
+            return String::from_utf8(buffer).ok();
+        }
+    }
+    None
+}
+
+// ========== リクエスト処理 ==========
+
+async fn handle_gemini_request(request_line: &str, fingerprint: Option<String>, state: &AppState) -> String {
+    let request_url = match Url::parse(request_line) {
+        Ok(url) if url.scheme() == "gemini" => url,
+        _ => return "59 不正なリクエストです\r\n".to_string(),
+    };
+
+    let target_url = match request_url.query() {
+        Some(query) if !query.is_empty() => urlencoding::decode(query)
+            .map(|s| s.into_owned())
+            .unwrap_or_else(|_| query.to_string()),
+        _ => return "10 軽量化するURLを入力してください\r\n".to_string(),
+    };
+
+    let fingerprint = match fingerprint {
+        Some(fingerprint) => fingerprint,
+        None => return "60 クライアント証明書が必要です\r\n".to_string(),
+    };
+
+    let api_key = match state.api_key_store.read().await.find_key_by_fingerprint(&fingerprint) {
+        Some(key) => key,
+        None => return format!("61 この証明書はAPIキーに登録されていません (指紋: {})\r\n", fingerprint),
+    };
+
+    match process_url_to_gemtext(&target_url, &api_key, state).await {
+        Ok(gemtext) => format!("20 text/gemini; charset=utf-8\r\n{}", gemtext),
+        Err(error_msg) => format!("43 {}\r\n", error_msg),
+    }
+}
+
+async fn process_url_to_gemtext(target_url: &str, api_key: &str, state: &AppState) -> Result<String, String> {
+    let profile = key_profile(api_key, &state.api_key_store).await;
+    let (document, original_size) = fetch_document(target_url, &profile, api_key, state).await?;
+    let gemtext = render_gemtext(&document);
+
+    // 使用量を記録
+    let mut store = state.api_key_store.write().await;
+    if let Err(e) = store.add_usage(api_key, original_size, gemtext.len() as u64) {
+        eprintln!("使用量記録エラー: {}", e);
+    }
+
+    Ok(gemtext)
+}
+
+// ========== Gemtext生成 ==========
+
+pub fn render_gemtext(document: &Document) -> String {
+    let mut gemtext = String::new();
+
+    if let Some(title) = &document.title {
+        gemtext.push_str(&format!("# {}\n\n", title));
+    }
+
+    for block in &document.blocks {
+        match block {
+            Block::Heading(level, text) => {
+                let marks = "#".repeat((*level).clamp(1, 3) as usize);
+                gemtext.push_str(&format!("\n{} {}\n", marks, text));
+            }
+            Block::Text(text) => gemtext.push_str(&format!("{}\n", escape_line_types(text, TEXT_LINE_PREFIXES))),
+            Block::ListItem(text) => gemtext.push_str(&format!("* {}\n", text)),
+            Block::Preformatted(text) => {
+                gemtext.push_str(&format!("```\n{}\n```\n", escape_line_types(text, PREFORMATTED_LINE_PREFIXES)))
+            }
+            Block::Link { url, text } if url.starts_with("http") => {
+                // プロキシ経由でリンクを辿れるようにする
+                gemtext.push_str(&format!("=> /?{} {}\n", urlencoding::encode(url), text));
+            }
+            Block::Link { url, text } => gemtext.push_str(&format!("=> {} {}\n", url, text)),
+        }
+    }
+
+    gemtext
+}
+
+// 行頭が行の種類を表す記号の場合、空白を前に置いて通常の行にする
+// （本文の "=> " や "# " がリンクや見出しに、整形済みテキスト中の ``` がブロックの終わりにならないように）
+fn escape_line_types(text: &str, prefixes: &[&str]) -> String {
+    text.lines()
+        .map(|line| {
+            if prefixes.iter().any(|prefix| line.starts_with(prefix)) {
+                format!(" {}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_type_markers_in_text_are_escaped() {
+        let document = Document {
+            title: None,
+            blocks: vec![
+                Block::Text("=> ここはリンクではない".to_string()),
+                Block::Text("# 見出しではない".to_string()),
+                Block::Preformatted("```rust\nfn main() {}\n```".to_string()),
+                Block::Text("後ろの段落".to_string()),
+            ],
+        };
+        let gemtext = render_gemtext(&document);
+        assert_eq!(
+            gemtext,
+            " => ここはリンクではない\n # 見出しではない\n```\n ```rust\nfn main() {}\n ```\n```\n後ろの段落\n"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::document::normalize_text;
+use crate::persist::{JsonFile, Persisted};
+
+use chrono::{DateTime, Duration, Utc};
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// フィードのないページのリンクから生成するAtomフィード（リンクを最初に見つけた日時を記録する）
+
+// ========== 定数 ==========
+const FEED_LINKS_FILE: &str = "feed_links.json";
+// フィードに含める記事の最大数
+const MAX_FEED_ENTRIES: usize = 50;
+// ページごとに記録しておくリンクの最大数（古いものから忘れる）
+const MAX_REMEMBERED_LINKS: usize = 500;
+// 記録しておくページの最大数（最後に取得したのが古いものから忘れる）
+const MAX_REMEMBERED_PAGES: usize = 1000;
+// この日数取得されなかったページの記録は忘れる
+const PAGE_RETENTION_DAYS: i64 = 30;
+// リンクが変わらない場合、最終取得日時はこの間隔でだけ保存する
+const TOUCH_SAVE_INTERVAL_HOURS: i64 = 1;
+// 記事のリンクの署名に含める文字列（コールバックの署名等と区別する）
+const ENTRY_SIGNATURE_CONTEXT: &str = "rigil-feed-entry:";
+// 記事のリンクに付けるAPIキーの識別子の長さ（SHA-256の16進数の先頭）
+const KEY_ID_LEN: usize = 16;
+
+// ========== データ構造 ==========
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeenLink {
+    pub title: String,
+    pub first_seen: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FeedPage {
+    last_fetched: String,
+    links: HashMap<String, SeenLink>,
+}
+
+// フィードの記事（ページ上の順序を保ったまま、新しく見つけたものから並べる）
+pub struct GeneratedEntry {
+    pub url: String,
+    pub title: String,
+    pub first_seen: DateTime<Utc>,
+}
+
+// ========== リンク記録ストア ==========
+pub struct FeedLinkStore {
+    // ページURL -> 記録
+    pages: HashMap<String, FeedPage>,
+    file: JsonFile,
+}
+
+impl FeedLinkStore {
+    pub fn new() -> Self {
+        Self {
+            pages: HashMap::new(),
+            file: JsonFile::new(FEED_LINKS_FILE),
+        }
+    }
+
+    pub fn load_from_file() -> Self {
+        let mut store = Self::new();
+        if let Some(pages) = store.file.load() {
+            store.pages = pages;
+        }
+        store
+    }
+
+    // ページ上のリンクを記録し、フィードに含める記事を返す（記録が変わった場合だけ保存する）
+    pub fn record_links(&mut self, page_url: &str, links: &[(String, String)]) -> Vec<GeneratedEntry> {
+        let now = Utc::now();
+        let mut changed = self.forget_stale_pages(now);
+
+        let page = self.pages.entry(page_url.to_string()).or_insert_with(|| {
+            changed = true;
+            FeedPage {
+                last_fetched: now.to_rfc3339(),
+                links: HashMap::new(),
+            }
+        });
+        let last_fetched = parse_time(&page.last_fetched).unwrap_or(now);
+        if now - last_fetched >= Duration::hours(TOUCH_SAVE_INTERVAL_HOURS) {
+            page.last_fetched = now.to_rfc3339();
+            changed = true;
+        }
+
+        let seen = &mut page.links;
+        for (url, title) in links {
+            match seen.get_mut(url) {
+                Some(link) if link.title == *title => {}
+                Some(link) => {
+                    link.title = title.clone();
+                    changed = true;
+                }
+                None => {
+                    seen.insert(
+                        url.clone(),
+                        SeenLink {
+                            title: title.clone(),
+                            first_seen: now.to_rfc3339(),
+                        },
+                    );
+                    changed = true;
+                }
+            }
+        }
+        changed |= forget_oldest_links(seen, links);
+
+        let mut entries: Vec<GeneratedEntry> = links
+            .iter()
+            .filter_map(|(url, _)| seen.get(url).map(|link| (url, link)))
+            .map(|(url, link)| GeneratedEntry {
+                url: url.clone(),
+                title: link.title.clone(),
+                first_seen: parse_time(&link.first_seen).unwrap_or(now),
+            })
+            .collect();
+        // 同時に見つけたリンクはページ上の順序のまま（安定ソート）
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.first_seen));
+        entries.truncate(MAX_FEED_ENTRIES);
+
+        if changed {
+            self.file.mark_dirty();
+        }
+        entries
+    }
+
+    // 長く取得されていないページと、上限を超えた古いページを忘れる（忘れたページがあればtrue）
+    fn forget_stale_pages(&mut self, now: DateTime<Utc>) -> bool {
+        let before = self.pages.len();
+        let cutoff = now - Duration::days(PAGE_RETENTION_DAYS);
+        self.pages
+            .retain(|_, page| parse_time(&page.last_fetched).map(|fetched| fetched >= cutoff).unwrap_or(false));
+
+        if self.pages.len() >= MAX_REMEMBERED_PAGES {
+            let mut pages: Vec<(String, String)> = self
+                .pages
+                .iter()
+                .map(|(url, page)| (url.clone(), page.last_fetched.clone()))
+                .collect();
+            pages.sort_by(|a, b| a.1.cmp(&b.1));
+            // 新しいページを記録する余地を残す
+            let excess = self.pages.len() + 1 - MAX_REMEMBERED_PAGES;
+            for (url, _) in pages.into_iter().take(excess) {
+                self.pages.remove(&url);
+            }
+        }
+        self.pages.len() != before
+    }
+}
+
+impl Persisted for FeedLinkStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.pages).map_err(|e| e.to_string())
+    }
+}
+
+// 上限を超えた場合、ページに残っていないリンクを古いものから忘れる
+fn forget_oldest_links(seen: &mut HashMap<String, SeenLink>, current_links: &[(String, String)]) -> bool {
+    if seen.len() <= MAX_REMEMBERED_LINKS {
+        return false;
+    }
+    let mut removable: Vec<(String, String)> = seen
+        .iter()
+        .filter(|(url, _)| !current_links.iter().any(|(current, _)| current == *url))
+        .map(|(url, link)| (url.clone(), link.first_seen.clone()))
+        .collect();
+    removable.sort_by(|a, b| a.1.cmp(&b.1));
+    let excess = seen.len() - MAX_REMEMBERED_LINKS;
+    let mut forgotten = false;
+    for (url, _) in removable.into_iter().take(excess) {
+        forgotten |= seen.remove(&url).is_some();
+    }
+    forgotten
+}
+
+// <title>の内容（フィードのタイトルに使う）
+pub fn page_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let content_start = start + lower[start..].find('>')? + 1;
+    let content_end = content_start + lower[content_start..].find("</title")?;
+    let title = normalize_text(&html[content_start..content_end]);
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|dt| dt.with_timezone(&Utc))
+}
+
+// ========== 記事のリンク ==========
+// フィードリーダーに渡るURLにAPIキーを含めないため、記事は/feed/openを通して開く
+// （APIキーの識別子と、APIキーを鍵としたURLの署名を付ける）
+
+pub fn key_id(api_key: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, api_key.as_bytes());
+    let hex: String = digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    hex[..KEY_ID_LEN].to_string()
+}
+
+fn entry_signature(api_key: &str, url: &str) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, api_key.as_bytes());
+    let tag = ring::hmac::sign(&key, format!("{}{}", ENTRY_SIGNATURE_CONTEXT, url).as_bytes());
+    tag.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// options_queryは軽量化オプションのクエリ文字列（先頭の&を含む）
+pub fn entry_link(api_key: &str, url: &str, options_query: &str) -> String {
+    format!(
+        "/feed/open?url={}&key={}&sig={}{}",
+        urlencoding::encode(url),
+        key_id(api_key),
+        entry_signature(api_key, url),
+        options_query
+    )
+}
+
+// /feed/openのリンクがこのAPIキーで作られたものか
+pub fn verify_entry(api_key: &str, id: &str, url: &str, signature: &str) -> bool {
+    if key_id(api_key) != id {
+        return false;
+    }
+    let tag = match decode_hex(signature) {
+        Some(tag) => tag,
+        None => return false,
+    };
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, api_key.as_bytes());
+    ring::hmac::verify(&key, format!("{}{}", ENTRY_SIGNATURE_CONTEXT, url).as_bytes(), &tag).is_ok()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+// ========== Atomの出力 ==========
+
+// entry_linkは記事のURLからフィードに載せるリンクを作る
+pub fn render_atom(page_url: &str, title: &str, entries: &[GeneratedEntry], entry_link: impl Fn(&str) -> String) -> String {
+    let updated = entries
+        .iter()
+        .map(|entry| entry.first_seen)
+        .max()
+        .unwrap_or_else(Utc::now);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("  <id>{}</id>\n", escape(page_url)));
+    xml.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    xml.push_str(&format!("  <link rel=\"alternate\" href=\"{}\"/>\n", escape(page_url)));
+    xml.push_str(&format!("  <updated>{}</updated>\n", updated.to_rfc3339()));
+    // Atomではフィードに著者が必要なため、ページのホスト名を使う
+    let author = url::Url::parse(page_url).ok().and_then(|u| u.host_str().map(|h| h.to_string())).unwrap_or_default();
+    xml.push_str(&format!("  <author><name>{}</name></author>\n", escape(&author)));
+    xml.push_str("  <generator>Rigil Proxy</generator>\n");
+
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}</id>\n", escape(&entry.url)));
+        xml.push_str(&format!("    <title>{}</title>\n", escape(&entry.title)));
+        // 相対URLはフィード自体のURLを基準に解決される
+        xml.push_str(&format!("    <link rel=\"alternate\" href=\"{}\"/>\n", escape(&entry_link(&entry.url))));
+        xml.push_str(&format!("    <updated>{}</updated>\n", entry.first_seen.to_rfc3339()));
+        xml.push_str(&format!("    <summary>{}</summary>\n", escape(&entry.url)));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+// ========== 型エイリアス ==========
+pub type SharedFeedLinkStore = Arc<RwLock<FeedLinkStore>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::flush;
+
+    #[tokio::test]
+    async fn unchanged_links_are_not_saved_again() {
+        let path = std::env::temp_dir().join(format!("rigil-feed-links-{}.json", rand::random::<u64>()));
+        let store = RwLock::new(FeedLinkStore {
+            pages: HashMap::new(),
+            file: JsonFile::new(&path),
+        });
+        let links = vec![("https://example.com/a".to_string(), "A".to_string())];
+
+        store.write().await.record_links("https://example.com/", &links);
+        flush(&store).await;
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+
+        let entries = store.write().await.record_links("https://example.com/", &links);
+        flush(&store).await;
+        assert!(!path.exists());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].url, "https://example.com/a");
+    }
+
+    #[test]
+    fn entry_links_are_signed_without_the_key() {
+        let link = entry_link("secret-key", "https://example.com/a?b=1", "&images=alt");
+        assert!(!link.contains("secret-key"));
+        assert!(link.starts_with("/feed/open?url=https%3A%2F%2Fexample.com%2Fa%3Fb%3D1&key="));
+        assert!(link.ends_with("&images=alt"));
+
+        let signature = entry_signature("secret-key", "https://example.com/a?b=1");
+        assert!(verify_entry("secret-key", &key_id("secret-key"), "https://example.com/a?b=1", &signature));
+        assert!(!verify_entry("secret-key", &key_id("secret-key"), "https://example.com/other", &signature));
+        assert!(!verify_entry("other-key", &key_id("secret-key"), "https://example.com/a?b=1", &signature));
+    }
+}
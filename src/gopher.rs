@@ -0,0 +1,295 @@
+use crate::app_state::AppState;
+use crate::config::GopherConfig;
+use crate::document::{fetch_document, key_profile, Block, Document};
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// ========== 定数 ==========
+const WRAP_COLUMNS: usize = 70;
+const MAX_SELECTOR_BYTES: u64 = 2048;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// セレクタの形式：
+//   /<APIキー>                   ルートメニュー（URL入力）
+//   /<APIキー>/7 [TAB] <URL>     入力されたURLのメニュー
+//   /<APIキー>/1/<エンコード済みURL>  ページのメニュー（本文とリンク一覧）
+//   /<APIキー>/0/<エンコード済みURL>  ページ本文（テキスト）
+
+// ========== サーバー ==========
+
+pub async fn run_gopher_server(config: GopherConfig, state: AppState) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Gopherサーバーのポート{}を開けません: {}", config.port, e);
+            return;
+        }
+    };
+
+    println!("Rigil Gopher server running on port {}", config.port);
+
+    let config = Arc::new(config);
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Gopher接続の受け付けエラー: {}", e);
+                continue;
+            }
+        };
+
+        let config = config.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            handle_connection(stream, &config, &state).await;
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, config: &GopherConfig, state: &AppState) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    let read = tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        (&mut reader).take(MAX_SELECTOR_BYTES).read_line(&mut line),
+    ).await;
+    if !matches!(read, Ok(Ok(n)) if n > 0) {
+        return;
+    }
+
+    let request = line.trim_end_matches(['\r', '\n']);
+    let response = handle_gopher_request(request, config, state).await;
+
+    let mut stream = reader.into_inner();
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+// ========== リクエスト処理 ==========
+
+async fn handle_gopher_request(request: &str, config: &GopherConfig, state: &AppState) -> String {
+    let (selector, search) = match request.split_once('\t') {
+        Some((selector, search)) => (selector, Some(search)),
+        None => (request, None),
+    };
+
+    let mut parts = selector.trim_start_matches('/').splitn(3, '/');
+    let api_key = parts.next().unwrap_or("");
+    let item_type = parts.next();
+    let encoded_url = parts.next();
+
+    if api_key.is_empty() || !state.api_key_store.read().await.validate_key(api_key) {
+        return error_menu("有効なAPIキーが必要です（セレクタ /<APIキー> でアクセスしてください）", config);
+    }
+
+    let target_url = match (item_type, encoded_url, search) {
+        (None, _, _) => return root_menu(api_key, config),
+        (Some("7"), _, Some(url)) => url.trim().to_string(),
+        (Some("0" | "1"), Some(url), _) => urlencoding::decode(url)
+            .map(|s| s.into_owned())
+            .unwrap_or_else(|_| url.to_string()),
+        _ => return error_menu("不正なセレクタです", config),
+    };
+
+    let profile = key_profile(api_key, &state.api_key_store).await;
+    let (document, original_size) = match fetch_document(&target_url, &profile, api_key, state).await {
+        Ok(result) => result,
+        Err(error_msg) => return error_menu(&error_msg, config),
+    };
+
+    let response = if item_type == Some("0") {
+        render_text_item(&document)
+    } else {
+        render_page_menu(&document, &target_url, api_key, config)
+    };
+
+    // 使用量を記録
+    let mut store = state.api_key_store.write().await;
+    if let Err(e) = store.add_usage(api_key, original_size, response.len() as u64) {
+        eprintln!("使用量記録エラー: {}", e);
+    }
+
+    response
+}
+
+// ========== メニュー生成 ==========
+
+fn menu_line(item_type: char, display: &str, selector: &str, config: &GopherConfig) -> String {
+    format!("{}{}\t{}\t{}\t{}\r\n", item_type, sanitize(display), selector, config.host, config.port)
+}
+
+fn info_line(text: &str, config: &GopherConfig) -> String {
+    menu_line('i', text, "", config)
+}
+
+fn root_menu(api_key: &str, config: &GopherConfig) -> String {
+    let mut menu = String::new();
+    menu.push_str(&info_line("Rigil Proxy - Gopherフロントエンド", config));
+    menu.push_str(&info_line("", config));
+    menu.push_str(&menu_line('7', "URLを入力して軽量化", &format!("/{}/7", api_key), config));
+    menu.push_str(".\r\n");
+    menu
+}
+
+fn error_menu(message: &str, config: &GopherConfig) -> String {
+    format!("{}.\r\n", menu_line('3', message, "", config))
+}
+
+fn render_page_menu(document: &Document, target_url: &str, api_key: &str, config: &GopherConfig) -> String {
+    let encoded_url = urlencoding::encode(target_url);
+    let mut menu = String::new();
+
+    let title = document.title.as_deref().unwrap_or(target_url);
+    for line in wrap_text(title, WRAP_COLUMNS) {
+        menu.push_str(&info_line(&line, config));
+    }
+    menu.push_str(&info_line("", config));
+    menu.push_str(&menu_line('0', "本文を読む", &format!("/{}/0/{}", api_key, encoded_url), config));
+
+    let html_url = format!(
+        "{}/proxy?url={}&api_key={}",
+        config.http_base_url.trim_end_matches('/'),
+        encoded_url,
+        urlencoding::encode(api_key)
+    );
+    menu.push_str(&menu_line('h', "HTML版を開く", &format!("URL:{}", html_url), config));
+
+    let links: Vec<(&String, &String)> = document
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Link { url, text } => Some((url, text)),
+            _ => None,
+        })
+        .collect();
+
+    if !links.is_empty() {
+        menu.push_str(&info_line("", config));
+        menu.push_str(&info_line("リンク:", config));
+    }
+
+    for (number, (url, text)) in links.iter().enumerate() {
+        let display = format!("[{}] {}", number + 1, text);
+        if url.starts_with("http") {
+            // プロキシ経由で辿れるようにする
+            let selector = format!("/{}/1/{}", api_key, urlencoding::encode(url));
+            menu.push_str(&menu_line('1', &display, &selector, config));
+        } else {
+            menu.push_str(&menu_line('h', &display, &format!("URL:{}", url), config));
+        }
+    }
+
+    menu.push_str(".\r\n");
+    menu
+}
+
+// ========== テキスト生成 ==========
+
+fn render_text_item(document: &Document) -> String {
+    let mut lines: Vec<String> = Vec::new();
+
+    if let Some(title) = &document.title {
+        lines.extend(wrap_text(title, WRAP_COLUMNS));
+        lines.push("=".repeat(WRAP_COLUMNS));
+        lines.push(String::new());
+    }
+
+    let mut link_number = 0;
+    for block in &document.blocks {
+        match block {
+            Block::Heading(_, text) => {
+                lines.push(String::new());
+                lines.extend(wrap_text(text, WRAP_COLUMNS));
+                lines.push("-".repeat(WRAP_COLUMNS.min(display_width(text))));
+            }
+            Block::Text(text) => lines.extend(wrap_text(text, WRAP_COLUMNS)),
+            Block::ListItem(text) => {
+                for (index, line) in wrap_text(text, WRAP_COLUMNS - 2).into_iter().enumerate() {
+                    let prefix = if index == 0 { "* " } else { "  " };
+                    lines.push(format!("{}{}", prefix, line));
+                }
+            }
+            Block::Preformatted(text) => lines.extend(text.lines().map(|l| l.to_string())),
+            Block::Link { text, .. } => {
+                // メニューのリンク番号と対応させる
+                link_number += 1;
+                lines.extend(wrap_text(&format!("[{}] {}", link_number, text), WRAP_COLUMNS));
+            }
+        }
+    }
+
+    let mut text = String::new();
+    for line in lines {
+        // 単独の"."は終端と解釈されるため先頭のドットを重ねる
+        if line.starts_with('.') {
+            text.push('.');
+        }
+        text.push_str(&line);
+        text.push_str("\r\n");
+    }
+    text.push_str(".\r\n");
+    text
+}
+
+// ========== ヘルパー関数 ==========
+
+// メニュー項目の区切り文字を取り除く
+fn sanitize(text: &str) -> String {
+    text.chars().filter(|c| !matches!(c, '\t' | '\r' | '\n')).collect()
+}
+
+// 全角文字は2桁として数える
+fn char_width(ch: char) -> usize {
+    if (ch as u32) < 0x1100 {
+        1
+    } else {
+        2
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+// 指定桁数で折り返す（空白があれば空白で、なければ文字単位で折り返す）
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut current_width = 0;
+
+    for ch in text.chars() {
+        let w = char_width(ch);
+        if current_width + w > width {
+            match current.rfind(' ') {
+                Some(pos) if pos > 0 => {
+                    let rest = current[pos + 1..].to_string();
+                    current.truncate(pos);
+                    lines.push(current);
+                    current_width = display_width(&rest);
+                    current = rest;
+                }
+                _ => {
+                    lines.push(current);
+                    current = String::new();
+                    current_width = 0;
+                }
+            }
+        }
+        if current.is_empty() && ch == ' ' {
+            continue;
+        }
+        current.push(ch);
+        current_width += w;
+    }
+
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
@@ -1,8 +1,34 @@
+use crate::api_error::{current_request_id, generate_request_id, to_legacy_json, uses_legacy_envelope, with_request_id, ApiError, ErrorCode, ErrorResponse};
 use crate::api_key::{SharedApiKeyStore, ApiKeyError};
-use crate::api_types::{ApiResponse, UsageResponse};
-use crate::html_parser::{get_base_url, get_html, normalize_url, parse_html_to_text};
+use crate::app_state::AppState;
+use crate::api_types::{
+    AdminLoginRequest, ApiBatchRequest, ApiBookmarkRequest, ApiEpubRequest, ApiHistorySettingsRequest, ApiJobRequest, ApiKeySettingsRequest, ApiProcessOptions, ApiProcessRequest, ApiResponse,
+    ApiSaveRequest, ApiWatchRequest, BatchItemResult, BatchResponse, BookmarkListResponse, BookmarkResponse, CookieListResponse, CreateKeyRequest, FieldError, FingerprintRequest,
+    HistoryResponse, JobResponse, SavedPageContentResponse, SavedPageListResponse, SavedPageResponse, SearchResponse, SearchResultItem, SimpleResponse, SnapshotResponse, StatisticsResponse,
+    UsageResponse, WatchDiffResponse, WatchListResponse, WatchResponse,
+};
+use crate::bookmarks::{folders, generate_bookmark_id, Bookmark};
+use crate::config::Config;
+use crate::content::{self, ContentKind};
+use crate::fetcher::{get_bytes, get_page, post_form, FetchContext, FetchError, FetchedPage, PageBody};
+use crate::generated_feed::{entry_link, page_title, render_atom, verify_entry};
+use crate::history::{HistoryEntry, DEFAULT_HISTORY_ENTRIES};
+use crate::image_proxy::make_thumbnail;
+use crate::jobs::JobTask;
+use crate::html_parser::{add_save_form, extract_article_links, get_base_url, normalize_url, render_lightweight_page};
+use crate::document::{page_document, render_plain_text, Document};
+use crate::epub::{build_epub, image_urls, Chapter};
+use crate::process_options::{OutputFormat, ProcessOptions};
+use crate::persist::run_blocking;
+use crate::profile::ProcessingProfile;
+use crate::saved::{generate_saved_page_id, render_saved_list, render_saved_page, SavedPage};
+use crate::search::{render_search_page, SearchHit, DEFAULT_SEARCH_RESULTS, MAX_SEARCH_RESULTS};
+use crate::upstream::{available_user_agents, find_user_agent};
+use crate::watch::{check_watch, diff_snapshots, render_diff_html, BlockChange, NewWatch, Snapshot, Watch};
 
 use hyper::{Body, Request, Response, StatusCode};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::convert::Infallible;
 use serde_json;
@@ -16,6 +42,7 @@ pub fn create_html_response(body: String) -> Response<Body> {
 }
 
 pub fn create_json_response(body: String, status: StatusCode) -> Response<Body> {
+    let body = if uses_legacy_envelope() { to_legacy_json(&body) } else { body };
     let mut response = Response::new(Body::from(body));
     *response.status_mut() = status;
     response.headers_mut().insert("content-type", "application/json; charset=utf-8".parse().unwrap());
@@ -29,162 +56,1732 @@ pub fn parse_query_params(query: &str) -> HashMap<String, String> {
         .collect()
 }
 
-pub fn create_error_page(message: &str) -> String {
+// ========== エラーレスポンス ==========
+
+// JSONのエラーレスポンスと同じエラーコードとリクエストIDを表示する
+pub fn create_error_page(error: &ApiError) -> String {
+    let request_id = error
+        .request_id
+        .as_deref()
+        .map(|id| format!("（リクエストID: {}）", htmlescape::encode_minimal(id)))
+        .unwrap_or_default();
     format!(
         r#"<html><head><meta charset="UTF-8"></head><body>
             <h1>エラー</h1>
             <p>{}</p>
+            <p><small>{} {}</small></p>
             <p><a href="/">ホーム画面に戻る</a></p>
         </body></html>"#,
-        message
+        htmlescape::encode_minimal(&error.message),
+        error.code.name(),
+        request_id
     )
 }
 
-// ========== 認証ヘルパー ==========
+pub fn create_error_html_response(error: ApiError) -> Response<Body> {
+    let status = error.status_code();
+    let mut response = create_html_response(create_error_page(&error));
+    *response.status_mut() = status;
+    response
+}
+
+// {"success": false, "error": {...}}
+pub fn create_error_json_response(error: ApiError) -> Response<Body> {
+    let status = error.status_code();
+    create_json_response(serde_json::to_string(&ErrorResponse::new(error)).unwrap(), status)
+}
+
+fn success_json_response(message: String, status: StatusCode) -> Response<Body> {
+    let response = SimpleResponse { success: true, message };
+    create_json_response(serde_json::to_string(&response).unwrap(), status)
+}
+
+// ========== 認証ヘルパー ==========
+
+async fn validate_api_key(params: &HashMap<String, String>, api_key_store: &SharedApiKeyStore) -> Option<String> {
+    if let Some(api_key) = params.get("api_key") {
+        let store = api_key_store.read().await;
+        if store.validate_key(api_key) {
+            return Some(api_key.clone());
+        }
+    }
+    None
+}
+
+async fn validate_admin_key(params: &HashMap<String, String>, api_key_store: &SharedApiKeyStore) -> bool {
+    if let Some(admin_key) = params.get("admin_key") {
+        let store = api_key_store.read().await;
+        return store.validate_admin_key(admin_key);
+    }
+    false
+}
+
+fn invalid_api_key_error() -> ApiError {
+    ApiError::new(ErrorCode::InvalidApiKey, "有効なAPIキーが必要です")
+}
+
+fn admin_required_error() -> ApiError {
+    ApiError::new(ErrorCode::AdminRequired, "管理者権限が必要です")
+}
+
+// ========== プロキシ機能 ==========
+
+pub async fn handle_proxy_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+
+    let target_url = match params.get("url") {
+        Some(url) => url,
+        None => return Ok(create_error_html_response(missing_url_error())),
+    };
+
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+    let context = state.fetch_context(&api_key, &options, client_accept_language(&req)).await;
+    match process_url_and_record_usage(target_url, &api_key, &state, &options, &context).await {
+        Ok(processed_html) => Ok(create_html_response(processed_html)),
+        Err(e) => Ok(create_error_html_response(ApiError::from(&e))),
+    }
+}
+
+pub async fn handle_api_get_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+
+    let target_url = match params.get("url") {
+        Some(url) => url,
+        None => return Ok(api_error_json_response(missing_url_error(), None)),
+    };
+
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(api_error_json_response(invalid_api_key_error(), Some(target_url))),
+    };
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(api_error_json_response(error, Some(target_url))),
+    };
+    let context = state.fetch_context(&api_key, &options, client_accept_language(&req)).await;
+    let (status, response) = process_url_for_api(target_url, &api_key, &state, &options, &context).await;
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), status))
+}
+
+// JSONボディで軽量化オプションを指定するJSON API（APIキーはボディまたはX-API-Keyヘッダー）
+pub async fn handle_api_post_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+    let accept_language = client_accept_language(&req).map(|v| v.to_string());
+
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request: ApiProcessRequest = match parse_json_request(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(api_error_json_response(error, None)),
+    };
+
+    let mut params = api_option_params(&request.options);
+    if let Some(url) = request.url.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        params.insert("url".to_string(), url.to_string());
+    }
+    let target_url = params.get("url").map(|url| normalize_url(url));
+
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(api_error_json_response(invalid_api_key_error(), target_url.as_deref())),
+    };
+
+    // 不正なフィールドをまとめて返す
+    let mut field_errors = option_field_errors(&params);
+    if let Some(error) = url_field_error("url", target_url.as_deref(), true) {
+        field_errors.insert(0, error);
+    }
+    if !field_errors.is_empty() {
+        return Ok(api_error_json_response(ApiError::invalid_request(field_errors), target_url.as_deref()));
+    }
+    let target_url = target_url.unwrap_or_default();
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(api_error_json_response(error, Some(&target_url))),
+    };
+    let context = state.fetch_context(&api_key, &options, accept_language.as_deref()).await;
+    let (status, response) = process_url_for_api(&target_url, &api_key, &state, &options, &context).await;
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), status))
+}
+
+// ========== バッチ処理 ==========
+
+// 複数のURLを同時実行数を制限して処理する（使用量は1件ごとに記録する）
+// stream: trueの場合は完了した順にNDJSONで返し、それ以外はurlsの順に並べて返す
+pub async fn handle_batch_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+    let accept_language = client_accept_language(&req).map(|v| v.to_string());
+
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request: ApiBatchRequest = match parse_json_request(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(api_error_json_response(error, None)),
+    };
+
+    let mut params = api_option_params(&request.options);
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(api_error_json_response(invalid_api_key_error(), None)),
+    };
+
+    let config = &Config::global().batch;
+    let mut field_errors = option_field_errors(&params);
+    if request.urls.is_empty() {
+        field_errors.insert(0, field_error("urls", "1件以上のURLを指定してください"));
+    } else if request.urls.len() > config.max_urls {
+        field_errors.insert(0, field_error("urls", &format!("URLは{}件までです", config.max_urls)));
+    }
+    if !field_errors.is_empty() {
+        return Ok(api_error_json_response(ApiError::invalid_request(field_errors), None));
+    }
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(api_error_json_response(error, None)),
+    };
+
+    let mut results = run_batch(request.urls, api_key, options, accept_language, state);
+
+    if request.stream {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            while let Some(result) = results.recv().await {
+                let mut line = serde_json::to_string(&result).unwrap_or_default();
+                line.push('\n');
+                if sender.send_data(hyper::body::Bytes::from(line)).await.is_err() {
+                    // クライアントが切断した
+                    break;
+                }
+            }
+        });
+        let mut response = Response::new(body);
+        response.headers_mut().insert("content-type", "application/x-ndjson; charset=utf-8".parse().unwrap());
+        return Ok(response);
+    }
+
+    let mut items = Vec::new();
+    while let Some(result) = results.recv().await {
+        items.push(result);
+    }
+    items.sort_by_key(|item| item.index);
+    let response = BatchResponse {
+        success: items.iter().all(|item| item.response.success),
+        results: items,
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+// 各URLをタスクで処理し、完了した順に結果を送る（エラーにはバッチのリクエストIDを付ける）
+// 受信側が閉じられた（クライアントが切断した）場合、残りのタスクは取得せずに終わる
+fn run_batch(urls: Vec<String>, api_key: String, options: ProcessOptions, accept_language: Option<String>, state: AppState) -> tokio::sync::mpsc::Receiver<BatchItemResult> {
+    let (sender, receiver) = tokio::sync::mpsc::channel(urls.len().max(1));
+    let request_id = current_request_id().unwrap_or_else(generate_request_id);
+
+    for (index, url) in urls.into_iter().enumerate() {
+        let sender = sender.clone();
+        let api_key = api_key.clone();
+        let options = options.clone();
+        let accept_language = accept_language.clone();
+        let state = state.clone();
+
+        tokio::spawn(with_request_id(request_id.clone(), async move {
+            let _permit = tokio::select! {
+                permit = state.batch_permits.acquire() => match permit {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                },
+                _ = sender.closed() => return,
+            };
+            let process = async {
+                let context = state.fetch_context(&api_key, &options, accept_language.as_deref()).await;
+                process_url_for_api(&url, &api_key, &state, &options, &context).await
+            };
+            // 取得中に切断された場合は使用量を記録する前に中断する
+            let response = tokio::select! {
+                (_, response) = process => response,
+                _ = sender.closed() => return,
+            };
+            let _ = sender.send(BatchItemResult { index, url, response }).await;
+        }));
+    }
+
+    receiver
+}
+
+// ========== 非同期ジョブ ==========
+
+// ジョブを登録してすぐにジョブIDを返す（処理はバックグラウンドのワーカーが行う）
+pub async fn handle_create_job_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+    let accept_language = client_accept_language(&req).map(|v| v.to_string());
+
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request: ApiJobRequest = match parse_json_request(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(api_error_json_response(error, None)),
+    };
+
+    let mut params = api_option_params(&request.options);
+    let target_url = request.url.as_deref().map(str::trim).filter(|u| !u.is_empty()).map(normalize_url);
+    let callback_url = request.callback_url.as_deref().map(str::trim).filter(|u| !u.is_empty()).map(|u| u.to_string());
+
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(api_error_json_response(invalid_api_key_error(), target_url.as_deref())),
+    };
+
+    let mut field_errors = option_field_errors(&params);
+    if let Some(error) = url_field_error("callback_url", callback_url.as_deref(), false) {
+        field_errors.insert(0, error);
+    }
+    if let Some(error) = url_field_error("url", target_url.as_deref(), true) {
+        field_errors.insert(0, error);
+    }
+    if !field_errors.is_empty() {
+        return Ok(api_error_json_response(ApiError::invalid_request(field_errors), target_url.as_deref()));
+    }
+    let target_url = target_url.unwrap_or_default();
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(api_error_json_response(error, Some(&target_url))),
+    };
+
+    let job = {
+        let mut store = state.jobs.store.write().await;
+        let max_pending = Config::global().jobs.max_pending_per_key;
+        if store.pending_count_for(&api_key) >= max_pending {
+            let message = format!("待機中・処理中のジョブは1つのAPIキーにつき{}件までです", max_pending);
+            return Ok(api_error_json_response(ApiError::new(ErrorCode::QuotaExceeded, message), Some(&target_url)));
+        }
+        store.create(&api_key, &target_url, callback_url.clone())
+    };
+    let task = JobTask {
+        id: job.id.clone(),
+        api_key,
+        url: target_url,
+        options,
+        accept_language,
+        callback_url,
+    };
+    if let Err(e) = state.jobs.enqueue(task) {
+        // 処理されないジョブが待機中のまま上限に数えられないようにする
+        state.jobs.store.write().await.remove(&job.id);
+        return Ok(api_error_json_response(ApiError::new(ErrorCode::JobQueueUnavailable, e), None));
+    }
+
+    let response = JobResponse {
+        success: true,
+        job: job.info(),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::ACCEPTED))
+}
+
+// ジョブの状態と結果を返す（作成したAPIキーからのみ参照できる）
+pub async fn handle_get_job_request(req: Request<Body>, job_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let job = state.jobs.store.read().await.get(job_id, &api_key);
+    match job {
+        Some(job) => {
+            let response = JobResponse { success: true, job: job.info() };
+            Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+        }
+        None => Ok(create_error_json_response(ApiError::new(
+            ErrorCode::JobNotFound,
+            "ジョブが見つかりません（期限切れの可能性があります）",
+        ))),
+    }
+}
+
+// ========== ページ監視 ==========
+
+// 監視を登録し、最初のスナップショットをバックグラウンドで取得する
+pub async fn handle_create_watch_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request: ApiWatchRequest = match parse_json_request(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let trimmed = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(|v| v.to_string());
+    let (profile, user_agent, encoding) = (trimmed(&request.profile), trimmed(&request.ua), trimmed(&request.encoding));
+    let mut params: HashMap<String, String> = [("profile", &profile), ("ua", &user_agent), ("encoding", &encoding)]
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|v| (name.to_string(), v.clone())))
+        .collect();
+    let target_url = trimmed(&request.url).map(|url| normalize_url(&url));
+
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let config = &Config::global().watch;
+    let interval_minutes = request.interval_minutes.unwrap_or(config.default_interval_minutes);
+    let mut field_errors = option_field_errors(&params);
+    if interval_minutes < config.min_interval_minutes {
+        field_errors.insert(0, field_error("interval_minutes", &format!("{}分以上を指定してください", config.min_interval_minutes)));
+    }
+    if let Some(error) = url_field_error("url", target_url.as_deref(), true) {
+        field_errors.insert(0, error);
+    }
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+
+    let watch = {
+        let mut store = state.watches.write().await;
+        if store.count_for(&api_key) >= config.max_watches_per_key {
+            let message = format!("監視は1つのAPIキーにつき{}件まで登録できます", config.max_watches_per_key);
+            return Ok(create_error_json_response(ApiError::new(ErrorCode::QuotaExceeded, message)));
+        }
+        store.create(
+            &api_key,
+            NewWatch {
+                url: target_url.unwrap_or_default(),
+                interval_minutes,
+                profile,
+                user_agent,
+                encoding,
+            },
+        )
+    };
+
+    let check_state = state.clone();
+    let check_target = watch.clone();
+    tokio::spawn(async move { check_watch(&check_target, &check_state).await });
+
+    let response = WatchResponse { success: true, watch: watch.info() };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::CREATED))
+}
+
+pub async fn handle_list_watches_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let watches = state.watches.read().await.list(&api_key);
+    let response = WatchListResponse {
+        success: true,
+        watches: watches.iter().map(Watch::info).collect(),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+pub async fn handle_get_watch_request(req: Request<Body>, watch_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    match find_watch(&req, watch_id, &state).await {
+        Ok((watch, _)) => {
+            let response = WatchResponse { success: true, watch: watch.info() };
+            Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+        }
+        Err(error) => Ok(create_error_json_response(error)),
+    }
+}
+
+pub async fn handle_delete_watch_request(req: Request<Body>, watch_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    if state.watches.write().await.remove(watch_id, &api_key) {
+        Ok(success_json_response("監視を削除しました".to_string(), StatusCode::OK))
+    } else {
+        Ok(create_error_json_response(watch_not_found_error()))
+    }
+}
+
+pub async fn handle_get_snapshot_request(req: Request<Body>, watch_id: &str, snapshot_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let watch = match find_watch(&req, watch_id, &state).await {
+        Ok((watch, _)) => watch,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    match snapshot_id.parse().ok().and_then(|id| watch.snapshot(id)) {
+        Some(snapshot) => {
+            let response = SnapshotResponse {
+                success: true,
+                watch_id: watch.id.clone(),
+                snapshot: snapshot.info(),
+                blocks: snapshot.blocks.clone(),
+            };
+            Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+        }
+        None => Ok(create_error_json_response(snapshot_not_found_error())),
+    }
+}
+
+// from・toを省略すると最新のスナップショットとその1つ前を比較する
+pub async fn handle_watch_diff_request(req: Request<Body>, watch_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let (watch, params) = match find_watch(&req, watch_id, &state).await {
+        Ok(found) => found,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let (from, to) = match select_snapshots(&watch, &params) {
+        Ok(selected) => selected,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let blocks = diff_snapshots(from, to).await;
+    let changed = |change: BlockChange| blocks.iter().filter(|block| block.change == change).map(|block| block.text.clone()).collect();
+    let response = WatchDiffResponse {
+        success: true,
+        watch_id: watch.id.clone(),
+        url: watch.url.clone(),
+        from: from.map(Snapshot::info),
+        to: to.info(),
+        added: changed(BlockChange::Added),
+        removed: changed(BlockChange::Removed),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+// 差分のHTML表示（/watch/diff?id=...&api_key=...）
+pub async fn handle_watch_diff_page_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let watch_id = parse_query_params(query).get("id").cloned().unwrap_or_default();
+    let (watch, params) = match find_watch(&req, &watch_id, &state).await {
+        Ok(found) => found,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+    let (from, to) = match select_snapshots(&watch, &params) {
+        Ok(selected) => selected,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+
+    let blocks = diff_snapshots(from, to).await;
+    let api_key = urlencoding::encode(&watch.api_key);
+    let page_link = format!("/proxy?url={}&api_key={}", urlencoding::encode(&watch.url), api_key);
+    let html = render_diff_html(&watch, from, to, &blocks, &page_link, |from, to| {
+        let from_query = from.map(|id| format!("&from={}", id)).unwrap_or_default();
+        format!("/watch/diff?id={}&api_key={}{}&to={}", urlencoding::encode(&watch.id), api_key, from_query, to)
+    });
+    Ok(create_html_response(html))
+}
+
+// APIキー（クエリまたはX-API-Keyヘッダー）が登録した監視を探す
+async fn find_watch(req: &Request<Body>, watch_id: &str, state: &AppState) -> Result<(Watch, HashMap<String, String>), ApiError> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = validate_body_api_key(None, header_api_key(req), &mut params, state)
+        .await
+        .ok_or_else(invalid_api_key_error)?;
+    let watch = state.watches.read().await.get(watch_id, &api_key).ok_or_else(watch_not_found_error)?;
+    Ok((watch, params))
+}
+
+// 比較先（to、省略時は最新）と比較元（from、省略時は比較先の1つ前）を選ぶ
+fn select_snapshots<'a>(watch: &'a Watch, params: &HashMap<String, String>) -> Result<(Option<&'a Snapshot>, &'a Snapshot), ApiError> {
+    let find = |name: &str| -> Result<Option<&'a Snapshot>, ApiError> {
+        match params.get(name).filter(|v| !v.is_empty()) {
+            Some(value) => value.parse().ok().and_then(|id| watch.snapshot(id)).map(Some).ok_or_else(snapshot_not_found_error),
+            None => Ok(None),
+        }
+    };
+
+    let to = match find("to")?.or(watch.snapshots.last()) {
+        Some(to) => to,
+        None => return Err(ApiError::new(ErrorCode::SnapshotNotFound, "スナップショットがまだありません（最初の取得が完了していないか、失敗しています）")),
+    };
+    let from = match find("from")? {
+        Some(from) => Some(from),
+        None => watch.previous_snapshot(to.id),
+    };
+    Ok((from, to))
+}
+
+fn watch_not_found_error() -> ApiError {
+    ApiError::new(ErrorCode::WatchNotFound, "監視が見つかりません")
+}
+
+fn snapshot_not_found_error() -> ApiError {
+    ApiError::new(ErrorCode::SnapshotNotFound, "スナップショットが見つかりません（古いものは削除されます）")
+}
+
+// ========== 保存したページ ==========
+
+// POST /api/saved（ページを取得・軽量化して保存する）
+pub async fn handle_create_saved_page_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+    let accept_language = client_accept_language(&req).map(|v| v.to_string());
+
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request: ApiSaveRequest = match parse_json_request(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let mut params = api_option_params(&request.options);
+    let target_url = request.url.as_deref().map(str::trim).filter(|u| !u.is_empty()).map(normalize_url);
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let mut field_errors = option_field_errors(&params);
+    if let Some(error) = url_field_error("url", target_url.as_deref(), true) {
+        field_errors.insert(0, error);
+    }
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+    let target_url = target_url.unwrap_or_default();
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let context = state.fetch_context(&api_key, &options, accept_language.as_deref()).await;
+    match fetch_and_save_page(&target_url, &api_key, &state, &options, &context).await {
+        Ok(page) => {
+            let response = SavedPageResponse { success: true, page: page.info() };
+            Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::CREATED))
+        }
+        Err(error) => Ok(create_error_json_response(error)),
+    }
+}
+
+// 軽量化ページの保存ボタンの送信先（POST /save?view=...）。表示したページを保存し、保存したページへリダイレクトする
+pub async fn handle_save_form_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(_) => return Ok(create_error_html_response(body_read_error())),
+    };
+    for (name, value) in url::form_urlencoded::parse(&body).into_owned() {
+        if let Some(proxy_name) = name.strip_prefix("rigil_") {
+            params.entry(proxy_name.to_string()).or_insert(value);
+        }
+    }
+
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    let view_id = params.get("view").map(|id| id.as_str()).unwrap_or_default();
+    let viewed = state.viewed_pages.lock().await.get(view_id, &api_key);
+    let (page, options) = match viewed {
+        Some(viewed) => viewed,
+        None => {
+            let error = ApiError::new(ErrorCode::InvalidRequest, "表示したページの保存期限が過ぎました。ページを表示し直してから保存してください");
+            return Ok(create_error_html_response(error));
+        }
+    };
+    match save_fetched_page(&page, &api_key, &state, &options).await {
+        Ok(page) => {
+            let location = saved_page_link(&page.id, &api_key);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::SEE_OTHER;
+            response.headers_mut().insert(hyper::header::LOCATION, location.parse().unwrap());
+            Ok(response)
+        }
+        Err(error) => Ok(create_error_html_response(error)),
+    }
+}
+
+pub async fn handle_list_saved_pages_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let pages = state.saved_pages.read().await.list(&api_key);
+    let response = SavedPageListResponse {
+        success: true,
+        pages: pages.iter().map(SavedPage::info).collect(),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+// format=textの場合はプレーンテキスト、それ以外は軽量化したHTMLを返す
+pub async fn handle_get_saved_page_request(req: Request<Body>, page_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let (page, params) = match find_saved_page(&req, page_id, &state).await {
+        Ok(found) => found,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let (format, content) = match params.get("format").map(|f| f.as_str()) {
+        Some("text") => ("text", page.text.clone()),
+        Some("html") | None => ("html", page.content.clone()),
+        Some(other) => {
+            let error = ApiError::new(ErrorCode::InvalidOption, format!("不正な値です: {}（使用可能: html, text）", other));
+            return Ok(create_error_json_response(error));
+        }
+    };
+    let response = SavedPageContentResponse {
+        success: true,
+        page: page.info(),
+        format: format.to_string(),
+        content,
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+pub async fn handle_delete_saved_page_request(req: Request<Body>, page_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    if !state.saved_pages.write().await.remove(page_id, &api_key) {
+        return Ok(create_error_json_response(saved_page_not_found_error()));
+    }
+    if let Err(e) = state.search_index.remove_page(page_id).await {
+        eprintln!("検索インデックスの更新エラー: {}", e);
+    }
+    Ok(success_json_response("保存したページを削除しました".to_string(), StatusCode::OK))
+}
+
+// 保存したページの一覧（/saved?api_key=...）
+pub async fn handle_saved_list_page_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    let pages = state.saved_pages.read().await.list(&api_key);
+    // EPUBには新しいものから章の上限までを入れる
+    let epub_ids: Vec<&str> = pages.iter().take(Config::global().epub.max_chapters).map(|page| page.id.as_str()).collect();
+    let epub_link = format!("/epub?saved={}&api_key={}", epub_ids.join(","), urlencoding::encode(&api_key));
+    let body = render_saved_list(&pages, &epub_link, |id| saved_page_link(id, &api_key));
+    Ok(create_html_response(render_lightweight_page(&body)))
+}
+
+// 保存したページの表示（/saved/{id}?api_key=...）
+pub async fn handle_saved_page_view_request(req: Request<Body>, page_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let (page, params) = match find_saved_page(&req, page_id, &state).await {
+        Ok(found) => found,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+
+    let api_key = params.get("api_key").cloned().unwrap_or_default();
+    let original_link = format!("/proxy?url={}&api_key={}", urlencoding::encode(&page.url), urlencoding::encode(&api_key));
+    let list_link = format!("/saved?api_key={}", urlencoding::encode(&api_key));
+    Ok(create_html_response(render_saved_page(&page, &original_link, &list_link)))
+}
+
+// APIキー（クエリまたはX-API-Keyヘッダー）が保存したページを探す
+async fn find_saved_page(req: &Request<Body>, page_id: &str, state: &AppState) -> Result<(SavedPage, HashMap<String, String>), ApiError> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = validate_body_api_key(None, header_api_key(req), &mut params, state)
+        .await
+        .ok_or_else(invalid_api_key_error)?;
+    let page = state.saved_pages.read().await.get(page_id, &api_key).ok_or_else(saved_page_not_found_error)?;
+    Ok((page, params))
+}
+
+// ページを取得して保存する（POST /api/saved）
+async fn fetch_and_save_page(target_url: &str, api_key: &str, state: &AppState, options: &ProcessOptions, context: &FetchContext) -> Result<SavedPage, ApiError> {
+    let result = get_page(&normalize_url(target_url), context).await;
+    state.save_fetch_context(context).await;
+    let page = result.map_err(|e| ApiError::from(&e))?;
+
+    let saved = save_fetched_page(&page, api_key, state, options).await?;
+    record_usage(api_key, state, page.original_size(), saved.content.len() as u64).await;
+    Ok(saved)
+}
+
+// 軽量化したHTMLとプレーンテキストを保存する（上限の確認と追加は1つの書き込みロックで行う）
+async fn save_fetched_page(page: &FetchedPage, api_key: &str, state: &AppState, options: &ProcessOptions) -> Result<SavedPage, ApiError> {
+    let content = content::render_page(page, options);
+    let document = page_document(page, &options.profile);
+    let saved = SavedPage {
+        id: generate_saved_page_id(),
+        api_key: api_key.to_string(),
+        url: page.final_url.clone(),
+        title: fetched_page_title(page, &document),
+        saved_at: chrono::Utc::now().to_rfc3339(),
+        original_size_bytes: page.original_size(),
+        text: render_plain_text(&document),
+        content,
+    };
+
+    let max_pages = Config::global().saved.max_pages_per_key;
+    if !state.saved_pages.write().await.add(saved.clone(), max_pages) {
+        let message = format!("保存できるページは{}件までです。不要なページを削除してください", max_pages);
+        return Err(ApiError::new(ErrorCode::QuotaExceeded, message));
+    }
+    // 保存は完了しているため、インデックスの更新に失敗しても保存結果を返す（次回起動時に作り直す）
+    if let Err(e) = state.search_index.add_page(&saved).await {
+        eprintln!("検索インデックスの更新エラー: {}", e);
+    }
+    Ok(saved)
+}
+
+// ========== EPUB ==========
+
+// 1ページまたは保存したページをEPUBにする（POST /api/epub）
+pub async fn handle_epub_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+    let accept_language = client_accept_language(&req).map(|v| v.to_string());
+
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request: ApiEpubRequest = match parse_json_request(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let mut params = api_option_params(&request.options);
+    let target_url = request.url.as_deref().map(str::trim).filter(|u| !u.is_empty()).map(normalize_url);
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let mut field_errors = option_field_errors(&params);
+    field_errors.extend(epub_source_errors(target_url.as_deref(), &request.saved_ids));
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let context = state.fetch_context(&api_key, &options, accept_language.as_deref()).await;
+    match make_epub(target_url.as_deref(), &request.saved_ids, request.title, &api_key, &state, &options, &context).await {
+        Ok((title, epub)) => Ok(create_epub_response(&title, epub)),
+        Err(error) => Ok(create_error_json_response(error)),
+    }
+}
+
+// ブラウザからのダウンロード（/epub?url=...&api_key=... または /epub?saved=ID,ID&api_key=...）
+pub async fn handle_epub_download_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    let target_url = params.get("url").map(|u| u.trim()).filter(|u| !u.is_empty()).map(normalize_url);
+    let saved_ids: Vec<String> = params
+        .get("saved")
+        .map(|ids| ids.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default();
+    let field_errors = epub_source_errors(target_url.as_deref(), &saved_ids);
+    if !field_errors.is_empty() {
+        return Ok(create_error_html_response(ApiError::invalid_request(field_errors)));
+    }
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+    let context = state.fetch_context(&api_key, &options, client_accept_language(&req)).await;
+    let title = params.get("title").cloned();
+    match make_epub(target_url.as_deref(), &saved_ids, title, &api_key, &state, &options, &context).await {
+        Ok((title, epub)) => Ok(create_epub_response(&title, epub)),
+        Err(error) => Ok(create_error_html_response(error)),
+    }
+}
+
+// urlとsaved_idsはどちらか一方のみ指定できる
+fn epub_source_errors(target_url: Option<&str>, saved_ids: &[String]) -> Vec<FieldError> {
+    let max_chapters = Config::global().epub.max_chapters;
+    match (target_url, saved_ids.len()) {
+        (None, 0) => vec![field_error("url", "urlまたはsaved_idsを指定してください")],
+        (Some(_), count) if count > 0 => vec![field_error("saved_ids", "urlとsaved_idsはどちらか一方を指定してください")],
+        (Some(url), _) => url_field_error("url", Some(url), true).into_iter().collect(),
+        (None, count) if count > max_chapters => vec![field_error("saved_ids", &format!("{}件まで指定できます", max_chapters))],
+        (None, _) => Vec::new(),
+    }
+}
+
+// 章を集め、縮小画像を取得してEPUBを作る。(本のタイトル, EPUB) を返す
+async fn make_epub(target_url: Option<&str>, saved_ids: &[String], title: Option<String>, api_key: &str, state: &AppState, options: &ProcessOptions, context: &FetchContext) -> Result<(String, Vec<u8>), ApiError> {
+    let chapters = match target_url {
+        Some(target_url) => {
+            let result = get_page(target_url, context).await;
+            state.save_fetch_context(context).await;
+            let page = result.map_err(|e| ApiError::from(&e))?;
+            let content = content::render_page(&page, options);
+            record_usage(api_key, state, page.original_size(), content.len() as u64).await;
+            vec![Chapter {
+                title: fetched_page_title(&page, &page_document(&page, &options.profile)),
+                url: page.final_url.clone(),
+                content,
+            }]
+        }
+        None => {
+            let store = state.saved_pages.read().await;
+            saved_ids
+                .iter()
+                .map(|id| {
+                    let page = store.get(id, api_key).ok_or_else(saved_page_not_found_error)?;
+                    Ok(Chapter { title: page.title, url: page.url, content: page.content })
+                })
+                .collect::<Result<Vec<Chapter>, ApiError>>()?
+        }
+    };
+
+    let title = match title.map(|t| t.trim().to_string()).filter(|t| !t.is_empty()) {
+        Some(title) => title,
+        None if chapters.len() == 1 => chapters[0].title.clone(),
+        None => format!("保存したページ {}", chrono::Local::now().format("%Y-%m-%d")),
+    };
+    let images = fetch_epub_images(&chapters, api_key, state, context).await;
+    let book_title = title.clone();
+    let epub = run_blocking(move || build_epub(&book_title, &chapters, &images))
+        .await
+        .and_then(|result| result)
+        .map_err(|e| ApiError::new(ErrorCode::InternalError, format!("EPUBの作成エラー: {}", e)))?;
+    Ok((title, epub))
+}
+
+// 章の縮小画像を取得する（取得や縮小に失敗した画像は代替テキストのままにする）
+// 同時に取得するのはimage_concurrency件まで（クライアントが切断した場合、残りのタスクは中止される）
+async fn fetch_epub_images(chapters: &[Chapter], api_key: &str, state: &AppState, context: &FetchContext) -> HashMap<String, Vec<u8>> {
+    let config = Config::global();
+    let mut urls: Vec<String> = Vec::new();
+    for url in chapters.iter().flat_map(|chapter| image_urls(&chapter.content)) {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls.truncate(config.epub.max_images);
+
+    let concurrency = config.epub.image_concurrency.max(1);
+    let mut pending = urls.into_iter();
+    let mut tasks = tokio::task::JoinSet::new();
+    let mut images = HashMap::new();
+    loop {
+        while tasks.len() < concurrency {
+            let url = match pending.next() {
+                Some(url) => url,
+                None => break,
+            };
+            let context = context.clone();
+            tasks.spawn(async move {
+                let result = fetch_epub_image(&url, &context).await;
+                (url, result)
+            });
+        }
+        let (url, result) = match tasks.join_next().await {
+            Some(Ok(finished)) => finished,
+            Some(Err(e)) => {
+                eprintln!("EPUBの画像取得エラー: {}", e);
+                continue;
+            }
+            None => break,
+        };
+        match result {
+            Ok((source_size, thumbnail)) => {
+                if let Err(e) = state.api_key_store.write().await.add_image_usage(api_key, source_size, thumbnail.len() as u64) {
+                    eprintln!("画像使用量記録エラー: {}", e);
+                }
+                images.insert(url, thumbnail);
+            }
+            Err(e) => eprintln!("EPUBの画像エラー: {}: {}", url, e),
+        }
+    }
+    state.save_fetch_context(context).await;
+    images
+}
+
+// 画像を取得して縮小する。(元のサイズ, 縮小画像) を返す
+async fn fetch_epub_image(url: &str, context: &FetchContext) -> Result<(u64, Vec<u8>), String> {
+    let config = Config::global();
+    let (source, _content_type) = get_bytes(url, config.images.max_source_bytes, context).await.map_err(|e| e.to_string())?;
+    let source_size = source.len() as u64;
+    let thumbnail = make_thumbnail(source, &config.images).await?;
+    Ok((source_size, thumbnail))
+}
+
+// 日本語のタイトルはfilename*で渡す（古いクライアント向けにASCIIの名前も付ける）
+fn create_epub_response(title: &str, epub: Vec<u8>) -> Response<Body> {
+    let disposition = format!("attachment; filename=\"rigil.epub\"; filename*=UTF-8''{}.epub", urlencoding::encode(title));
+    let mut response = Response::new(Body::from(epub));
+    response.headers_mut().insert("content-type", "application/epub+zip".parse().unwrap());
+    if let Ok(value) = disposition.parse() {
+        response.headers_mut().insert(hyper::header::CONTENT_DISPOSITION, value);
+    }
+    response
+}
+
+// ========== 全文検索 ==========
+
+// 保存したページの全文検索（/api/search?q=...&api_key=...&limit=...）
+pub async fn handle_search_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let search_query = params.get("q").map(|q| q.trim().to_string()).unwrap_or_default();
+    let mut field_errors = Vec::new();
+    if search_query.is_empty() {
+        field_errors.push(field_error("q", "検索語を指定してください"));
+    }
+    let limit = match limit_param(&params, DEFAULT_SEARCH_RESULTS, MAX_SEARCH_RESULTS) {
+        Ok(limit) => limit,
+        Err(error) => {
+            field_errors.push(error);
+            0
+        }
+    };
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+
+    let hits = match search_saved_pages(&api_key, &search_query, limit, &state).await {
+        Ok(hits) => hits,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let response = SearchResponse {
+        success: true,
+        query: search_query,
+        results: hits
+            .into_iter()
+            .map(|hit| SearchResultItem {
+                link: saved_page_link(&hit.page.id, &api_key),
+                page: hit.page.info(),
+                score: hit.score,
+                snippet: hit.snippet,
+                snippet_html: hit.snippet_html,
+            })
+            .collect(),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+// 検索フォームと結果の表示（/search?q=...&api_key=...）
+pub async fn handle_search_page_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    let search_query = params.get("q").map(|q| q.trim().to_string()).unwrap_or_default();
+    let hits = if search_query.is_empty() {
+        Vec::new()
+    } else {
+        match search_saved_pages(&api_key, &search_query, DEFAULT_SEARCH_RESULTS, &state).await {
+            Ok(hits) => hits,
+            Err(error) => return Ok(create_error_html_response(error)),
+        }
+    };
+    let list_link = format!("/saved?api_key={}", urlencoding::encode(&api_key));
+    let body = render_search_page(&search_query, &api_key, &hits, &list_link, |id| saved_page_link(id, &api_key));
+    Ok(create_html_response(render_lightweight_page(&body)))
+}
+
+async fn search_saved_pages(api_key: &str, search_query: &str, limit: usize, state: &AppState) -> Result<Vec<SearchHit>, ApiError> {
+    let pages = state.saved_pages.read().await;
+    state
+        .search_index
+        .search(api_key, search_query, limit, &pages)
+        .map_err(|e| ApiError::new(ErrorCode::StorageError, format!("検索エラー: {}", e)))
+}
+
+// ページのタイトル（HTMLは<title>、それ以外は解析結果のタイトル、どちらもなければURL）
+fn fetched_page_title(page: &FetchedPage, document: &Document) -> String {
+    html_page_title(page)
+        .or_else(|| document.title.clone())
+        .unwrap_or_else(|| page.final_url.clone())
+}
+
+fn html_page_title(page: &FetchedPage) -> Option<String> {
+    match (&page.kind, &page.body) {
+        (ContentKind::Html, PageBody::Text(html)) => page_title(html),
+        _ => None,
+    }
+}
+
+fn saved_page_link(page_id: &str, api_key: &str) -> String {
+    format!("/saved/{}?api_key={}", urlencoding::encode(page_id), urlencoding::encode(api_key))
+}
+
+fn saved_page_not_found_error() -> ApiError {
+    ApiError::new(ErrorCode::SavedPageNotFound, "保存したページが見つかりません")
+}
+
+// ========== 閲覧履歴 ==========
+
+// 閲覧履歴（/api/history?api_key=...&limit=...）
+pub async fn handle_history_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let config = &Config::global().history;
+    let limit = match limit_param(&params, DEFAULT_HISTORY_ENTRIES, config.max_entries_per_key.max(1)) {
+        Ok(limit) => limit,
+        Err(error) => return Ok(create_error_json_response(ApiError::invalid_request(vec![error]))),
+    };
+
+    let history = state.history.read().await;
+    let response = HistoryResponse {
+        success: true,
+        enabled: history.is_enabled(&api_key),
+        retention_days: config.retention_days,
+        entries: history.list(&api_key, limit).iter().map(HistoryEntry::info).collect(),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+pub async fn handle_clear_history_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    state.history.write().await.clear(&api_key);
+    Ok(success_json_response("閲覧履歴を削除しました".to_string(), StatusCode::OK))
+}
+
+// 閲覧履歴の記録の切り替え（キーの利用者が自分で切り替えられるよう管理者キーは不要）
+pub async fn handle_history_settings_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let header_api_key = header_api_key(&req);
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let request = match parse_json_request::<ApiHistorySettingsRequest>(&body_str) {
+        Ok(request) => request,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+    let enabled = match request.enabled {
+        Some(enabled) => enabled,
+        None => return Ok(create_error_json_response(ApiError::invalid_request(vec![field_error("enabled", "必須です")]))),
+    };
+
+    let mut params = HashMap::new();
+    let api_key = match validate_body_api_key(request.api_key, header_api_key, &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    state.history.write().await.set_enabled(&api_key, enabled);
+    let message = if enabled {
+        "閲覧履歴の記録を再開しました"
+    } else {
+        "閲覧履歴の記録を止め、これまでの閲覧履歴を削除しました"
+    };
+    Ok(success_json_response(message.to_string(), StatusCode::OK))
+}
+
+// ========== ブックマーク ==========
+
+pub async fn handle_create_bookmark_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let (request, api_key) = match read_bookmark_request(req, &state).await {
+        Ok(found) => found,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let target_url = request.url.as_deref().map(str::trim).filter(|u| !u.is_empty()).map(normalize_url);
+    let mut field_errors: Vec<FieldError> = url_field_error("url", target_url.as_deref(), true).into_iter().collect();
+    field_errors.extend(bookmark_title_error(request.title.as_deref()));
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+    let target_url = target_url.unwrap_or_default();
+
+    let title = match request.title.map(|t| t.trim().to_string()) {
+        Some(title) => title,
+        None => state.history.read().await.title_for(&api_key, &target_url).unwrap_or_else(|| target_url.clone()),
+    };
+    let bookmark = Bookmark {
+        id: generate_bookmark_id(),
+        api_key: api_key.clone(),
+        url: target_url,
+        title,
+        folder: request.folder.map(|f| f.trim().to_string()).unwrap_or_default(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    // 上限の確認と追加は1つの書き込みロックで行う
+    let max_bookmarks = Config::global().bookmarks.max_bookmarks_per_key;
+    if !state.bookmarks.write().await.add(bookmark.clone(), max_bookmarks) {
+        let message = format!("登録できるブックマークは{}件までです。不要なブックマークを削除してください", max_bookmarks);
+        return Ok(create_error_json_response(ApiError::new(ErrorCode::QuotaExceeded, message)));
+    }
+    let response = BookmarkResponse { success: true, bookmark: bookmark.info() };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::CREATED))
+}
+
+pub async fn handle_list_bookmarks_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let mut bookmarks = state.bookmarks.read().await.list(&api_key);
+    let all_folders = folders(&bookmarks);
+    // folderを指定した場合はそのフォルダのみ（空文字列はフォルダなし）
+    if let Some(folder) = params.get("folder") {
+        bookmarks.retain(|bookmark| bookmark.folder == folder.trim());
+    }
+    let response = BookmarkListResponse {
+        success: true,
+        folders: all_folders,
+        bookmarks: bookmarks.iter().map(Bookmark::info).collect(),
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+}
+
+// タイトル・URL・フォルダの変更（指定したフィールドのみ）
+pub async fn handle_update_bookmark_request(req: Request<Body>, bookmark_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let (request, api_key) = match read_bookmark_request(req, &state).await {
+        Ok(found) => found,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let target_url = request.url.as_deref().map(str::trim).map(normalize_url);
+    let mut field_errors: Vec<FieldError> = url_field_error("url", target_url.as_deref(), false).into_iter().collect();
+    field_errors.extend(bookmark_title_error(request.title.as_deref()));
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+
+    let title = request.title.map(|t| t.trim().to_string());
+    let folder = request.folder.map(|f| f.trim().to_string());
+    let result = state.bookmarks.write().await.update(bookmark_id, &api_key, target_url, title, folder);
+    match result {
+        Some(bookmark) => {
+            let response = BookmarkResponse { success: true, bookmark: bookmark.info() };
+            Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
+        }
+        None => Ok(create_error_json_response(bookmark_not_found_error())),
+    }
+}
+
+pub async fn handle_delete_bookmark_request(req: Request<Body>, bookmark_id: &str, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+    let api_key = match validate_body_api_key(None, header_api_key(&req), &mut params, &state).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    if state.bookmarks.write().await.remove(bookmark_id, &api_key) {
+        Ok(success_json_response("ブックマークを削除しました".to_string(), StatusCode::OK))
+    } else {
+        Ok(create_error_json_response(bookmark_not_found_error()))
+    }
+}
+
+// JSONボディを読み、APIキー（ボディまたはX-API-Keyヘッダー）を検証する
+async fn read_bookmark_request(req: Request<Body>, state: &AppState) -> Result<(ApiBookmarkRequest, String), ApiError> {
+    let header_api_key = header_api_key(&req);
+    let body_str = get_request_body(req).await?;
+    let mut request: ApiBookmarkRequest = parse_json_request(&body_str)?;
+
+    let mut params = HashMap::new();
+    let api_key = validate_body_api_key(request.api_key.take(), header_api_key, &mut params, state)
+        .await
+        .ok_or_else(invalid_api_key_error)?;
+    Ok((request, api_key))
+}
+
+fn bookmark_title_error(title: Option<&str>) -> Option<FieldError> {
+    match title {
+        Some(title) if title.trim().is_empty() => Some(field_error("title", "空にはできません")),
+        _ => None,
+    }
+}
+
+fn bookmark_not_found_error() -> ApiError {
+    ApiError::new(ErrorCode::BookmarkNotFound, "ブックマークが見つかりません")
+}
+
+// ========== JSONボディのリクエスト共通 ==========
+
+fn header_api_key(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+// ボディのapi_key（なければX-API-Keyヘッダー）を検証する
+async fn validate_body_api_key(body_api_key: Option<String>, header_api_key: Option<String>, params: &mut HashMap<String, String>, state: &AppState) -> Option<String> {
+    if let Some(api_key) = body_api_key.filter(|k| !k.is_empty()).or(header_api_key) {
+        params.insert("api_key".to_string(), api_key);
+    }
+    validate_api_key(params, &state.api_key_store).await
+}
+
+// JSONボディの軽量化オプションをGETと同じクエリパラメータの形にする
+fn api_option_params(options: &ApiProcessOptions) -> HashMap<String, String> {
+    let fields = [
+        ("format", &options.format),
+        ("profile", &options.profile),
+        ("links", &options.links),
+        ("nav", &options.nav),
+        ("structure", &options.structure),
+        ("images", &options.images),
+        ("forms", &options.forms),
+        ("ua", &options.ua),
+        ("encoding", &options.encoding),
+    ];
+    let mut params: HashMap<String, String> = fields
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| (name.to_string(), v.to_string()))
+        })
+        .collect();
+    if options.no_cache {
+        params.insert("cache".to_string(), "bypass".to_string());
+    }
+    params
+}
+
+fn option_field_errors(params: &HashMap<String, String>) -> Vec<FieldError> {
+    ProcessOptions::param_errors(params)
+        .into_iter()
+        .map(|(field, message)| field_error(field, &message))
+        .collect()
+}
+
+// http(s)のURLか検証する（requiredでなければ未指定を許す）
+fn url_field_error(field: &str, value: Option<&str>, required: bool) -> Option<FieldError> {
+    match value.map(url::Url::parse) {
+        None if required => Some(field_error(field, "必須です")),
+        None => None,
+        Some(Ok(url)) if url.scheme() == "http" || url.scheme() == "https" => None,
+        Some(_) => Some(field_error(field, "http(s)のURLを指定してください")),
+    }
+}
+
+// クエリのlimit（省略時はdefault、1からmaxまで）
+fn limit_param(params: &HashMap<String, String>, default: usize, max: usize) -> Result<usize, FieldError> {
+    match params.get("limit") {
+        None => Ok(default),
+        Some(value) => match value.parse::<usize>() {
+            Ok(limit) if (1..=max).contains(&limit) => Ok(limit),
+            _ => Err(field_error("limit", &format!("1から{}までの整数を指定してください", max))),
+        },
+    }
+}
 
-async fn validate_api_key(params: &HashMap<String, String>, api_key_store: &SharedApiKeyStore) -> Option<String> {
-    if let Some(api_key) = params.get("api_key") {
-        let store = api_key_store.read().await;
-        if store.validate_key(api_key) {
-            return Some(api_key.clone());
-        }
+fn field_error(field: &str, message: &str) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        message: message.to_string(),
     }
-    None
 }
 
-async fn validate_admin_key(params: &HashMap<String, String>, api_key_store: &SharedApiKeyStore) -> bool {
-    if let Some(admin_key) = params.get("admin_key") {
-        let store = api_key_store.read().await;
-        return store.validate_admin_key(admin_key);
+fn invalid_json_error(error: &serde_json::Error) -> ApiError {
+    ApiError::new(ErrorCode::InvalidJson, format!("無効なJSONです: {}", error))
+}
+
+// JSONボディを解析する（型の合わない項目と未知の項目は項目ごとのエラーにする）
+// すべての項目が省略可能なリクエスト用（項目を1つずつ解析して誤りのある項目を特定する）
+fn parse_json_request<T: DeserializeOwned + JsonSchema>(body: &str) -> Result<T, ApiError> {
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|e| invalid_json_error(&e))?;
+    let object = match value.as_object() {
+        Some(object) => object,
+        None => return Err(ApiError::new(ErrorCode::InvalidJson, "JSONオブジェクトを指定してください")),
+    };
+
+    let schema = schemars::schema_for!(T);
+    let known_fields: Vec<&String> = schema.schema.object.iter().flat_map(|object| object.properties.keys()).collect();
+    let mut field_errors = Vec::new();
+    for (name, field_value) in object {
+        if !known_fields.contains(&name) {
+            field_errors.push(field_error(name, "不明な項目です"));
+            continue;
+        }
+        let single = serde_json::Value::Object([(name.clone(), field_value.clone())].into_iter().collect());
+        if let Err(e) = serde_json::from_value::<T>(single) {
+            field_errors.push(field_error(name, &format!("値の型が正しくありません: {}", e)));
+        }
     }
-    false
+    if !field_errors.is_empty() {
+        return Err(ApiError::invalid_request(field_errors));
+    }
+
+    serde_json::from_value(value).map_err(|e| invalid_json_error(&e))
 }
 
-fn create_unauthorized_json_response(message: &str) -> Response<Body> {
-    let error_response = serde_json::json!({
-        "success": false,
-        "error": message
-    });
-    let json_response = serde_json::to_string(&error_response).unwrap();
-    create_json_response(json_response, StatusCode::UNAUTHORIZED)
+// ApiResponseの形のエラーレスポンス（JSON APIのエンドポイント用）
+fn api_error_json_response(error: ApiError, original_url: Option<&str>) -> Response<Body> {
+    let status = error.status_code();
+    let error_response = create_api_error_response(error, original_url);
+    create_json_response(serde_json::to_string(&error_response).unwrap(), status)
 }
 
-fn create_bad_request_json_response(message: &str) -> Response<Body> {
-    let error_response = serde_json::json!({
-        "success": false,
-        "error": message
-    });
-    let json_response = serde_json::to_string(&error_response).unwrap();
-    create_json_response(json_response, StatusCode::BAD_REQUEST)
+// ========== フォーム送信 ==========
+
+// 軽量化ページのGETフォームの送信先。rigil_で始まるフィールドはプロキシ用で、
+// それ以外のフィールドから送信先のURLを組み立てる
+pub async fn handle_form_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let fields: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+    let mut proxy_params: HashMap<String, String> = HashMap::new();
+    let mut form_fields: Vec<(String, String)> = Vec::new();
+    for (name, value) in fields {
+        match name.strip_prefix("rigil_") {
+            Some(proxy_name) => {
+                proxy_params.insert(proxy_name.to_string(), value);
+            }
+            None => form_fields.push((name, value)),
+        }
+    }
+
+    let action = match proxy_params.get("action").and_then(|a| url::Url::parse(a).ok()) {
+        Some(action) if action.scheme() == "http" || action.scheme() == "https" => action,
+        _ => return Ok(create_error_html_response(invalid_form_action_error())),
+    };
+
+    let api_key = match validate_api_key(&proxy_params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    let options = match request_process_options(&proxy_params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+
+    // GETフォームの送信と同様に、送信先URLのクエリをフィールドで置き換える
+    let mut target_url = action;
+    target_url.set_query(None);
+    if !form_fields.is_empty() {
+        target_url.query_pairs_mut().extend_pairs(&form_fields);
+    }
+
+    let context = state.fetch_context(&api_key, &options, client_accept_language(&req)).await;
+    match process_url_and_record_usage(target_url.as_str(), &api_key, &state, &options, &context).await {
+        Ok(processed_html) => Ok(create_html_response(processed_html)),
+        Err(e) => Ok(create_error_html_response(ApiError::from(&e))),
+    }
 }
 
-// ========== プロキシ機能 ==========
+// 軽量化ページのPOSTフォームの送信先。送信先URLと軽量化オプションはクエリで受け取り、
+// 本文のうちrigil_で始まらないフィールドをそのまま送信先へPOSTする
+pub async fn handle_proxy_post_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let mut params = parse_query_params(query);
+
+    let content_type = req
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_lowercase();
+    let accept_language = client_accept_language(&req).map(|lang| lang.to_string());
+    if !content_type.starts_with("application/x-www-form-urlencoded") {
+        let error = ApiError::new(ErrorCode::UnsupportedMediaType, "application/x-www-form-urlencoded形式のフォームのみ送信できます");
+        return Ok(create_error_html_response(error));
+    }
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(_) => return Ok(create_error_html_response(body_read_error())),
+    };
+
+    let mut form_fields: Vec<(String, String)> = Vec::new();
+    for (name, value) in url::form_urlencoded::parse(&body).into_owned() {
+        match name.strip_prefix("rigil_") {
+            // クエリで指定されていない場合のみ、本文のプロキシ用フィールドを使う
+            Some(proxy_name) => {
+                params.entry(proxy_name.to_string()).or_insert(value);
+            }
+            None => form_fields.push((name, value)),
+        }
+    }
+
+    let target_url = match params.get("url").and_then(|a| url::Url::parse(a).ok()) {
+        Some(action) if action.scheme() == "http" || action.scheme() == "https" => action,
+        _ => return Ok(create_error_html_response(invalid_form_action_error())),
+    };
+
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
+    };
+
+    if !state.api_key_store.read().await.is_post_allowed(&api_key) {
+        let error = ApiError::new(ErrorCode::PostNotAllowed, "このAPIキーではフォームのPOST送信が許可されていません");
+        return Ok(create_error_html_response(error));
+    }
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+
+    let context = state.fetch_context(&api_key, &options, accept_language.as_deref()).await;
+    let result = post_form(target_url.as_str(), &form_fields, &context).await;
+    state.save_fetch_context(&context).await;
+
+    match result {
+        Ok(page) => {
+            let processed_html = process_page_and_record_usage(page, &api_key, &state, &options).await;
+            Ok(create_html_response(processed_html))
+        }
+        Err(e) => Ok(create_error_html_response(ApiError::from(&e))),
+    }
+}
+
+// ========== 画像縮小 ==========
 
-pub async fn handle_proxy_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+pub async fn handle_image_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
     let query = req.uri().query().unwrap_or("");
     let params = parse_query_params(query);
 
     let target_url = match params.get("url") {
-        Some(url) => url,
-        None => {
-            let error_html = create_error_page("URLパラメータが必要です");
-            return Ok(create_html_response(error_html));
-        }
+        Some(url) => normalize_url(url),
+        None => return Ok(create_error_json_response(missing_url_error())),
     };
 
-    let api_key = match validate_api_key(&params, &api_key_store).await {
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
         Some(key) => key,
-        None => {
-            let error_html = create_error_page("有効なAPIキーが必要です");
-            return Ok(create_html_response(error_html));
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let config = &Config::global().images;
+    let context = state.fetch_context(&api_key, &ProcessOptions::default(), client_accept_language(&req)).await;
+    let result = get_bytes(&target_url, config.max_source_bytes, &context).await;
+    state.save_fetch_context(&context).await;
+
+    let source = match result {
+        Ok((source, _content_type)) => source,
+        Err(e) => return Ok(create_error_json_response(ApiError::from(&e))),
+    };
+
+    let source_size = source.len() as u64;
+    let thumbnail = match make_thumbnail(source, config).await {
+        Ok(thumbnail) => thumbnail,
+        Err(e) => return Ok(create_error_json_response(ApiError::new(ErrorCode::UnsupportedContent, e))),
+    };
+
+    // 使用量を記録
+    {
+        let mut store = state.api_key_store.write().await;
+        if let Err(e) = store.add_image_usage(&api_key, source_size, thumbnail.len() as u64) {
+            eprintln!("画像使用量記録エラー: {}", e);
         }
+    }
+
+    let mut response = Response::new(Body::from(thumbnail));
+    response.headers_mut().insert("content-type", "image/jpeg".parse().unwrap());
+    response.headers_mut().insert("cache-control", "private, max-age=86400".parse().unwrap());
+    Ok(response)
+}
+
+// ========== 生成フィード ==========
+
+// ページの記事リンクからAtomフィードを生成する
+pub async fn handle_generated_feed_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+
+    let target_url = match params.get("url") {
+        Some(url) => normalize_url(url),
+        None => return Ok(create_error_json_response(missing_url_error())),
     };
 
-    match process_url_and_record_usage(target_url, &api_key, &api_key_store).await {
-        Ok(processed_html) => Ok(create_html_response(processed_html)),
-        Err(error_msg) => {
-            let error_html = create_error_page(&error_msg);
-            Ok(create_html_response(error_html))
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
+
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let context = state.fetch_context(&api_key, &options, client_accept_language(&req)).await;
+    let result = get_page(&target_url, &context).await;
+    state.save_fetch_context(&context).await;
+
+    let page = match result {
+        Ok(page) => page,
+        Err(e) => return Ok(create_error_json_response(ApiError::from(&e))),
+    };
+    let html = match (&page.kind, &page.body) {
+        (ContentKind::Html, PageBody::Text(html)) => html,
+        _ => return Ok(create_error_json_response(ApiError::new(ErrorCode::UnsupportedContent, "HTMLページではないためフィードを生成できません"))),
+    };
+
+    let links = extract_article_links(html, &get_base_url(&page.final_url), &page.final_url, &options);
+    let entries = state.feed_links.write().await.record_links(&target_url, &links);
+
+    let title = page_title(html).unwrap_or_else(|| target_url.clone());
+    let options_query = options.to_query();
+    let atom = render_atom(&target_url, &title, &entries, |url| entry_link(&api_key, url, &options_query));
+
+    // 使用量を記録
+    {
+        let mut store = state.api_key_store.write().await;
+        if let Err(e) = store.add_usage(&api_key, page.original_size(), atom.len() as u64) {
+            eprintln!("使用量記録エラー: {}", e);
         }
     }
+
+    let mut response = Response::new(Body::from(atom));
+    response.headers_mut().insert("content-type", "application/atom+xml; charset=utf-8".parse().unwrap());
+    Ok(response)
 }
 
-pub async fn handle_api_get_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+// 生成したフィードの記事を開く（リンクの署名からAPIキーを確かめ、/proxyと同じように表示する）
+pub async fn handle_feed_entry_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
     let query = req.uri().query().unwrap_or("");
     let params = parse_query_params(query);
 
     let target_url = match params.get("url") {
         Some(url) => url,
-        None => {
-            let error_response = create_api_error_response("URLパラメータが必要です", None);
-            return Ok(create_json_response(serde_json::to_string(&error_response).unwrap(), StatusCode::BAD_REQUEST));
-        }
+        None => return Ok(create_error_html_response(missing_url_error())),
     };
 
-    let api_key = match validate_api_key(&params, &api_key_store).await {
+    let id = params.get("key").map(|id| id.as_str()).unwrap_or("");
+    let signature = params.get("sig").map(|sig| sig.as_str()).unwrap_or("");
+    let api_key = state
+        .api_key_store
+        .read()
+        .await
+        .list_keys()
+        .into_iter()
+        .find(|api_key| verify_entry(api_key, id, target_url, signature));
+    let api_key = match api_key {
         Some(key) => key,
-        None => {
-            let error_response = create_api_error_response("有効なAPIキーが必要です", Some(target_url));
-            return Ok(create_json_response(serde_json::to_string(&error_response).unwrap(), StatusCode::UNAUTHORIZED));
-        }
+        None => return Ok(create_error_html_response(invalid_api_key_error())),
     };
 
-    let response = process_url_for_api(target_url, &api_key, &api_key_store).await;
-    let json_response = serde_json::to_string(&response).unwrap_or_else(|_| {
-        r#"{"success":false,"data":null,"error":"JSON serialization error"}"#.to_string()
-    });
+    let options = match request_process_options(&params, &api_key, &state.api_key_store).await {
+        Ok(options) => options,
+        Err(error) => return Ok(create_error_html_response(error)),
+    };
+    let context = state.fetch_context(&api_key, &options, client_accept_language(&req)).await;
+    match process_url_and_record_usage(target_url, &api_key, &state, &options, &context).await {
+        Ok(processed_html) => Ok(create_html_response(processed_html)),
+        Err(e) => Ok(create_error_html_response(ApiError::from(&e))),
+    }
+}
+
+// ========== Cookieジャー ==========
+
+// 保持している上流サイトのCookieを一覧する（domainで絞り込み可能）
+pub async fn handle_list_cookies_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
 
-    Ok(create_json_response(json_response, StatusCode::OK))
+    let domain = params.get("domain").filter(|d| !d.is_empty()).map(|d| d.as_str());
+    let cookies = state.cookie_jars.read().await.list_cookies(&api_key, domain);
+    let response = CookieListResponse {
+        success: true,
+        cookies,
+    };
+    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
 }
 
-// ========== APIキー管理 ==========
+// 保持している上流サイトのCookieを削除する（domainを省略するとすべて削除）
+pub async fn handle_clear_cookies_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+
+    let api_key = match validate_api_key(&params, &state.api_key_store).await {
+        Some(key) => key,
+        None => return Ok(create_error_json_response(invalid_api_key_error())),
+    };
 
-#[derive(serde::Serialize)]
-struct SimpleResponse {
-    success: bool,
-    message: Option<String>,
-    error: Option<String>,
+    let domain = params.get("domain").filter(|d| !d.is_empty()).map(|d| d.as_str());
+    let removed = state.cookie_jars.write().await.clear_cookies(&api_key, domain);
+    Ok(success_json_response(format!("{}件のCookieを削除しました", removed), StatusCode::OK))
 }
 
+// ========== APIキー管理 ==========
+
 pub async fn handle_create_key_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
     let body_str = match get_request_body(req).await {
         Ok(body) => body,
-        Err(response) => return Ok(response),
-    };
-
-    let request_data: Result<serde_json::Value, _> = serde_json::from_str(&body_str);
-
-    match request_data {
-        Ok(data) => {
-            if let Some(key) = data.get("key").and_then(|k| k.as_str()) {
-                match create_new_api_key(key, &api_key_store).await {
-                    Ok(message) => {
-                        let response = SimpleResponse {
-                            success: true,
-                            message: Some(message),
-                            error: None,
-                        };
-                        Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
-                    }
-                    Err(error) => {
-                        let response = SimpleResponse {
-                            success: false,
-                            message: None,
-                            error: Some(error.to_string()),
-                        };
-                        let status = match error {
-                            ApiKeyError::KeyAlreadyExists => StatusCode::CONFLICT,
-                            _ => StatusCode::INTERNAL_SERVER_ERROR,
-                        };
-                        Ok(create_json_response(serde_json::to_string(&response).unwrap(), status))
-                    }
-                }
-            } else {
-                Ok(create_bad_request_json_response("keyフィールドが必要です"))
-            }
-        }
-        Err(_) => Ok(create_bad_request_json_response("無効なJSONです"))
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let request_data: CreateKeyRequest = match serde_json::from_str(&body_str) {
+        Ok(data) => data,
+        Err(e) if e.is_data() => return Ok(create_error_json_response(missing_field_error("keyフィールドが必要です"))),
+        Err(e) => return Ok(create_error_json_response(invalid_json_error(&e))),
+    };
+
+    match create_new_api_key(&request_data.key, &api_key_store).await {
+        Ok(message) => Ok(success_json_response(message, StatusCode::OK)),
+        Err(error) => Ok(create_error_json_response(ApiError::from(error))),
+    }
+}
+
+// POST /api/v1/keys（作成時は201を返す）
+pub async fn handle_v1_create_key_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    let mut response = handle_create_key_request(req, api_key_store).await?;
+    if response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::CREATED;
     }
+    Ok(response)
 }
 
 pub async fn handle_list_keys_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
@@ -192,14 +1789,7 @@ pub async fn handle_list_keys_request(req: Request<Body>, api_key_store: SharedA
     let params = parse_query_params(query);
 
     if !validate_admin_key(&params, &api_key_store).await {
-        let error_response = UsageResponse {
-            success: false,
-            key: None,
-            total_bytes_processed: None,
-            keys: None,
-            error: Some("管理者権限が必要です".to_string()),
-        };
-        return Ok(create_json_response(serde_json::to_string(&error_response).unwrap(), StatusCode::UNAUTHORIZED));
+        return Ok(create_error_json_response(admin_required_error()));
     }
 
     let admin_key = params.get("admin_key").unwrap();
@@ -211,39 +1801,157 @@ pub async fn handle_list_keys_request(req: Request<Body>, api_key_store: SharedA
                 key: None,
                 total_bytes_processed: None,
                 keys: Some(keys_data),
-                error: None,
             };
             Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
         }
-        Err(error) => Ok(create_unauthorized_json_response(&error.to_string()))
+        Err(error) => Ok(create_error_json_response(ApiError::from(error))),
     }
 }
 
 pub async fn handle_delete_key_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
     let query = req.uri().query().unwrap_or("");
     let params = parse_query_params(query);
+    delete_key(&params, params.get("key").map(|k| k.as_str()), &api_key_store).await
+}
 
-    if !validate_admin_key(&params, &api_key_store).await {
-        return Ok(create_unauthorized_json_response("管理者権限が必要です"));
+// DELETE /api/v1/keys/{key}
+pub async fn handle_v1_delete_key_request(req: Request<Body>, key: &str, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+    delete_key(&params, Some(key), &api_key_store).await
+}
+
+async fn delete_key(params: &HashMap<String, String>, key: Option<&str>, api_key_store: &SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    if !validate_admin_key(params, api_key_store).await {
+        return Ok(create_error_json_response(admin_required_error()));
     }
 
-    if let Some(key_to_delete) = params.get("key") {
+    if let Some(key_to_delete) = key {
         let admin_key = params.get("admin_key").unwrap();
         let mut store = api_key_store.write().await;
 
         match store.remove_key(admin_key, key_to_delete) {
-            Ok(()) => {
-                let response = SimpleResponse {
-                    success: true,
-                    message: Some(format!("APIキー '{}' を削除しました", key_to_delete)),
-                    error: None,
-                };
-                Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
-            }
-            Err(error) => Ok(create_unauthorized_json_response(&error.to_string()))
+            Ok(()) => Ok(success_json_response(format!("APIキー '{}' を削除しました", key_to_delete), StatusCode::OK)),
+            Err(error) => Ok(create_error_json_response(ApiError::from(error))),
         }
     } else {
-        Ok(create_bad_request_json_response("keyパラメータが必要です"))
+        Ok(create_error_json_response(missing_field_error("keyパラメータが必要です")))
+    }
+}
+
+pub async fn handle_add_fingerprint_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    add_fingerprint(req, None, &api_key_store).await
+}
+
+// POST /api/v1/keys/{key}/fingerprints
+pub async fn handle_v1_add_fingerprint_request(req: Request<Body>, key: &str, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    add_fingerprint(req, Some(key), &api_key_store).await
+}
+
+// 従来のパスではボディのkey、/api/v1ではパスのkeyを使う
+async fn add_fingerprint(req: Request<Body>, path_key: Option<&str>, api_key_store: &SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let mut request_data: FingerprintRequest = match serde_json::from_str(&body_str) {
+        Ok(data) => data,
+        Err(e) if e.is_data() => return Ok(create_error_json_response(missing_field_error("admin_key、key、fingerprintフィールドが必要です"))),
+        Err(e) => return Ok(create_error_json_response(invalid_json_error(&e))),
+    };
+    if let Some(key) = path_key {
+        request_data.key = key.to_string();
+    }
+    if request_data.key.is_empty() {
+        return Ok(create_error_json_response(missing_field_error("admin_key、key、fingerprintフィールドが必要です")));
+    }
+
+    let mut store = api_key_store.write().await;
+    match store.add_client_cert_fingerprint(&request_data.admin_key, &request_data.key, &request_data.fingerprint) {
+        Ok(()) => Ok(success_json_response(format!("APIキー '{}' に証明書指紋を登録しました", request_data.key), StatusCode::OK)),
+        Err(error) => Ok(create_error_json_response(ApiError::from(error))),
+    }
+}
+
+pub async fn handle_delete_fingerprint_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+    delete_fingerprint(&params, params.get("key").map(|k| k.as_str()), params.get("fingerprint").map(|f| f.as_str()), &api_key_store).await
+}
+
+// DELETE /api/v1/keys/{key}/fingerprints/{fingerprint}
+pub async fn handle_v1_delete_fingerprint_request(req: Request<Body>, key: &str, fingerprint: &str, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let params = parse_query_params(query);
+    delete_fingerprint(&params, Some(key), Some(fingerprint), &api_key_store).await
+}
+
+async fn delete_fingerprint(params: &HashMap<String, String>, key: Option<&str>, fingerprint: Option<&str>, api_key_store: &SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    if !validate_admin_key(params, api_key_store).await {
+        return Ok(create_error_json_response(admin_required_error()));
+    }
+
+    let (key, fingerprint) = match (key, fingerprint) {
+        (Some(key), Some(fingerprint)) => (key, fingerprint),
+        _ => return Ok(create_error_json_response(missing_field_error("keyとfingerprintパラメータが必要です"))),
+    };
+
+    let admin_key = params.get("admin_key").unwrap();
+    let mut store = api_key_store.write().await;
+    match store.remove_client_cert_fingerprint(admin_key, key, fingerprint) {
+        Ok(()) => Ok(success_json_response(format!("APIキー '{}' から証明書指紋を削除しました", key), StatusCode::OK)),
+        Err(error) => Ok(create_error_json_response(ApiError::from(error))),
+    }
+}
+
+pub async fn handle_update_key_settings_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    update_key_settings(req, None, &api_key_store).await
+}
+
+// PATCH /api/v1/keys/{key}
+pub async fn handle_v1_update_key_settings_request(req: Request<Body>, key: &str, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    update_key_settings(req, Some(key), &api_key_store).await
+}
+
+// 従来のパスではボディのkey、/api/v1ではパスのkeyを使う
+async fn update_key_settings(req: Request<Body>, path_key: Option<&str>, api_key_store: &SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+    let body_str = match get_request_body(req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(create_error_json_response(error)),
+    };
+
+    let mut settings: ApiKeySettingsRequest = match serde_json::from_str(&body_str) {
+        Ok(settings) => settings,
+        Err(e) => return Ok(create_error_json_response(invalid_json_error(&e))),
+    };
+    if let Some(key) = path_key {
+        settings.key = key.to_string();
+    }
+    if settings.key.is_empty() {
+        return Ok(create_error_json_response(missing_field_error("keyフィールドが必要です")));
+    }
+
+    let mut field_errors = Vec::new();
+    if let Some(profile) = settings.default_profile.as_deref().filter(|p| !p.is_empty()) {
+        if ProcessingProfile::find(profile).is_none() {
+            field_errors.push(field_error("default_profile", &format!("不明なプロファイルです: {}", profile)));
+        }
+    }
+    if let Some(user_agent) = settings.user_agent.as_deref().filter(|ua| !ua.is_empty()) {
+        if find_user_agent(user_agent).is_none() {
+            let message = format!("不明なUser-Agentプロファイルです: {}（使用可能: {}）", user_agent, available_user_agents().join(", "));
+            field_errors.push(field_error("user_agent", &message));
+        }
+    }
+    if !field_errors.is_empty() {
+        return Ok(create_error_json_response(ApiError::invalid_request(field_errors)));
+    }
+
+    let mut store = api_key_store.write().await;
+    match store.update_settings(&settings) {
+        Ok(()) => Ok(success_json_response(format!("APIキー '{}' の設定を更新しました", settings.key), StatusCode::OK)),
+        Err(error) => Ok(create_error_json_response(ApiError::from(error))),
     }
 }
 
@@ -254,27 +1962,20 @@ pub async fn handle_statistics_request(req: Request<Body>, api_key_store: Shared
     let params = parse_query_params(query);
 
     if !validate_admin_key(&params, &api_key_store).await {
-        return Ok(create_unauthorized_json_response("管理者権限が必要です"));
+        return Ok(create_error_json_response(admin_required_error()));
     }
 
     let admin_key = params.get("admin_key").unwrap();
     let store = api_key_store.read().await;
     match store.get_statistics(admin_key) {
-        Ok(stats) => {
-            let (total_original, total_processed, total_compressions, compression_ratio, total_keys) = stats.as_tuple();
-            let response = serde_json::json!({
-                "success": true,
-                "statistics": {
-                    "total_keys": total_keys,
-                    "total_original_bytes": total_original,
-                    "total_processed_bytes": total_processed,
-                    "total_compressions": total_compressions,
-                    "compression_ratio": compression_ratio
-                }
-            });
+        Ok(statistics) => {
+            let response = StatisticsResponse {
+                success: true,
+                statistics,
+            };
             Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
         }
-        Err(error) => Ok(create_unauthorized_json_response(&error.to_string()))
+        Err(error) => Ok(create_error_json_response(ApiError::from(error))),
     }
 }
 
@@ -283,108 +1984,202 @@ pub async fn handle_statistics_request(req: Request<Body>, api_key_store: Shared
 pub async fn handle_admin_login_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
     let body_str = match get_request_body(req).await {
         Ok(body) => body,
-        Err(response) => return Ok(response),
+        Err(error) => return Ok(create_error_json_response(error)),
     };
 
-    let request_data: Result<serde_json::Value, _> = serde_json::from_str(&body_str);
-
-    match request_data {
-        Ok(data) => {
-            if let Some(admin_key) = data.get("admin_key").and_then(|k| k.as_str()) {
-                let store = api_key_store.read().await;
+    let request_data: AdminLoginRequest = match serde_json::from_str(&body_str) {
+        Ok(data) => data,
+        Err(e) if e.is_data() => return Ok(create_error_json_response(missing_field_error("admin_keyフィールドが必要です"))),
+        Err(e) => return Ok(create_error_json_response(invalid_json_error(&e))),
+    };
 
-                if store.validate_admin_key(admin_key) {
-                    let response = serde_json::json!({
-                        "success": true,
-                        "message": "ログイン成功"
-                    });
-                    Ok(create_json_response(serde_json::to_string(&response).unwrap(), StatusCode::OK))
-                } else {
-                    Ok(create_unauthorized_json_response("無効な管理者キーです"))
-                }
-            } else {
-                Ok(create_bad_request_json_response("admin_keyフィールドが必要です"))
-            }
-        }
-        Err(_) => Ok(create_bad_request_json_response("無効なJSONです"))
+    let store = api_key_store.read().await;
+    if store.validate_admin_key(&request_data.admin_key) {
+        Ok(success_json_response("ログイン成功".to_string(), StatusCode::OK))
+    } else {
+        Ok(create_error_json_response(ApiError::new(ErrorCode::AdminRequired, "無効な管理者キーです")))
     }
 }
 
 // ========== ヘルパー関数 ==========
 
-async fn get_request_body(req: Request<Body>) -> Result<String, Response<Body>> {
+fn missing_url_error() -> ApiError {
+    ApiError::new(ErrorCode::MissingUrl, "URLパラメータが必要です")
+}
+
+fn missing_field_error(message: &str) -> ApiError {
+    ApiError::new(ErrorCode::InvalidRequest, message)
+}
+
+fn invalid_form_action_error() -> ApiError {
+    ApiError::new(ErrorCode::InvalidUrl, "フォームの送信先が不正です")
+}
+
+fn body_read_error() -> ApiError {
+    ApiError::new(ErrorCode::InvalidRequest, "リクエストボディの読み取りに失敗しました")
+}
+
+async fn get_request_body(req: Request<Body>) -> Result<String, ApiError> {
     match hyper::body::to_bytes(req.into_body()).await {
         Ok(body_bytes) => Ok(String::from_utf8_lossy(&body_bytes).to_string()),
-        Err(_) => Err(create_bad_request_json_response("リクエストボディの読み取りに失敗しました"))
+        Err(_) => Err(body_read_error())
     }
 }
 
+// 上流へ転送するクライアントのAccept-Language
+fn client_accept_language(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(hyper::header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+}
+
+// リクエストパラメータとAPIキーの既定プロファイルから軽量化オプションを作る
+async fn request_process_options(params: &HashMap<String, String>, api_key: &str, api_key_store: &SharedApiKeyStore) -> Result<ProcessOptions, ApiError> {
+    let key_profile = api_key_store.read().await.get_default_profile(api_key);
+    ProcessOptions::from_params(params, key_profile.as_deref()).map_err(|message| ApiError::new(ErrorCode::InvalidOption, message))
+}
+
 async fn create_new_api_key(key: &str, api_key_store: &SharedApiKeyStore) -> Result<String, ApiKeyError> {
     let mut store = api_key_store.write().await;
     store.add_key(key.to_string())?;
     Ok(format!("APIキー '{}' を作成しました", key))
 }
 
-async fn process_url_and_record_usage(target_url: &str, api_key: &str, api_key_store: &SharedApiKeyStore) -> Result<String, String> {
+async fn process_url_and_record_usage(target_url: &str, api_key: &str, state: &AppState, options: &ProcessOptions, context: &FetchContext) -> Result<String, FetchError> {
     let normalized_url = normalize_url(target_url);
 
-    match get_html(&normalized_url).await {
-        Ok((html_body, final_url)) => {
-            let base_url = get_base_url(&final_url);
-            let original_size = html_body.len() as u64;
-            let processed_html = parse_html_to_text(&html_body, &base_url, &final_url);
-            let processed_size = processed_html.len() as u64;
+    let result = get_page(&normalized_url, context).await;
+    state.save_fetch_context(context).await;
+    let page = result?;
 
-            // 使用量を記録
-            let mut store = api_key_store.write().await;
-            if let Err(e) = store.add_usage(api_key, original_size, processed_size) {
-                eprintln!("使用量記録エラー: {}", e);
-            }
+    Ok(process_page_and_record_usage(page, api_key, state, options).await)
+}
 
-            Ok(processed_html)
-        }
-        Err(e) => Err(format!("URL取得エラー: {}", e))
+// 取得済みのページをContent-Typeに応じて軽量化し、使用量を記録する
+// （ブラウザで表示するページのため閲覧履歴にも記録し、表示した内容を保存できるよう保存ボタンを加える）
+async fn process_page_and_record_usage(page: FetchedPage, api_key: &str, state: &AppState, options: &ProcessOptions) -> String {
+    let processed_html = content::render_page(&page, options);
+    record_usage(api_key, state, page.original_size(), processed_html.len() as u64).await;
+    record_history(&page, api_key, state, processed_html.len() as u64).await;
+    let view_id = state.viewed_pages.lock().await.remember(api_key, page, options);
+    add_save_form(&processed_html, &view_id)
+}
+
+// ファイルへの書き出しはまとめて行うため、ここではメモリ上の履歴を更新するだけ
+async fn record_history(page: &FetchedPage, api_key: &str, state: &AppState, processed_size: u64) {
+    if !state.history.read().await.is_enabled(api_key) {
+        return;
+    }
+    let entry = HistoryEntry {
+        url: page.final_url.clone(),
+        title: html_page_title(page).unwrap_or_else(|| page.final_url.clone()),
+        visited_at: chrono::Utc::now().to_rfc3339(),
+        original_size_bytes: page.original_size(),
+        processed_size_bytes: processed_size,
+    };
+    state.history.write().await.record(api_key, entry);
+}
+
+async fn record_usage(api_key: &str, state: &AppState, original_size: u64, processed_size: u64) {
+    let mut store = state.api_key_store.write().await;
+    if let Err(e) = store.add_usage(api_key, original_size, processed_size) {
+        eprintln!("使用量記録エラー: {}", e);
+    }
+}
+
+// JSON APIはformatに応じて軽量化したHTMLまたはプレーンテキストを返す
+fn render_api_output(page: &FetchedPage, options: &ProcessOptions) -> String {
+    match options.format {
+        OutputFormat::Html => content::render_page(page, options),
+        OutputFormat::Text => render_plain_text(&page_document(page, &options.profile)),
     }
 }
 
-async fn process_url_for_api(target_url: &str, api_key: &str, api_key_store: &SharedApiKeyStore) -> ApiResponse {
+pub async fn process_url_for_api(target_url: &str, api_key: &str, state: &AppState, options: &ProcessOptions, context: &FetchContext) -> (StatusCode, ApiResponse) {
     let normalized_url = normalize_url(target_url);
 
-    match get_html(&normalized_url).await {
-        Ok((html_body, final_url)) => {
-            let base_url = get_base_url(&final_url);
-            let original_size = html_body.len() as u64;
-            let processed_html = parse_html_to_text(&html_body, &base_url, &final_url);
-            let processed_size = processed_html.len() as u64;
+    let result = get_page(&normalized_url, context).await;
+    state.save_fetch_context(context).await;
 
-            // 使用量を記録
-            let mut store = api_key_store.write().await;
-            if let Err(e) = store.add_usage(api_key, original_size, processed_size) {
-                eprintln!("使用量記録エラー: {}", e);
-            }
+    match result {
+        Ok(page) => {
+            let original_size = page.original_size();
+            let processed_html = render_api_output(&page, options);
+            let processed_size = processed_html.len() as u64;
+            record_usage(api_key, state, original_size, processed_size).await;
 
-            ApiResponse {
+            let response = ApiResponse {
                 success: true,
                 data: Some(processed_html),
                 error: None,
-                original_url: Some(final_url),
+                original_url: Some(page.final_url),
                 processed_at: chrono::Utc::now().to_rfc3339(),
                 original_size_bytes: Some(original_size),
                 processed_size_bytes: Some(processed_size),
-            }
+            };
+            (StatusCode::OK, response)
+        }
+        Err(e) => {
+            let error = ApiError::from(&e);
+            (error.status_code(), create_api_error_response(error, Some(&normalized_url)))
         }
-        Err(e) => create_api_error_response(&e, Some(&normalized_url))
     }
 }
 
-fn create_api_error_response(error_msg: &str, original_url: Option<&str>) -> ApiResponse {
+fn create_api_error_response(error: ApiError, original_url: Option<&str>) -> ApiResponse {
     ApiResponse {
         success: false,
         data: None,
-        error: Some(error_msg.to_string()),
+        error: Some(error),
         original_url: original_url.map(|s| s.to_string()),
         processed_at: chrono::Utc::now().to_rfc3339(),
         original_size_bytes: None,
         processed_size_bytes: None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_names(error: &ApiError) -> Vec<String> {
+        error.field_errors.iter().flatten().map(|e| e.field.clone()).collect()
+    }
+
+    #[test]
+    fn wrong_option_types_are_reported_per_field() {
+        let error = parse_json_request::<ApiProcessRequest>(r#"{"url": "https://example.com/", "no_cache": "yes", "format": 1}"#).err().unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        let mut fields = field_names(&error);
+        fields.sort();
+        assert_eq!(fields, vec!["format", "no_cache"]);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let error = parse_json_request::<ApiProcessRequest>(r#"{"url": "https://example.com/", "nocache": true}"#).err().unwrap();
+        assert_eq!(field_names(&error), vec!["nocache"]);
+
+        let request = parse_json_request::<ApiProcessRequest>(r#"{"url": "https://example.com/", "no_cache": true, "links": "footnotes"}"#).unwrap();
+        assert!(request.options.no_cache);
+        assert_eq!(request.options.links.as_deref(), Some("footnotes"));
+    }
+
+    #[test]
+    fn history_settings_are_checked_per_field() {
+        let error = parse_json_request::<ApiHistorySettingsRequest>(r#"{"enabled": "off", "enable": false}"#).err().unwrap();
+        let mut fields = field_names(&error);
+        fields.sort();
+        assert_eq!(fields, vec!["enable", "enabled"]);
+
+        let request = parse_json_request::<ApiHistorySettingsRequest>(r#"{"api_key": "key"}"#).unwrap();
+        assert_eq!(request.enabled, None);
+    }
+
+    #[test]
+    fn malformed_json_is_still_invalid_json() {
+        let error = parse_json_request::<ApiProcessRequest>("{").err().unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidJson);
+    }
+}
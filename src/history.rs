@@ -0,0 +1,239 @@
+use crate::api_types::HistoryEntryInfo;
+use crate::config::Config;
+use crate::persist::{JsonFile, Persisted};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// APIキーごとの閲覧履歴（/proxyで表示したページ。キーごとに記録を止められる）
+// 表示のたびに記録するため、ファイルへはまとめて書き出す
+
+// ========== 定数 ==========
+const HISTORY_FILE: &str = "history.json";
+// 閲覧履歴を返す件数の既定値
+pub const DEFAULT_HISTORY_ENTRIES: usize = 50;
+
+// ========== データ構造 ==========
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    pub url: String,
+    pub title: String,
+    pub visited_at: String,
+    pub original_size_bytes: u64,
+    pub processed_size_bytes: u64,
+}
+
+impl HistoryEntry {
+    pub fn info(&self) -> HistoryEntryInfo {
+        HistoryEntryInfo {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            visited_at: self.visited_at.clone(),
+            original_size_bytes: self.original_size_bytes,
+            processed_size_bytes: self.processed_size_bytes,
+            bytes_saved: self.original_size_bytes.saturating_sub(self.processed_size_bytes),
+        }
+    }
+}
+
+// ========== 閲覧履歴ストア ==========
+#[derive(Serialize, Deserialize)]
+pub struct HistoryStore {
+    // APIキーごとに古いものから並べる
+    #[serde(default)]
+    entries: HashMap<String, Vec<HistoryEntry>>,
+    // 記録を止めたAPIキー
+    #[serde(default)]
+    disabled_keys: HashSet<String>,
+    #[serde(skip, default = "history_file")]
+    file: JsonFile,
+}
+
+impl HistoryStore {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            disabled_keys: HashSet::new(),
+            file: history_file(),
+        }
+    }
+
+    pub fn load_from_file() -> Self {
+        let mut store: Self = history_file().load().unwrap_or_else(Self::new);
+        store.remove_expired();
+        store
+    }
+
+    // サーバーの設定で無効な場合はどのキーも記録しない
+    pub fn is_enabled(&self, api_key: &str) -> bool {
+        Config::global().history.enabled && !self.disabled_keys.contains(api_key)
+    }
+
+    // 記録を止める場合はこれまでの閲覧履歴も削除する
+    pub fn set_enabled(&mut self, api_key: &str, enabled: bool) {
+        if enabled {
+            self.disabled_keys.remove(api_key);
+        } else {
+            self.disabled_keys.insert(api_key.to_string());
+            self.entries.remove(api_key);
+        }
+        self.file.mark_dirty();
+    }
+
+    // 同じURLを続けて表示した場合は1件にまとめる
+    pub fn record(&mut self, api_key: &str, entry: HistoryEntry) {
+        if !self.is_enabled(api_key) {
+            return;
+        }
+
+        let entries = self.entries.entry(api_key.to_string()).or_default();
+        if entries.last().is_some_and(|last| last.url == entry.url) {
+            entries.pop();
+        }
+        entries.push(entry);
+
+        let max_entries = Config::global().history.max_entries_per_key;
+        if entries.len() > max_entries {
+            let excess = entries.len() - max_entries;
+            entries.drain(..excess);
+        }
+        self.remove_expired();
+        self.file.mark_dirty();
+    }
+
+    // 新しいものから最大limit件
+    pub fn list(&self, api_key: &str, limit: usize) -> Vec<HistoryEntry> {
+        let cutoff = retention_cutoff();
+        self.entries
+            .get(api_key)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .rev()
+                    .filter(|entry| !is_expired(entry, cutoff))
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // 最後に表示したときのタイトル
+    pub fn title_for(&self, api_key: &str, url: &str) -> Option<String> {
+        self.entries.get(api_key)?.iter().rev().find(|entry| entry.url == url).map(|entry| entry.title.clone())
+    }
+
+    pub fn clear(&mut self, api_key: &str) {
+        if self.entries.remove(api_key).is_some() {
+            self.file.mark_dirty();
+        }
+    }
+
+    // 保持期間を過ぎた履歴を削除する
+    fn remove_expired(&mut self) {
+        let cutoff = retention_cutoff();
+        for entries in self.entries.values_mut() {
+            entries.retain(|entry| !is_expired(entry, cutoff));
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+    }
+}
+
+impl Persisted for HistoryStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+}
+
+// ========== ヘルパー関数 ==========
+
+fn history_file() -> JsonFile {
+    JsonFile::new(HISTORY_FILE)
+}
+
+// これより前に表示した履歴は期限切れ（期限なしの場合はNone）
+fn retention_cutoff() -> Option<DateTime<Utc>> {
+    match Config::global().history.retention_days {
+        0 => None,
+        days => Some(Utc::now() - Duration::days(days as i64)),
+    }
+}
+
+fn is_expired(entry: &HistoryEntry, cutoff: Option<DateTime<Utc>>) -> bool {
+    match (cutoff, DateTime::parse_from_rfc3339(&entry.visited_at)) {
+        (Some(cutoff), Ok(visited_at)) => visited_at.with_timezone(&Utc) < cutoff,
+        _ => false,
+    }
+}
+
+// ========== 型エイリアス ==========
+pub type SharedHistoryStore = Arc<RwLock<HistoryStore>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, visited_at: DateTime<Utc>) -> HistoryEntry {
+        HistoryEntry {
+            url: url.to_string(),
+            title: url.to_string(),
+            visited_at: visited_at.to_rfc3339(),
+            original_size_bytes: 100,
+            processed_size_bytes: 10,
+        }
+    }
+
+    fn urls(entries: Vec<HistoryEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.url).collect()
+    }
+
+    #[test]
+    fn entries_past_the_retention_period_are_dropped() {
+        let retention_days = Config::global().history.retention_days as i64;
+        let mut store = HistoryStore::new();
+        store.entries.insert(
+            "key1".to_string(),
+            vec![entry("https://example.com/old", Utc::now() - Duration::days(retention_days + 1))],
+        );
+        assert!(store.list("key1", 10).is_empty());
+
+        store.record("key1", entry("https://example.com/new", Utc::now()));
+        assert_eq!(store.entries["key1"].len(), 1);
+        assert_eq!(urls(store.list("key1", 10)), vec!["https://example.com/new"]);
+    }
+
+    #[test]
+    fn repeated_views_are_merged_and_listed_newest_first() {
+        let mut store = HistoryStore::new();
+        store.record("key1", entry("https://example.com/a", Utc::now()));
+        store.record("key1", entry("https://example.com/b", Utc::now()));
+        store.record("key1", entry("https://example.com/b", Utc::now()));
+        assert_eq!(urls(store.list("key1", 10)), vec!["https://example.com/b", "https://example.com/a"]);
+        assert!(store.list("key2", 10).is_empty());
+    }
+
+    #[test]
+    fn turning_history_off_clears_and_stops_recording() {
+        let mut store = HistoryStore::new();
+        store.record("key1", entry("https://example.com/a", Utc::now()));
+        store.record("key2", entry("https://example.com/a", Utc::now()));
+
+        store.set_enabled("key1", false);
+        assert!(!store.is_enabled("key1"));
+        assert!(store.list("key1", 10).is_empty());
+        store.record("key1", entry("https://example.com/b", Utc::now()));
+        assert!(store.list("key1", 10).is_empty());
+        assert_eq!(store.list("key2", 10).len(), 1);
+
+        store.set_enabled("key1", true);
+        store.record("key1", entry("https://example.com/c", Utc::now()));
+        assert_eq!(urls(store.list("key1", 10)), vec!["https://example.com/c"]);
+    }
+}
@@ -1,7 +1,7 @@
-use url::Url;
-use reader_mode_maker;
-use std::time::Duration;
 use htmlescape;
+use crate::culling::get_attribute;
+use crate::feed::find_feed_links;
+use crate::process_options::{FormMode, ImageMode, LinkStyle, ProcessOptions};
 
 // URLを正規化する関数（Rigil-Browserと同じ）
 pub fn normalize_url(name: &str) -> String {
@@ -49,7 +49,7 @@ pub fn get_base_url(url: &str) -> String {
 }
 
 // 相対URLを絶対URLに変換する関数（Rigil-Browserと同じ）
-fn resolve_relative_url(href: &str, base_url: &str, current_url: &str) -> String {
+pub fn resolve_relative_url(href: &str, base_url: &str, current_url: &str) -> String {
     if href.contains("http") {
         return href.to_string();
     }
@@ -80,7 +80,7 @@ fn resolve_relative_url(href: &str, base_url: &str, current_url: &str) -> String
 }
 
 // hrefを抽出する関数（Rigil-Browserと同じ）
-fn extract_href(tag: &str) -> String {
+pub fn extract_href(tag: &str) -> String {
     let tag_chars: Vec<char> = tag.chars().collect();
     let mut href = String::new();
     let mut i = 1;
@@ -100,11 +100,25 @@ fn extract_href(tag: &str) -> String {
     href
 }
 
+// ========== 定数 ==========
+// この数以上のリンクが本文をほとんど挟まずに並んでいればナビゲーションとみなす
+const NAV_CLUSTER_MIN_LINKS: usize = 8;
+// ナビゲーション内のリンク間に許容する本文の文字数
+const NAV_CLUSTER_MAX_GAP_CHARS: usize = 3;
+// 記事へのリンクとみなすリンクテキストの最小文字数
+const ARTICLE_LINK_MIN_CHARS: usize = 8;
+
+// 解析途中の断片（タグ・テキスト、またはリンク）
+enum Segment {
+    Markup(String),
+    Link { href: String, text: String },
+}
+
 // リンクタグを処理する関数（プロキシ用に修正）
-fn process_link_tag(tag: &str, contents: &[char], i: &mut usize, base_url: &str, current_url: &str) -> String {
+fn process_link_tag(tag: &str, contents: &[char], i: &mut usize, base_url: &str, current_url: &str) -> Option<Segment> {
     let href = extract_href(tag);
     if href.is_empty() {
-        return String::new();
+        return None;
     }
 
     let resolved_href = resolve_relative_url(&href, base_url, current_url);
@@ -132,6 +146,11 @@ fn process_link_tag(tag: &str, contents: &[char], i: &mut usize, base_url: &str,
                 break;
             } else if peek_tag.starts_with("<a ") || peek_tag == "<a>" {
                 // ネストしたaタグ（スキップ）
+            } else if peek_tag.to_lowercase().starts_with("<img") {
+                // 画像だけのリンクは代替テキストをリンクテキストにする
+                if let Some(alt) = get_attribute(&peek_tag, "alt") {
+                    link_content.push_str(&alt);
+                }
             }
 
             // タグをスキップ
@@ -144,66 +163,253 @@ fn process_link_tag(tag: &str, contents: &[char], i: &mut usize, base_url: &str,
     }
 
     // リンクテキストが空の場合はURLを使用
-    let mut display_text = if link_content.trim().is_empty() {
+    let display_text = if link_content.trim().is_empty() {
         resolved_href.clone()
     } else {
         link_content.trim().to_string()
     };
 
-    // 長いリンクテキストを短縮する（50文字以上の場合）
-    if display_text.chars().count() > 50 {
-        display_text = format!("{}...", display_text.chars().take(47).collect::<String>());
+    Some(Segment::Link { href: resolved_href, text: display_text })
+}
+
+// 長いリンクテキストを短縮する（プロファイルの上限を超える場合）
+fn shorten_link_text(text: &str, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        format!("{}...", text.chars().take(max_chars.saturating_sub(3)).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+// プロキシ経由でリンクを処理するように修正
+pub fn render_proxy_link(href: &str, text: &str, options: &ProcessOptions) -> String {
+    let display_text = shorten_link_text(text, options.profile.link_text_max_chars);
+
+    // リンクを書き換えないプロファイルでは元のURLへ直接リンクする
+    if !options.profile.rewrite_links {
+        return format!(
+            "<a href=\"{}\">{}</a>",
+            htmlescape::encode_minimal(href),
+            htmlescape::encode_minimal(&display_text)
+        );
     }
 
-    // プロキシ経由でリンクを処理するように修正
     format!(
-        "<a href=\"/proxy?url={}\" title=\"{}\">{}</a>",
-        urlencoding::encode(&resolved_href),
-        htmlescape::encode_minimal(&resolved_href),
+        "<a href=\"/proxy?url={}{}\" title=\"{}\">{}</a>",
+        urlencoding::encode(href),
+        htmlescape::encode_minimal(&options.to_query()),
+        htmlescape::encode_minimal(href),
         htmlescape::encode_minimal(&display_text)
     )
 }
 
-// HTMLを解析してテキストに変換する関数（Rigil-Browserと同じ）
-pub fn parse_html_to_text(html: &str, base_url: &str, current_url: &str) -> String {
-    let mut formatted_text = String::new();
+// 属性値用のエスケープ（encode_attributeより出力が小さい）
+pub fn escape_attribute(value: &str) -> String {
+    htmlescape::encode_minimal(value).replace('"', "&quot;")
+}
 
-    // 基本的なHTMLヘッダーを追加
-    let javascript_code = r#"<script>
-function addApiKeyToProxyLinks(){
-    // URLパラメータからAPIキーを取得
-    const urlParams=new URLSearchParams(window.location.search);
-    let apiKey=urlParams.get('api_key');
+// 画像タグを画像モードに応じたHTMLに置き換える
+fn render_image_tag(tag: &str, base_url: &str, current_url: &str, options: &ProcessOptions) -> String {
+    let alt = get_attribute(tag, "alt").map(|a| a.trim().to_string()).unwrap_or_default();
+    let placeholder = if alt.is_empty() {
+        "[画像]".to_string()
+    } else {
+        format!("[画像: {}]", alt)
+    };
 
-    // URLパラメータにない場合はLocalStorageから取得
-    if(!apiKey){
-        apiKey=localStorage.getItem('rigil_api_key');
+    // data: URIは縮小の対象にしない
+    let src = get_attribute(tag, "src")
+        .filter(|src| !src.is_empty() && !src.starts_with("data:"))
+        .map(|src| resolve_relative_url(&src, base_url, current_url));
+
+    match (options.images, src) {
+        (ImageMode::Link, Some(src)) => format!(
+            "<a href=\"/image?url={}\" title=\"{}\">{}</a>",
+            urlencoding::encode(&src),
+            htmlescape::encode_minimal(&src),
+            htmlescape::encode_minimal(&placeholder)
+        ),
+        // APIキーはページ読み込み後にスクリプトで付与してから読み込む
+        (ImageMode::Thumb, Some(src)) => format!(
+            "<img data-rigil-src=\"/image?url={}\" alt=\"{}\">",
+            urlencoding::encode(&src),
+            escape_attribute(&placeholder)
+        ),
+        (ImageMode::None, _) => String::new(),
+        _ => htmlescape::encode_minimal(&placeholder),
     }
+}
 
-    if(apiKey){
-        const proxyLinks=document.querySelectorAll('a[href^="/proxy?url="]');
-        proxyLinks.forEach(link=>{
-            const href=link.getAttribute('href');
-            if(href&&!href.includes('api_key=')){
-                link.setAttribute('href',href+'&api_key='+encodeURIComponent(apiKey));
+// フォームタグをプロキシ経由で送信するよう書き換える
+// （GETフォームは/form、POSTフォームはPOST /proxyへ送信する）
+// 送信できないフォーム（ファイル送信、JavaScript等）の場合はNoneを返す
+fn render_form_tag(tag: &str, base_url: &str, current_url: &str, options: &ProcessOptions) -> Option<String> {
+    let method = get_attribute(tag, "method").unwrap_or_default().to_lowercase();
+    let is_post = match method.as_str() {
+        "" | "get" => false,
+        "post" => true,
+        _ => return None,
+    };
+    if options.forms == FormMode::None || (is_post && options.forms != FormMode::Post) {
+        return None;
+    }
+    // POSTはapplication/x-www-form-urlencodedとして転送するため、ファイル送信等は扱えない
+    let enctype = get_attribute(tag, "enctype").unwrap_or_default().to_lowercase();
+    if is_post && !(enctype.is_empty() || enctype == "application/x-www-form-urlencoded") {
+        return None;
+    }
+
+    let action = get_attribute(tag, "action").unwrap_or_default();
+    let resolved_action = if action.trim().is_empty() {
+        current_url.to_string()
+    } else {
+        resolve_relative_url(htmlescape::decode_html(&action).unwrap_or(action).trim(), base_url, current_url)
+    };
+    if !resolved_action.starts_with("http") {
+        return None;
+    }
+
+    if is_post {
+        return Some(format!(
+            "<form method=\"post\" action=\"/proxy?url={}{}\">",
+            urlencoding::encode(&resolved_action),
+            escape_attribute(&options.to_query())
+        ));
+    }
+
+    let mut form = format!(
+        "<form method=\"get\" action=\"/form\"><input type=\"hidden\" name=\"rigil_action\" value=\"{}\">",
+        escape_attribute(&resolved_action)
+    );
+    for (name, value) in options.to_pairs() {
+        form.push_str(&format!(
+            "<input type=\"hidden\" name=\"rigil_{}\" value=\"{}\">",
+            name,
+            escape_attribute(&value)
+        ));
+    }
+    Some(form)
+}
+
+// 送信できないフォームの入力欄（select等は閉じタグまで）を読み飛ばす
+fn skip_until_closing(contents: &[char], i: &mut usize, closing: &str) {
+    let closing: Vec<char> = closing.chars().collect();
+    while *i < contents.len() {
+        let matches = contents.len() >= *i + closing.len()
+            && contents[*i..*i + closing.len()]
+                .iter()
+                .zip(&closing)
+                .all(|(a, b)| a.to_ascii_lowercase() == *b);
+        if matches {
+            *i += closing.len();
+            return;
+        }
+        *i += 1;
+    }
+}
+
+// 送信できないフォームの中で捨てる入力欄か（中身ごと捨てる場合は閉じタグを返す）
+fn form_control_closing(tag_lower: &str) -> Option<Option<&'static str>> {
+    if tag_lower.starts_with("<input") {
+        Some(None)
+    } else if tag_lower.starts_with("<select") {
+        Some(Some("</select>"))
+    } else if tag_lower.starts_with("<textarea") {
+        Some(Some("</textarea>"))
+    } else if tag_lower.starts_with("<button") {
+        Some(Some("</button>"))
+    } else {
+        None
+    }
+}
+
+// タグを除いた表示文字数
+fn visible_text_len(markup: &str) -> usize {
+    if markup.starts_with('<') {
+        0
+    } else {
+        markup.trim().chars().count()
+    }
+}
+
+// 本文をほとんど挟まずに連続するリンクの塊（メニュー、フッター等）を除去する
+fn drop_navigation_clusters(segments: Vec<Segment>) -> Vec<Segment> {
+    let mut result = Vec::with_capacity(segments.len());
+    let mut cluster: Vec<Segment> = Vec::new();
+    let mut cluster_links = 0;
+    let mut gap_chars = 0;
+
+    let flush = |cluster: &mut Vec<Segment>, cluster_links: usize, result: &mut Vec<Segment>| {
+        if cluster_links >= NAV_CLUSTER_MIN_LINKS {
+            // リンクと、リンク間のタグだけの断片を捨てる（末尾のタグは残す）
+            let last_link = cluster.iter().rposition(|s| matches!(s, Segment::Link { .. })).unwrap_or(0);
+            let tail = cluster.split_off(last_link + 1);
+            result.extend(cluster.drain(..).filter(|s| match s {
+                Segment::Markup(markup) => visible_text_len(markup) > 0,
+                Segment::Link { .. } => false,
+            }));
+            result.extend(tail);
+        } else {
+            result.append(cluster);
+        }
+    };
+
+    for segment in segments {
+        match &segment {
+            Segment::Link { .. } => {
+                cluster_links += 1;
+                gap_chars = 0;
+                cluster.push(segment);
             }
-        });
+            Segment::Markup(markup) => {
+                gap_chars += visible_text_len(markup);
+                if cluster_links > 0 && gap_chars <= NAV_CLUSTER_MAX_GAP_CHARS {
+                    cluster.push(segment);
+                } else {
+                    flush(&mut cluster, cluster_links, &mut result);
+                    cluster_links = 0;
+                    result.push(segment);
+                }
+            }
+        }
     }
+    flush(&mut cluster, cluster_links, &mut result);
+
+    result
 }
-document.addEventListener('DOMContentLoaded',addApiKeyToProxyLinks);
-//addApiKeyToProxyLinks();
-</script>"#;
 
-    formatted_text.push_str("<!DOCTYPE html><html><head><meta charset=\"UTF-8\"><style>body{font-family:'Segoe UI',Tahoma,Geneva,Verdana,sans-serif;line-height:1.6;margin:20px;color:#333;background-color:#fafafa;max-width:100%;overflow-x:auto;} a{color:#666;text-decoration:underline;margin-right:8px;word-break:break-word;max-width:100%;display:inline-block;} a:hover{color:#333;}</style>");
-    formatted_text.push_str(javascript_code);
-    formatted_text.push_str("</head><body>");
+fn is_heading_tag(markup: &str) -> bool {
+    let lower = markup.to_lowercase();
+    lower.len() > 2 && lower.starts_with("<h") && lower.as_bytes()[2].is_ascii_digit()
+}
+
+fn render_footnote_list(footnotes: &mut Vec<(usize, String, String)>, options: &ProcessOptions, output: &mut String) {
+    if footnotes.is_empty() {
+        return;
+    }
 
-    let culled_html = reader_mode_maker::culling(html);
+    output.push_str("<div class=\"rigil-links\">");
+    for (number, href, text) in footnotes.drain(..) {
+        output.push_str(&format!("[{}] {}<br>", number, render_proxy_link(&href, &text, options)));
+    }
+    output.push_str("</div>");
+}
+
+// カリング済みHTMLをタグ・テキストとリンクの断片に分ける
+fn collect_segments(culled_html: &str, base_url: &str, current_url: &str, options: &ProcessOptions) -> Vec<Segment> {
     let contents: Vec<char> = culled_html.chars().collect();
+    let mut segments = Vec::new();
+    let mut text = String::new();
     let mut i = 0;
+    // 送信できないフォームの中か（フォームタグと入力欄だけを捨て、中の本文は残す）
+    let mut in_dropped_form = false;
 
     while i < contents.len() {
         if contents[i] == '<' {
+            if !text.is_empty() {
+                segments.push(Segment::Markup(std::mem::take(&mut text)));
+            }
+
             let mut tag = String::new();
 
             // タグを読み取り
@@ -217,80 +423,218 @@ document.addEventListener('DOMContentLoaded',addApiKeyToProxyLinks);
 
             // タグの種類に応じて処理
             let tag_lower = tag.to_lowercase();
+            if in_dropped_form {
+                if tag_lower.starts_with("</form") {
+                    in_dropped_form = false;
+                    continue;
+                }
+                match form_control_closing(&tag_lower) {
+                    Some(Some(closing)) => {
+                        skip_until_closing(&contents, &mut i, closing);
+                        continue;
+                    }
+                    Some(None) => continue,
+                    None => {}
+                }
+            }
             if tag_lower.contains("<a ") || tag_lower == "<a>" {
-                let link_html = process_link_tag(&tag, &contents, &mut i, base_url, current_url);
-                if !link_html.is_empty() {
-                    formatted_text.push_str(&link_html);
+                if let Some(link) = process_link_tag(&tag, &contents, &mut i, base_url, current_url) {
+                    segments.push(link);
+                }
+            } else if tag_lower.starts_with("<img") {
+                segments.push(Segment::Markup(render_image_tag(&tag, base_url, current_url, options)));
+            } else if tag_lower.starts_with("<form") {
+                match render_form_tag(&tag, base_url, current_url, options) {
+                    Some(form) => segments.push(Segment::Markup(form)),
+                    None => in_dropped_form = true,
                 }
             } else {
-                formatted_text.push_str(&tag);
+                segments.push(Segment::Markup(tag));
             }
         } else {
             // 通常のテキスト
-            formatted_text.push(contents[i]);
+            text.push(contents[i]);
             i += 1;
         }
     }
+    if !text.is_empty() {
+        segments.push(Segment::Markup(text));
+    }
 
-    formatted_text.push_str("</body></html>");
-    formatted_text
+    segments
 }
 
-// HTMLを取得する関数（非同期版）
-pub async fn get_html(url: &str) -> Result<(String, String), String> {
-    // タイムアウト設定を含むHTTPクライアントを作成
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))  // 30秒のタイムアウト
-        .redirect(reqwest::redirect::Policy::limited(10))  // 最大10回のリダイレクト
-        .build()
-        .map_err(|e| format!("HTTPクライアントの作成エラー: {}", e))?;
-
-    // URLからクエリパラメータを分離
-    let parsed_url = match Url::parse(url) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("URL解析エラー: {}", e)),
-    };
+// ページ内の記事へのリンク（同じサイト内の、ナビゲーション以外でテキストが十分に長いもの）を (URL, テキスト) で返す
+pub fn extract_article_links(html: &str, base_url: &str, current_url: &str, options: &ProcessOptions) -> Vec<(String, String)> {
+    let culled_html = options.cull(html);
+    let segments = drop_navigation_clusters(collect_segments(&culled_html, base_url, current_url, options));
+    let page_url = url::Url::parse(current_url).ok();
+    let mut links: Vec<(String, String)> = Vec::new();
+
+    for segment in segments {
+        let (href, text) = match segment {
+            Segment::Link { href, text } => (href, text),
+            Segment::Markup(_) => continue,
+        };
+        let mut link_url = match url::Url::parse(&href) {
+            Ok(link_url) if link_url.scheme() == "http" || link_url.scheme() == "https" => link_url,
+            _ => continue,
+        };
+        link_url.set_fragment(None);
+
+        let same_site = page_url.as_ref().map(|page| page.host_str() == link_url.host_str()).unwrap_or(false);
+        let is_current_page = page_url.as_ref().map(|page| page.path() == link_url.path() && page.query() == link_url.query()).unwrap_or(false);
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !same_site || is_current_page || text.chars().count() < ARTICLE_LINK_MIN_CHARS {
+            continue;
+        }
 
-    let base_url = format!("{}://{}{}", parsed_url.scheme(), parsed_url.host_str().unwrap_or(""), parsed_url.path());
-    let query_pairs: Vec<(String, String)> = parsed_url.query_pairs().into_owned().collect();
+        let link_url = link_url.to_string();
+        if !links.iter().any(|(existing, _)| *existing == link_url) {
+            links.push((link_url, htmlescape::decode_html(&text).unwrap_or(text)));
+        }
+    }
 
-    println!("HTMLを取得中: {}", url);
+    links
+}
 
-    match client.get(&base_url).query(&query_pairs).send().await {
-        Ok(response) => {
-            // ステータスコードをチェック
-            if !response.status().is_success() {
-                return Err(format!("HTTPエラー: {} - {}", response.status(), response.status().canonical_reason().unwrap_or("不明なエラー")));
-            }
+// 軽量化ページ共通のヘッダー（スタイルと、プロキシ経由のリンクにAPIキーを付けるスクリプト）
+fn page_header() -> String {
+    let mut header = String::new();
 
-            // リダイレクト後の最終URLを取得
-            let final_url = response.url().to_string();
-            println!("最終URL: {}", final_url);
-
-            match response.text().await {
-                Ok(text) => {
-                    println!("HTML取得完了: {} bytes", text.len());
-                    Ok((text, final_url))
-                },
-                Err(e) => {
-                    if e.is_timeout() {
-                        Err("タイムアウトエラー: レスポンスの読み取りに時間がかかりすぎました".to_string())
-                    } else {
-                        Err(format!("レスポンス読み取りエラー: {}", e))
-                    }
-                }
+    let javascript_code = r#"<script>
+function addApiKeyToProxyLinks(){
+    // URLパラメータからAPIキーを取得
+    const urlParams=new URLSearchParams(window.location.search);
+    let apiKey=urlParams.get('api_key');
+
+    // URLパラメータにない場合はLocalStorageから取得
+    if(!apiKey){
+        apiKey=localStorage.getItem('rigil_api_key');
+    }
+
+    if(apiKey){
+        const proxyLinks=document.querySelectorAll('a[href^="/proxy?url="],a[href^="/image?url="]');
+        proxyLinks.forEach(link=>{
+            const href=link.getAttribute('href');
+            if(href&&!href.includes('api_key=')){
+                link.setAttribute('href',href+'&api_key='+encodeURIComponent(apiKey));
             }
+        });
+
+        // プロキシ経由のフォームにAPIキーを追加
+        document.querySelectorAll('form[action="/form"],form[method="post"][action^="/proxy?url="],form[method="post"][action^="/save?view="]').forEach(form=>{
+            const input=document.createElement('input');
+            input.type='hidden';
+            input.name='rigil_api_key';
+            input.value=apiKey;
+            form.appendChild(input);
+        });
+
+        // 縮小画像はAPIキーを付けてから読み込む
+        document.querySelectorAll('img[data-rigil-src]').forEach(img=>{
+            img.setAttribute('src',img.getAttribute('data-rigil-src')+'&api_key='+encodeURIComponent(apiKey));
+        });
+    }
+}
+document.addEventListener('DOMContentLoaded',addApiKeyToProxyLinks);
+//addApiKeyToProxyLinks();
+</script>"#;
+
+    header.push_str("<!DOCTYPE html><html><head><meta charset=\"UTF-8\"><style>body{font-family:'Segoe UI',Tahoma,Geneva,Verdana,sans-serif;line-height:1.6;margin:20px;color:#333;background-color:#fafafa;max-width:100%;overflow-x:auto;} a{color:#666;text-decoration:underline;margin-right:8px;word-break:break-word;max-width:100%;display:inline-block;} a:hover{color:#333;} .rigil-links{font-size:small;margin:8px 0;padding-top:4px;border-top:1px solid #ddd;} table{border-collapse:collapse;display:block;overflow-x:auto;} th,td{border:1px solid #ccc;padding:2px 6px;} pre{background:#f0f0f0;padding:8px;overflow-x:auto;white-space:pre;} code{font-family:monospace;} blockquote{margin:8px 0;padding-left:12px;border-left:3px solid #ccc;color:#555;} img{max-width:100%;} form{margin:8px 0;} input,select,textarea{max-width:100%;}</style>");
+    header.push_str(javascript_code);
+    header.push_str("</head><body>");
+    header
+}
+
+// 保存ボタンの前後の印（保存したページを表示する際に置き換える）
+pub const SAVE_FORM_START: &str = "<!--rigil-save-->";
+pub const SAVE_FORM_END: &str = "<!--/rigil-save-->";
+
+// ブラウザで表示するページの先頭にページを保存するボタンを加える
+// （view_idは表示したページの控えのID。APIキーはページ読み込み後にスクリプトで付与する）
+pub fn add_save_form(page_html: &str, view_id: &str) -> String {
+    let form = format!(
+        "{}<form method=\"post\" action=\"/save?view={}\" class=\"rigil-links\"><button type=\"submit\">このページを保存</button></form>{}",
+        SAVE_FORM_START,
+        urlencoding::encode(view_id),
+        SAVE_FORM_END
+    );
+    page_html.replacen("<body>", &format!("<body>{}", form), 1)
+}
+
+// 本文を軽量化ページとして出力する（HTML以外のコンテンツの表示に使う）
+pub fn render_lightweight_page(body: &str) -> String {
+    format!("{}{}</body></html>", page_header(), body)
+}
+
+// HTMLを解析してテキストに変換する関数（Rigil-Browserと同じ）
+pub fn parse_html_to_text(html: &str, base_url: &str, current_url: &str, options: &ProcessOptions) -> String {
+    // 基本的なHTMLヘッダーを追加
+    let mut formatted_text = page_header();
+
+    // ページがフィードを提供していれば先頭にリンクを表示する
+    let feed_links = find_feed_links(html, base_url, current_url);
+    if !feed_links.is_empty() {
+        formatted_text.push_str("<p class=\"rigil-links\">フィードがあります: ");
+        for (href, title) in &feed_links {
+            formatted_text.push_str(&render_proxy_link(href, title, options));
         }
-        Err(e) => {
-            if e.is_timeout() {
-                Err("タイムアウトエラー: サーバーからの応答に時間がかかりすぎました".to_string())
-            } else if e.is_connect() {
-                Err("接続エラー: サーバーに接続できません".to_string())
-            } else if e.is_request() {
-                Err("リクエストエラー: 不正なリクエストです".to_string())
-            } else {
-                Err(format!("ネットワークエラー: {}", e))
+        formatted_text.push_str("</p>");
+    }
+
+    let culled_html = options.cull(html);
+    let mut segments = collect_segments(&culled_html, base_url, current_url, options);
+
+    if options.drop_nav_links {
+        segments = drop_navigation_clusters(segments);
+    }
+
+    let mut footnotes: Vec<(usize, String, String)> = Vec::new();
+    let mut link_number = 0;
+
+    for segment in segments {
+        match segment {
+            Segment::Markup(markup) => {
+                if options.link_style == LinkStyle::SectionFootnotes && is_heading_tag(&markup) {
+                    render_footnote_list(&mut footnotes, options, &mut formatted_text);
+                }
+                formatted_text.push_str(&markup);
             }
+            Segment::Link { href, text } => match options.link_style {
+                LinkStyle::Inline => formatted_text.push_str(&render_proxy_link(&href, &text, options)),
+                LinkStyle::Footnotes | LinkStyle::SectionFootnotes => {
+                    link_number += 1;
+                    formatted_text.push_str(&format!("{}<sup>[{}]</sup>", htmlescape::encode_minimal(&text), link_number));
+                    footnotes.push((link_number, href, text));
+                }
+            },
         }
     }
+    render_footnote_list(&mut footnotes, options, &mut formatted_text);
+
+    formatted_text.push_str("</body></html>");
+    formatted_text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsendable_form_keeps_its_text() {
+        // ASP.NET等はページ全体をPOSTフォームで囲む
+        let html = "<html><body><form method=\"post\" action=\"/default.aspx\"><input type=\"hidden\" name=\"__VIEWSTATE\" value=\"x\">\
+            <h1>お知らせ</h1><p>本文です</p><select name=\"s\"><option>選択肢</option></select><button>送信</button></form><p>後ろ</p></body></html>";
+        let options = ProcessOptions { forms: FormMode::Get, ..ProcessOptions::default() };
+        let output = parse_html_to_text(html, "https://example.com", "https://example.com/default.aspx", &options);
+
+        assert!(output.contains("お知らせ"));
+        assert!(output.contains("本文です"));
+        assert!(output.contains("後ろ"));
+        assert!(!output.contains("選択肢"));
+        assert!(!output.contains("送信"));
+        assert!(!output.contains("__VIEWSTATE"));
+        assert!(!output.contains("<form"));
+    }
 }
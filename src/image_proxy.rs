@@ -0,0 +1,62 @@
+use crate::config::ImageConfig;
+use crate::persist::run_blocking;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::DynamicImage;
+
+// 画像を縮小・グレースケール化して低画質で再エンコードする
+
+// ========== 定数 ==========
+const MIN_JPEG_QUALITY: u8 = 10;
+const JPEG_QUALITY_STEP: u8 = 10;
+const MIN_DIMENSION: u32 = 16;
+
+// ========== 縮小処理 ==========
+
+pub async fn make_thumbnail(source: Vec<u8>, config: &'static ImageConfig) -> Result<Vec<u8>, String> {
+    run_blocking(move || shrink_image(&source, config))
+        .await
+        .map_err(|e| format!("画像の縮小処理が中断されました: {}", e))?
+}
+
+// 上限バイト数に収まるまで画質、次いでサイズを下げる
+fn shrink_image(source: &[u8], config: &ImageConfig) -> Result<Vec<u8>, String> {
+    let original = image::load_from_memory(source)
+        .map_err(|e| format!("画像の読み込みに失敗しました: {}", e))?;
+
+    let mut width = config.max_width.max(MIN_DIMENSION);
+    let mut height = config.max_height.max(MIN_DIMENSION);
+
+    loop {
+        let thumbnail = original.thumbnail(width, height).grayscale();
+
+        let encoded = encode_jpeg_within(&thumbnail, config)?;
+
+        if encoded.len() <= config.max_bytes || width <= MIN_DIMENSION || height <= MIN_DIMENSION {
+            return Ok(encoded);
+        }
+
+        width /= 2;
+        height /= 2;
+    }
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, quality)
+        .encode_image(image)
+        .map_err(|e| format!("JPEGエンコードエラー: {}", e))?;
+    Ok(buffer)
+}
+
+// 上限に収まる最も高い画質でエンコードする（収まらなければ最低画質の結果を返す）
+fn encode_jpeg_within(image: &DynamicImage, config: &ImageConfig) -> Result<Vec<u8>, String> {
+    let mut quality = config.jpeg_quality.clamp(MIN_JPEG_QUALITY, 100);
+    loop {
+        let encoded = encode_jpeg(image, quality)?;
+        if encoded.len() <= config.max_bytes || quality <= MIN_JPEG_QUALITY {
+            return Ok(encoded);
+        }
+        quality = quality.saturating_sub(JPEG_QUALITY_STEP).max(MIN_JPEG_QUALITY);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::api_types::{ApiResponse, JobInfo};
+use crate::app_state::AppState;
+use crate::config::Config;
+use crate::fetcher::{guarded_client_builder, validate_direct_url};
+use crate::handlers::process_url_for_api;
+use crate::persist::{self, random_hex_id, JsonFile, Persisted};
+use crate::process_options::ProcessOptions;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, RwLock};
+
+// 非同期ジョブ（バックグラウンドのワーカーで処理し、結果を保持・コールバックで通知する）
+
+// ========== 定数 ==========
+const JOBS_FILE: &str = "jobs.json";
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+// 保持期間を過ぎたジョブを削除する間隔
+const PURGE_INTERVAL: Duration = Duration::from_secs(600);
+// コールバックの署名ヘッダー（値は "sha256=" + HMAC-SHA256(APIキー, ボディ) の16進数）
+pub const SIGNATURE_HEADER: &str = "X-Rigil-Signature";
+
+// ========== データ構造 ==========
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Job {
+    pub id: String,
+    // ジョブを作成したAPIキー（同じキーからのみ参照できる）
+    pub api_key: String,
+    pub url: String,
+    pub status: JobStatus,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub callback_url: Option<String>,
+    // コールバックの送信結果（例: "delivered (200)"、"failed: ..."）
+    pub callback_status: Option<String>,
+    pub result: Option<ApiResponse>,
+}
+
+impl Job {
+    pub fn info(&self) -> JobInfo {
+        JobInfo {
+            id: self.id.clone(),
+            url: self.url.clone(),
+            status: self.status,
+            created_at: self.created_at.clone(),
+            finished_at: self.finished_at.clone(),
+            callback_url: self.callback_url.clone(),
+            callback_status: self.callback_status.clone(),
+            result: self.result.clone(),
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(self.status, JobStatus::Queued | JobStatus::Running)
+    }
+
+    fn is_expired(&self, retention: chrono::Duration) -> bool {
+        self.finished_at
+            .as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| at.with_timezone(&Utc) + retention < Utc::now())
+            .unwrap_or(false)
+    }
+}
+
+// ワーカーへ渡す処理内容（軽量化オプションは保存しない）
+pub struct JobTask {
+    pub id: String,
+    pub api_key: String,
+    pub url: String,
+    pub options: ProcessOptions,
+    pub accept_language: Option<String>,
+    pub callback_url: Option<String>,
+}
+
+// ========== ジョブストア ==========
+pub struct JobStore {
+    jobs: HashMap<String, Job>,
+    file: JsonFile,
+}
+
+impl JobStore {
+    pub fn new() -> Self {
+        Self {
+            jobs: HashMap::new(),
+            file: JsonFile::new(JOBS_FILE),
+        }
+    }
+
+    // 再起動で処理できなくなった未完了のジョブは失敗として扱う
+    pub fn load_from_file() -> Self {
+        let mut store = Self::new();
+        let mut jobs: HashMap<String, Job> = match store.file.load() {
+            Some(jobs) => jobs,
+            None => return store,
+        };
+        for job in jobs.values_mut().filter(|job| job.is_pending()) {
+            job.status = JobStatus::Failed;
+            job.finished_at = Some(Utc::now().to_rfc3339());
+            job.callback_status = Some("サーバーの再起動により中断されました".to_string());
+            store.file.mark_dirty();
+        }
+
+        store.jobs = jobs;
+        store.remove_expired_jobs();
+        store
+    }
+
+    // 待機中・処理中のジョブの数（APIキーごとの上限の確認に使う）
+    pub fn pending_count_for(&self, api_key: &str) -> usize {
+        self.jobs.values().filter(|job| job.api_key == api_key && job.is_pending()).count()
+    }
+
+    pub fn create(&mut self, api_key: &str, url: &str, callback_url: Option<String>) -> Job {
+        let job = Job {
+            id: generate_job_id(),
+            api_key: api_key.to_string(),
+            url: url.to_string(),
+            status: JobStatus::Queued,
+            created_at: Utc::now().to_rfc3339(),
+            finished_at: None,
+            callback_url,
+            callback_status: None,
+            result: None,
+        };
+        self.jobs.insert(job.id.clone(), job.clone());
+        self.file.mark_dirty();
+        job
+    }
+
+    // キューに入れられなかったジョブを取り消す
+    pub fn remove(&mut self, id: &str) {
+        if self.jobs.remove(id).is_some() {
+            self.file.mark_dirty();
+        }
+    }
+
+    // APIキーが作成したジョブのみ返す（削除前でも保持期間を過ぎたものは返さない）
+    pub fn get(&self, id: &str, api_key: &str) -> Option<Job> {
+        let retention = retention();
+        self.jobs
+            .get(id)
+            .filter(|job| job.api_key == api_key && !job.is_expired(retention))
+            .cloned()
+    }
+
+    fn set_running(&mut self, id: &str) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            job.status = JobStatus::Running;
+            self.file.mark_dirty();
+        }
+    }
+
+    fn set_result(&mut self, id: &str, result: ApiResponse) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            job.status = if result.success { JobStatus::Completed } else { JobStatus::Failed };
+            job.finished_at = Some(Utc::now().to_rfc3339());
+            job.result = Some(result);
+            self.file.mark_dirty();
+        }
+    }
+
+    fn set_callback_status(&mut self, id: &str, callback_status: String) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            job.callback_status = Some(callback_status);
+            self.file.mark_dirty();
+        }
+    }
+
+    fn remove_expired_jobs(&mut self) {
+        let retention = retention();
+        let before = self.jobs.len();
+        self.jobs.retain(|_, job| !job.is_expired(retention));
+        if self.jobs.len() != before {
+            self.file.mark_dirty();
+        }
+    }
+}
+
+impl Persisted for JobStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.jobs).map_err(|e| e.to_string())
+    }
+}
+
+fn retention() -> chrono::Duration {
+    chrono::Duration::hours(Config::global().jobs.retention_hours as i64)
+}
+
+// ========== ジョブキュー ==========
+pub struct JobQueue {
+    pub store: Arc<RwLock<JobStore>>,
+    sender: mpsc::UnboundedSender<JobTask>,
+}
+
+impl JobQueue {
+    // キューと、ワーカーに渡す受信側を作る
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<JobTask>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queue = Self {
+            store: Arc::new(RwLock::new(JobStore::load_from_file())),
+            sender,
+        };
+        (queue, receiver)
+    }
+
+    pub fn enqueue(&self, task: JobTask) -> Result<(), String> {
+        self.sender.send(task).map_err(|_| "ジョブキューが停止しています".to_string())
+    }
+}
+
+// ========== ワーカー ==========
+
+// ワーカーと、ストアの書き出し・保持期間を過ぎたジョブの削除を起動する
+pub fn start_workers(receiver: mpsc::UnboundedReceiver<JobTask>, state: AppState) {
+    persist::start_flusher(state.jobs.store.clone());
+
+    let store = state.jobs.store.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            store.write().await.remove_expired_jobs();
+        }
+    });
+
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..Config::global().jobs.workers.max(1) {
+        let receiver = receiver.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                // 受信側のロックは次のジョブを取り出す間だけ保持する
+                let task = receiver.lock().await.recv().await;
+                match task {
+                    Some(task) => run_job(task, &state).await,
+                    None => break,
+                }
+            }
+        });
+    }
+}
+
+async fn run_job(task: JobTask, state: &AppState) {
+    state.jobs.store.write().await.set_running(&task.id);
+
+    let context = state.fetch_context(&task.api_key, &task.options, task.accept_language.as_deref()).await;
+    let (_, result) = process_url_for_api(&task.url, &task.api_key, state, &task.options, &context).await;
+    state.jobs.store.write().await.set_result(&task.id, result.clone());
+
+    if let Some(callback_url) = &task.callback_url {
+        let callback_status = match deliver_callback(callback_url, &task.id, &task.api_key, &result).await {
+            Ok(status) => format!("delivered ({})", status.as_u16()),
+            Err(e) => format!("failed: {}", e),
+        };
+        state.jobs.store.write().await.set_callback_status(&task.id, callback_status);
+    }
+}
+
+// ========== コールバック ==========
+
+// 結果のApiResponseをコールバックURLへPOSTする
+// プロキシは使わず直接送り、内部ネットワークへの送信は接続するアドレスで制限する（取得と同じ設定）
+async fn deliver_callback(callback_url: &str, job_id: &str, api_key: &str, result: &ApiResponse) -> Result<reqwest::StatusCode, String> {
+    let url = url::Url::parse(callback_url).map_err(|e| e.to_string())?;
+    validate_direct_url(&url).map_err(|e| e.to_string())?;
+
+    let client = guarded_client_builder()
+        .no_proxy()
+        .timeout(CALLBACK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| e.to_string())?;
+    post_callback(&client, url, job_id, api_key, result).await
+}
+
+async fn post_callback(client: &reqwest::Client, url: url::Url, job_id: &str, api_key: &str, result: &ApiResponse) -> Result<reqwest::StatusCode, String> {
+    let body = serde_json::to_string(result).map_err(|e| e.to_string())?;
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Rigil-Job-Id", job_id)
+        .header(SIGNATURE_HEADER, sign_body(api_key, &body))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status().is_success() {
+        Ok(response.status())
+    } else {
+        Err(format!("HTTP {}", response.status().as_u16()))
+    }
+}
+
+fn sign_body(api_key: &str, body: &str) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, api_key.as_bytes());
+    let tag = ring::hmac::sign(&key, body.as_bytes());
+    let hex: String = tag.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+// ========== ヘルパー関数 ==========
+
+fn generate_job_id() -> String {
+    random_hex_id(16)
+}
+
+// ========== 型エイリアス ==========
+pub type SharedJobQueue = Arc<JobQueue>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use tokio::sync::oneshot;
+
+    // 受け取ったリクエストのヘッダーとボディを返す
+    type Received = (HashMap<String, String>, String);
+
+    // 1件のリクエストを受けてstatusで応答するローカルのHTTPサーバー
+    async fn start_receiver(status: u16) -> (url::Url, oneshot::Receiver<Received>) {
+        let (sender, receiver) = oneshot::channel();
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        let make_service = make_service_fn(move |_| {
+            let sender = sender.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let sender = sender.clone();
+                    async move {
+                        let headers = req
+                            .headers()
+                            .iter()
+                            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().to_string()))
+                            .collect();
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        if let Some(sender) = sender.lock().await.take() {
+                            let _ = sender.send((headers, String::from_utf8_lossy(&body).to_string()));
+                        }
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = hyper::StatusCode::from_u16(status).unwrap();
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = url::Url::parse(&format!("http://{}/hook", server.local_addr())).unwrap();
+        tokio::spawn(server);
+        (url, receiver)
+    }
+
+    fn sample_result() -> ApiResponse {
+        ApiResponse {
+            success: true,
+            data: Some("<p>本文</p>".to_string()),
+            error: None,
+            original_url: Some("https://example.com/".to_string()),
+            processed_at: Utc::now().to_rfc3339(),
+            original_size_bytes: Some(100),
+            processed_size_bytes: Some(12),
+        }
+    }
+
+    #[tokio::test]
+    async fn callback_posts_signed_result() {
+        let (url, received) = start_receiver(204).await;
+        let client = reqwest::Client::new();
+
+        let status = post_callback(&client, url, "job1", "secret", &sample_result()).await.unwrap();
+        assert_eq!(status.as_u16(), 204);
+
+        let (headers, body) = received.await.unwrap();
+        assert_eq!(headers["content-type"], "application/json");
+        assert_eq!(headers["x-rigil-job-id"], "job1");
+        assert_eq!(headers["x-rigil-signature"], sign_body("secret", &body));
+        let posted: ApiResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(posted.data.as_deref(), Some("<p>本文</p>"));
+    }
+
+    #[tokio::test]
+    async fn callback_reports_error_status() {
+        let (url, _received) = start_receiver(500).await;
+        let error = post_callback(&reqwest::Client::new(), url, "job1", "secret", &sample_result()).await.unwrap_err();
+        assert_eq!(error, "HTTP 500");
+    }
+
+    #[tokio::test]
+    async fn callback_to_loopback_is_blocked() {
+        let (url, _received) = start_receiver(204).await;
+        assert!(deliver_callback(url.as_str(), "job1", "secret", &sample_result()).await.is_err());
+    }
+
+    #[test]
+    fn pending_jobs_are_counted_per_key() {
+        let mut store = JobStore::new();
+        let first = store.create("key1", "https://example.com/1", None);
+        store.create("key1", "https://example.com/2", None);
+        store.create("key2", "https://example.com/3", None);
+        store.set_result(&first.id, sample_result());
+
+        assert_eq!(store.pending_count_for("key1"), 1);
+        assert_eq!(store.pending_count_for("key2"), 1);
+        assert!(store.get(&first.id, "key2").is_none());
+        assert_eq!(store.get(&first.id, "key1").unwrap().status, JobStatus::Completed);
+    }
+}
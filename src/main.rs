@@ -1,15 +1,64 @@
+mod api_error;
 mod api_key;
 mod api_types;
+mod app_state;
+mod bookmarks;
+mod config;
+mod content;
+mod cookie_jar;
+mod culling;
+mod document;
+mod epub;
+mod feed;
+mod fetcher;
+mod gemini;
+mod generated_feed;
+mod gopher;
+mod history;
 mod html_parser;
+mod image_proxy;
+mod jobs;
+mod openapi;
+mod persist;
+mod process_options;
+mod profile;
+mod saved;
+mod search;
+mod upstream;
+mod watch;
 mod web_ui;
 mod handlers;
 
+use api_error::{generate_request_id, is_legacy_path, with_envelope, with_request_id, ApiError, ErrorCode};
 use api_key::{ApiKeyStore, SharedApiKeyStore};
+use app_state::AppState;
+use bookmarks::BookmarkStore;
+use config::Config;
+use cookie_jar::CookieJarStore;
+use generated_feed::FeedLinkStore;
+use history::HistoryStore;
+use jobs::JobQueue;
+use saved::{SavedPageStore, ViewedPages};
+use search::SearchIndex;
+use watch::WatchStore;
 use web_ui::{get_api_docs_html, get_home_page_html, get_admin_page_html};
 use handlers::{
-    handle_proxy_request, handle_api_get_request, create_html_response,
+    handle_proxy_request, handle_proxy_post_request, handle_api_get_request, handle_api_post_request, handle_batch_request, create_html_response,
+    handle_create_job_request, handle_get_job_request,
     handle_create_key_request, handle_list_keys_request, handle_delete_key_request,
-    handle_statistics_request, handle_admin_login_request
+    handle_statistics_request, handle_admin_login_request,
+    handle_add_fingerprint_request, handle_delete_fingerprint_request,
+    handle_update_key_settings_request, handle_image_request, handle_form_request,
+    handle_list_cookies_request, handle_clear_cookies_request, handle_generated_feed_request, handle_feed_entry_request,
+    handle_v1_create_key_request, handle_v1_delete_key_request, handle_v1_update_key_settings_request,
+    handle_v1_add_fingerprint_request, handle_v1_delete_fingerprint_request, create_json_response, create_error_json_response,
+    handle_create_watch_request, handle_list_watches_request, handle_get_watch_request, handle_delete_watch_request,
+    handle_get_snapshot_request, handle_watch_diff_request, handle_watch_diff_page_request,
+    handle_create_saved_page_request, handle_save_form_request, handle_list_saved_pages_request, handle_get_saved_page_request,
+    handle_delete_saved_page_request, handle_saved_list_page_request, handle_saved_page_view_request,
+    handle_search_request, handle_search_page_request, handle_epub_request, handle_epub_download_request,
+    handle_history_request, handle_clear_history_request, handle_history_settings_request,
+    handle_create_bookmark_request, handle_list_bookmarks_request, handle_update_bookmark_request, handle_delete_bookmark_request
 };
 
 use hyper::service::{make_service_fn, service_fn};
@@ -17,7 +66,7 @@ use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock, Semaphore};
 
 // ========== 定数 ==========
 const SERVER_PORT: u16 = 80;
@@ -37,12 +86,52 @@ async fn main() {
     // 管理者キーを表示
     println!("管理者キー: {}", ApiKeyStore::get_admin_key());
 
+    // 保存したページと全文検索のインデックス
+    let saved_pages = SavedPageStore::load_from_file();
+    let search_index = SearchIndex::build(&saved_pages);
+
+    let (job_queue, job_receiver) = JobQueue::new();
+    let state = AppState {
+        api_key_store: api_key_store.clone(),
+        cookie_jars: Arc::new(RwLock::new(CookieJarStore::load_from_file())),
+        feed_links: Arc::new(RwLock::new(FeedLinkStore::load_from_file())),
+        jobs: Arc::new(job_queue),
+        watches: Arc::new(RwLock::new(WatchStore::load_from_file())),
+        saved_pages: Arc::new(RwLock::new(saved_pages)),
+        viewed_pages: Arc::new(Mutex::new(ViewedPages::new())),
+        search_index: Arc::new(search_index),
+        history: Arc::new(RwLock::new(HistoryStore::load_from_file())),
+        bookmarks: Arc::new(RwLock::new(BookmarkStore::load_from_file())),
+        batch_permits: Arc::new(Semaphore::new(Config::global().batch.concurrency.max(1))),
+    };
+
+    // JSONファイルに保存するストアの書き出し
+    persist::start_flusher(state.cookie_jars.clone());
+    persist::start_flusher(state.feed_links.clone());
+    persist::start_flusher(state.saved_pages.clone());
+    persist::start_flusher(state.history.clone());
+    persist::start_flusher(state.bookmarks.clone());
+
+    // 非同期ジョブのワーカー
+    jobs::start_workers(job_receiver, state.clone());
+
+    // ページ監視の定期取得
+    watch::start_checker(state.clone());
+
+    // Gemini/Gopherフロントエンド（設定で有効な場合のみ）
+    let config = Config::global();
+    if config.gemini.enabled {
+        tokio::spawn(gemini::run_gemini_server(config.gemini.clone(), state.clone()));
+    }
+    if config.gopher.enabled {
+        tokio::spawn(gopher::run_gopher_server(config.gopher.clone(), state.clone()));
+    }
+
     // サーバー起動
-    let api_key_store_clone = api_key_store.clone();
     let make_svc = make_service_fn(move |_conn| {
-        let store = api_key_store_clone.clone();
+        let state = state.clone();
         async move {
-            Ok::<_, Infallible>(service_fn(move |req| handle_request(req, store.clone())))
+            Ok::<_, Infallible>(service_fn(move |req| handle_request(req, state.clone())))
         }
     });
 
@@ -68,7 +157,21 @@ async fn initialize_default_api_key(api_key_store: &SharedApiKeyStore) {
 }
 
 // ========== リクエストルーティング ==========
-async fn handle_request(req: Request<Body>, api_key_store: SharedApiKeyStore) -> Result<Response<Body>, Infallible> {
+
+// リクエストIDを割り当て、エラーレスポンスとX-Request-Idヘッダーに含める
+// /api/v1より前からあるパスでは、JSONのエラーを以前の形で返す
+async fn handle_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let request_id = generate_request_id();
+    let legacy = is_legacy_path(req.uri().path());
+    let mut response = with_request_id(request_id.clone(), with_envelope(legacy, route_request(req, state))).await?;
+    if let Ok(value) = request_id.parse() {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    Ok(response)
+}
+
+async fn route_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let api_key_store = state.api_key_store.clone();
     match (req.method(), req.uri().path()) {
         // 静的ページ
         (&Method::GET, "/") => {
@@ -76,23 +179,131 @@ async fn handle_request(req: Request<Body>, api_key_store: SharedApiKeyStore) ->
             Ok(create_html_response(html.to_string()))
         }
         (&Method::GET, "/api/docs") => {
-            let docs_html = get_api_docs_html();
-            Ok(create_html_response(docs_html.to_string()))
+            Ok(create_html_response(get_api_docs_html()))
         }
         (&Method::GET, "/admin") => {
             let admin_html = get_admin_page_html();
             Ok(create_html_response(admin_html.to_string()))
         }
         
+        // バージョン付きAPI
+        (_, path) if path.starts_with("/api/v1/") => {
+            handle_v1_request(req, state).await
+        }
+
         // プロキシ機能
         (&Method::GET, "/proxy") => {
-            handle_proxy_request(req, api_key_store).await
+            handle_proxy_request(req, state).await
+        }
+        (&Method::POST, "/proxy") => {
+            handle_proxy_post_request(req, state).await
         }
         (&Method::GET, "/api/process") => {
-            handle_api_get_request(req, api_key_store).await
+            handle_api_get_request(req, state).await
+        }
+        (&Method::POST, "/api/process") => {
+            handle_api_post_request(req, state).await
+        }
+        (&Method::POST, "/api/batch") => {
+            handle_batch_request(req, state).await
+        }
+        (&Method::POST, "/api/jobs") => {
+            handle_create_job_request(req, state).await
+        }
+        (&Method::GET, path) if path.starts_with("/api/jobs/") => {
+            let job_id = path.trim_start_matches("/api/jobs/").to_string();
+            handle_get_job_request(req, &job_id, state).await
+        }
+        (&Method::GET, "/form") => {
+            handle_form_request(req, state).await
+        }
+        (&Method::GET, "/image") => {
+            handle_image_request(req, state).await
+        }
+        (&Method::GET, "/watch/diff") => {
+            handle_watch_diff_page_request(req, state).await
+        }
+
+        // 保存したページ
+        (&Method::POST, "/save") => {
+            handle_save_form_request(req, state).await
+        }
+        (&Method::GET, "/saved") => {
+            handle_saved_list_page_request(req, state).await
+        }
+        (&Method::GET, path) if path.starts_with("/saved/") => {
+            let page_id = path.trim_start_matches("/saved/").to_string();
+            handle_saved_page_view_request(req, &page_id, state).await
+        }
+        (&Method::GET, "/epub") => {
+            handle_epub_download_request(req, state).await
+        }
+        (&Method::POST, "/api/epub") => {
+            handle_epub_request(req, state).await
+        }
+        (&Method::GET, "/search") => {
+            handle_search_page_request(req, state).await
+        }
+        (&Method::GET, "/api/search") => {
+            handle_search_request(req, state).await
+        }
+        (&Method::POST, "/api/saved") => {
+            handle_create_saved_page_request(req, state).await
+        }
+        (&Method::GET, "/api/saved") => {
+            handle_list_saved_pages_request(req, state).await
+        }
+        (&Method::GET, path) if path.starts_with("/api/saved/") => {
+            let page_id = path.trim_start_matches("/api/saved/").to_string();
+            handle_get_saved_page_request(req, &page_id, state).await
+        }
+        (&Method::DELETE, path) if path.starts_with("/api/saved/") => {
+            let page_id = path.trim_start_matches("/api/saved/").to_string();
+            handle_delete_saved_page_request(req, &page_id, state).await
+        }
+
+        // 閲覧履歴とブックマーク
+        (&Method::GET, "/api/history") => {
+            handle_history_request(req, state).await
+        }
+        (&Method::DELETE, "/api/history") => {
+            handle_clear_history_request(req, state).await
+        }
+        (&Method::PATCH, "/api/history/settings") => {
+            handle_history_settings_request(req, state).await
+        }
+        (&Method::GET, "/api/bookmarks") => {
+            handle_list_bookmarks_request(req, state).await
+        }
+        (&Method::POST, "/api/bookmarks") => {
+            handle_create_bookmark_request(req, state).await
+        }
+        (&Method::PATCH, path) if path.starts_with("/api/bookmarks/") => {
+            let bookmark_id = path.trim_start_matches("/api/bookmarks/").to_string();
+            handle_update_bookmark_request(req, &bookmark_id, state).await
+        }
+        (&Method::DELETE, path) if path.starts_with("/api/bookmarks/") => {
+            let bookmark_id = path.trim_start_matches("/api/bookmarks/").to_string();
+            handle_delete_bookmark_request(req, &bookmark_id, state).await
         }
         
-        // APIキー管理
+        // 生成したフィード
+        (&Method::GET, "/feed") => {
+            handle_generated_feed_request(req, state).await
+        }
+        (&Method::GET, "/feed/open") => {
+            handle_feed_entry_request(req, state).await
+        }
+
+        // Cookieジャー
+        (&Method::GET, "/api/cookies") => {
+            handle_list_cookies_request(req, state).await
+        }
+        (&Method::DELETE, "/api/cookies") => {
+            handle_clear_cookies_request(req, state).await
+        }
+
+        // APIキー管理（従来のパス。/api/v1/keysと同じ処理）
         (&Method::POST, "/api/keys/create") => {
             handle_create_key_request(req, api_key_store).await
         }
@@ -102,6 +313,15 @@ async fn handle_request(req: Request<Body>, api_key_store: SharedApiKeyStore) ->
         (&Method::DELETE, "/api/keys/delete") => {
             handle_delete_key_request(req, api_key_store).await
         }
+        (&Method::POST, "/api/keys/settings") => {
+            handle_update_key_settings_request(req, api_key_store).await
+        }
+        (&Method::POST, "/api/keys/fingerprints") => {
+            handle_add_fingerprint_request(req, api_key_store).await
+        }
+        (&Method::DELETE, "/api/keys/fingerprints") => {
+            handle_delete_fingerprint_request(req, api_key_store).await
+        }
         
         // 統計・認証
         (&Method::GET, "/api/statistics") => {
@@ -112,10 +332,86 @@ async fn handle_request(req: Request<Body>, api_key_store: SharedApiKeyStore) ->
         }
         
         // 404
-        _ => {
-            let mut not_found = Response::default();
-            *not_found.status_mut() = StatusCode::NOT_FOUND;
-            Ok(not_found)
+        _ => Ok(not_found_response()),
+    }
+}
+
+// ========== /api/v1 ルーティング ==========
+// パスをデコード済みのセグメントに分けて照合する（従来のパスと同じハンドラーを使う）
+async fn handle_v1_request(req: Request<Body>, state: AppState) -> Result<Response<Body>, Infallible> {
+    let api_key_store = state.api_key_store.clone();
+    let method = req.method().clone();
+    let segments: Vec<String> = req
+        .uri()
+        .path()
+        .trim_start_matches("/api/v1/")
+        .split('/')
+        .map(|segment| urlencoding::decode(segment).map(|s| s.into_owned()).unwrap_or_else(|_| segment.to_string()))
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(|segment| segment.as_str()).collect();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["openapi.json"]) => {
+            let document = serde_json::to_string_pretty(openapi::openapi_document()).unwrap_or_default();
+            Ok(create_json_response(document, StatusCode::OK))
         }
+
+        // 軽量化・ジョブ
+        (&Method::GET, ["process"]) => handle_api_get_request(req, state).await,
+        (&Method::POST, ["process"]) => handle_api_post_request(req, state).await,
+        (&Method::POST, ["batch"]) => handle_batch_request(req, state).await,
+        (&Method::POST, ["jobs"]) => handle_create_job_request(req, state).await,
+        (&Method::GET, ["jobs", job_id]) => handle_get_job_request(req, job_id, state).await,
+
+        // 保存したページ
+        (&Method::GET, ["saved"]) => handle_list_saved_pages_request(req, state).await,
+        (&Method::POST, ["saved"]) => handle_create_saved_page_request(req, state).await,
+        (&Method::GET, ["saved", page_id]) => handle_get_saved_page_request(req, page_id, state).await,
+        (&Method::DELETE, ["saved", page_id]) => handle_delete_saved_page_request(req, page_id, state).await,
+        (&Method::GET, ["search"]) => handle_search_request(req, state).await,
+        (&Method::POST, ["epub"]) => handle_epub_request(req, state).await,
+
+        // 閲覧履歴とブックマーク
+        (&Method::GET, ["history"]) => handle_history_request(req, state).await,
+        (&Method::DELETE, ["history"]) => handle_clear_history_request(req, state).await,
+        (&Method::PATCH, ["history", "settings"]) => handle_history_settings_request(req, state).await,
+        (&Method::GET, ["bookmarks"]) => handle_list_bookmarks_request(req, state).await,
+        (&Method::POST, ["bookmarks"]) => handle_create_bookmark_request(req, state).await,
+        (&Method::PATCH, ["bookmarks", bookmark_id]) => handle_update_bookmark_request(req, bookmark_id, state).await,
+        (&Method::DELETE, ["bookmarks", bookmark_id]) => handle_delete_bookmark_request(req, bookmark_id, state).await,
+
+        // ページ監視
+        (&Method::GET, ["watches"]) => handle_list_watches_request(req, state).await,
+        (&Method::POST, ["watches"]) => handle_create_watch_request(req, state).await,
+        (&Method::GET, ["watches", watch_id]) => handle_get_watch_request(req, watch_id, state).await,
+        (&Method::DELETE, ["watches", watch_id]) => handle_delete_watch_request(req, watch_id, state).await,
+        (&Method::GET, ["watches", watch_id, "diff"]) => handle_watch_diff_request(req, watch_id, state).await,
+        (&Method::GET, ["watches", watch_id, "snapshots", snapshot_id]) => {
+            handle_get_snapshot_request(req, watch_id, snapshot_id, state).await
+        }
+
+        // Cookieジャー
+        (&Method::GET, ["cookies"]) => handle_list_cookies_request(req, state).await,
+        (&Method::DELETE, ["cookies"]) => handle_clear_cookies_request(req, state).await,
+
+        // APIキー管理
+        (&Method::GET, ["keys"]) => handle_list_keys_request(req, api_key_store).await,
+        (&Method::POST, ["keys"]) => handle_v1_create_key_request(req, api_key_store).await,
+        (&Method::PATCH, ["keys", key]) => handle_v1_update_key_settings_request(req, key, api_key_store).await,
+        (&Method::DELETE, ["keys", key]) => handle_v1_delete_key_request(req, key, api_key_store).await,
+        (&Method::POST, ["keys", key, "fingerprints"]) => handle_v1_add_fingerprint_request(req, key, api_key_store).await,
+        (&Method::DELETE, ["keys", key, "fingerprints", fingerprint]) => {
+            handle_v1_delete_fingerprint_request(req, key, fingerprint, api_key_store).await
+        }
+
+        // 統計・認証
+        (&Method::GET, ["statistics"]) => handle_statistics_request(req, api_key_store).await,
+        (&Method::POST, ["admin", "login"]) => handle_admin_login_request(req, api_key_store).await,
+
+        _ => Ok(not_found_response()),
     }
 }
+
+fn not_found_response() -> Response<Body> {
+    create_error_json_response(ApiError::new(ErrorCode::NotFound, "ページが見つかりません"))
+}
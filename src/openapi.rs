@@ -0,0 +1,1057 @@
+use crate::api_error::ErrorResponse;
+use crate::api_types::{
+    AdminLoginRequest, ApiBatchRequest, ApiBookmarkRequest, ApiEpubRequest, ApiHistorySettingsRequest, ApiJobRequest, ApiKeySettingsRequest,
+    ApiProcessOptions, ApiProcessRequest, ApiResponse, ApiSaveRequest, ApiWatchRequest, BatchItemResult, BatchResponse, BookmarkListResponse, BookmarkResponse,
+    CookieListResponse, CreateKeyRequest, FingerprintRequest, HistoryResponse, JobResponse,
+    SavedPageContentResponse, SavedPageListResponse, SavedPageResponse, SearchResponse, SimpleResponse, SnapshotResponse, StatisticsResponse,
+    UsageResponse, WatchDiffResponse, WatchListResponse, WatchResponse,
+};
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+// /api/v1のOpenAPI 3ドキュメント（スキーマはapi_typesの型から生成する）と、それを元にしたHTMLドキュメント
+
+// ========== 定数 ==========
+pub const OPENAPI_PATH: &str = "/api/v1/openapi.json";
+
+static DOCUMENT: OnceLock<Value> = OnceLock::new();
+
+// HTMLドキュメントでの表示順
+const TAGS: &[(&str, &str)] = &[
+    ("軽量化", "ブラウザから直接開くエンドポイント（HTML・画像・Atomを返す）"),
+    ("JSON API", "処理結果をJSONで返すエンドポイント"),
+    ("保存したページ", "後で読むために保存した軽量化ページ"),
+    ("EPUB", "軽量化したページを電子書籍リーダー向けのEPUB 3にまとめる"),
+    ("閲覧履歴とブックマーク", "APIキーごとの閲覧履歴とブックマーク（ホーム画面にも表示する）"),
+    ("ページ監視", "登録したURLを定期的に取得し、軽量化したテキストの変更を記録する"),
+    ("Cookieジャー", "サーバー側で保持している上流サイトのCookie"),
+    ("APIキー管理", "管理者キーが必要なエンドポイント"),
+];
+const METHODS: &[&str] = &["get", "post", "patch", "delete"];
+
+// JSONボディのフィールド名とクエリパラメータ名が異なる軽量化オプション
+const QUERY_PARAMETER_NAMES: &[(&str, &str)] = &[("no_cache", "cache")];
+
+// ========== 操作の定義 ==========
+
+struct Parameter {
+    name: Cow<'static, str>,
+    // "query" / "path" / "header"
+    location: &'static str,
+    required: bool,
+    description: Cow<'static, str>,
+}
+
+enum Content {
+    Json(fn(&mut SchemaGenerator) -> Schema),
+    // NDJSONの各行のスキーマ
+    NdJson(fn(&mut SchemaGenerator) -> Schema),
+    // JSON以外（メディアタイプのみ示す）
+    Other(&'static str),
+}
+
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    description: &'static str,
+    parameters: Vec<Parameter>,
+    request_body: Option<Content>,
+    responses: Vec<(u16, &'static str, Vec<Content>)>,
+}
+
+fn schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+fn query(name: &'static str, required: bool, description: &'static str) -> Parameter {
+    Parameter { name: name.into(), location: "query", required, description: description.into() }
+}
+
+fn path(name: &'static str, description: &'static str) -> Parameter {
+    Parameter { name: name.into(), location: "path", required: true, description: description.into() }
+}
+
+fn url_and_api_key() -> Vec<Parameter> {
+    vec![query("url", true, "取得するURL"), query("api_key", true, "APIキー")]
+}
+
+// 軽量化オプションのクエリパラメータを加える（ApiProcessOptionsのフィールドと説明から作る）
+fn with_options(mut parameters: Vec<Parameter>) -> Vec<Parameter> {
+    parameters.extend(option_parameters());
+    parameters
+}
+
+fn option_parameters() -> Vec<Parameter> {
+    let schema = schemars::schema_for!(ApiProcessOptions);
+    let properties = schema.schema.object.map(|object| object.properties).unwrap_or_default();
+    properties
+        .into_iter()
+        .map(|(field, property)| {
+            let name = QUERY_PARAMETER_NAMES
+                .iter()
+                .find(|(json_name, _)| *json_name == field)
+                .map(|(_, query_name)| query_name.to_string())
+                .unwrap_or(field);
+            let description = match property {
+                Schema::Object(object) => object.metadata.and_then(|metadata| metadata.description).unwrap_or_default(),
+                Schema::Bool(_) => String::new(),
+            };
+            Parameter {
+                name: name.into(),
+                location: "query",
+                required: false,
+                description: description.into(),
+            }
+        })
+        .collect()
+}
+
+fn api_key_header() -> Parameter {
+    Parameter {
+        name: "X-API-Key".into(),
+        location: "header",
+        required: false,
+        description: "APIキー（ボディの `api_key` を優先する）".into(),
+    }
+}
+
+fn admin_key_query() -> Parameter {
+    query("admin_key", true, "管理者キー")
+}
+
+fn operations() -> Vec<Operation> {
+    vec![
+        // 軽量化
+        Operation {
+            method: "get",
+            path: "/proxy",
+            tag: "軽量化",
+            summary: "HTML軽量化",
+            description: "軽量化したHTMLを返す。テキストは `<pre>` で、JSON・XMLは整形して表示し、画像は縮小プレビュー、PDFは抽出したテキスト、その他のファイルはサイズ付きのダウンロードリンクになる。RSS・Atomフィードは記事一覧として表示し、フィードを提供しているページには先頭にフィードへのリンクを表示する。",
+            parameters: with_options(url_and_api_key()),
+            request_body: None,
+            responses: vec![(200, "軽量化したページ", vec![Content::Other("text/html")])],
+        },
+        Operation {
+            method: "post",
+            path: "/proxy",
+            tag: "軽量化",
+            summary: "フォームのPOST送信",
+            description: "本文を送信先URLへPOSTし、リダイレクト先のページを軽量化して返す。管理者がAPIキーごとに `allow_post` を有効にする必要がある。",
+            parameters: with_options(vec![query("url", true, "送信先URL"), query("api_key", true, "APIキー")]),
+            request_body: Some(Content::Other("application/x-www-form-urlencoded")),
+            responses: vec![(200, "軽量化したページ", vec![Content::Other("text/html")])],
+        },
+        Operation {
+            method: "get",
+            path: "/image",
+            tag: "軽量化",
+            summary: "画像の縮小",
+            description: "縮小・グレースケール化した低画質の画像を返す。",
+            parameters: url_and_api_key(),
+            request_body: None,
+            responses: vec![
+                (200, "縮小した画像", vec![Content::Other("image/jpeg")]),
+                (422, "画像として読み込めない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/feed",
+            tag: "軽量化",
+            summary: "ページのリンクからフィードを生成",
+            description: "フィードのないページの記事リンクからAtomフィードを生成する。リンクを最初に見つけた日時が記事の日時になる。記事のリンクはAPIキーを含まない `/feed/open` を通してプロキシ経由で開く。",
+            parameters: url_and_api_key(),
+            request_body: None,
+            responses: vec![
+                (200, "Atomフィード", vec![Content::Other("application/atom+xml")]),
+                (422, "HTMLページではない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/feed/open",
+            tag: "軽量化",
+            summary: "生成したフィードの記事を開く",
+            description: "`/feed` の記事のリンク。APIキーの代わりに、キーの識別子とキーで作った署名で確かめ、`/proxy` と同じ軽量化ページを返す。",
+            parameters: vec![
+                query("url", true, "記事のURL"),
+                query("key", true, "APIキーの識別子"),
+                query("sig", true, "記事のURLの署名"),
+            ],
+            request_body: None,
+            responses: vec![
+                (200, "軽量化したページ", vec![Content::Other("text/html")]),
+                (401, "署名が正しくない", vec![Content::Other("text/html")]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/save",
+            tag: "軽量化",
+            summary: "ページを保存して表示する",
+            description: "ブラウザで表示した軽量化ページ（`/proxy`）の「このページを保存」ボタンの送信先。表示した内容を取得し直さずに保存し、保存したページ（`/saved/{id}`）へリダイレクトする。表示したページは1時間・最大100件まで控えておく。APIキーは本文の `rigil_api_key` でも指定できる。",
+            parameters: vec![query("view", true, "表示したページの控えのID（保存ボタンに含まれる）"), query("api_key", false, "APIキー")],
+            request_body: Some(Content::Other("application/x-www-form-urlencoded")),
+            responses: vec![(303, "保存したページへのリダイレクト", vec![])],
+        },
+        Operation {
+            method: "get",
+            path: "/saved",
+            tag: "軽量化",
+            summary: "保存したページの一覧を表示する",
+            description: "",
+            parameters: vec![query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![(200, "保存したページの一覧", vec![Content::Other("text/html")])],
+        },
+        Operation {
+            method: "get",
+            path: "/saved/{id}",
+            tag: "軽量化",
+            summary: "保存したページを表示する",
+            description: "保存時の軽量化ページを、保存日時と元のページへのリンクを付けて表示する。",
+            parameters: vec![path("id", "保存したページのID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![(200, "保存したページ", vec![Content::Other("text/html")])],
+        },
+        Operation {
+            method: "get",
+            path: "/epub",
+            tag: "軽量化",
+            summary: "EPUBをダウンロードする",
+            description: "`url` のページ、または `saved` に並べた保存したページ（カンマ区切りのID）をEPUB 3にする。保存したページの一覧の「EPUBでダウンロード」から開く。",
+            parameters: with_options(vec![
+                query("url", false, "EPUBにするURL"),
+                query("saved", false, "保存したページのID（カンマ区切り。urlとどちらか一方）"),
+                query("title", false, "本のタイトル"),
+                query("api_key", true, "APIキー"),
+            ]),
+            request_body: None,
+            responses: vec![(200, "EPUB", vec![Content::Other("application/epub+zip")])],
+        },
+        Operation {
+            method: "get",
+            path: "/search",
+            tag: "軽量化",
+            summary: "保存したページを検索する",
+            description: "検索フォームと、関連度の高い順に並べた結果（一致部分を強調した抜粋と保存したページへのリンク）を表示する。",
+            parameters: vec![query("q", false, "検索語（省略するとフォームのみ）"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![(200, "検索結果", vec![Content::Other("text/html")])],
+        },
+        Operation {
+            method: "get",
+            path: "/watch/diff",
+            tag: "軽量化",
+            summary: "ページ監視の差分を表示する",
+            description: "追加したブロックを緑、削除したブロックを赤で表示し、変更のないブロックは前後の数件のみ表示する。",
+            parameters: vec![
+                query("id", true, "監視ID"),
+                query("api_key", true, "APIキー"),
+                query("from", false, "比較元のスナップショットID"),
+                query("to", false, "比較先のスナップショットID"),
+            ],
+            request_body: None,
+            responses: vec![(200, "差分のページ", vec![Content::Other("text/html")])],
+        },
+        // JSON API
+        Operation {
+            method: "get",
+            path: "/api/v1/process",
+            tag: "JSON API",
+            summary: "URLを軽量化してJSONで返す",
+            description: "失敗時は `error.code`（`upstream_timeout`、`upstream_dns`、`upstream_blocked` 等）と対応するHTTPステータスを返す。",
+            parameters: with_options(url_and_api_key()),
+            request_body: None,
+            responses: vec![
+                (200, "処理結果", vec![Content::Json(schema::<ApiResponse>)]),
+                (400, "パラメータの不足・不正", vec![Content::Json(schema::<ApiResponse>)]),
+                (401, "APIキーが無効", vec![Content::Json(schema::<ApiResponse>)]),
+                (502, "上流サイトからの取得に失敗", vec![Content::Json(schema::<ApiResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/process",
+            tag: "JSON API",
+            summary: "URLと軽量化オプションをJSONボディで指定する",
+            description: "不正なフィールドは `error.code: invalid_request` と `error.field_errors` でフィールドごとに返す。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiProcessRequest>)),
+            responses: vec![
+                (200, "処理結果", vec![Content::Json(schema::<ApiResponse>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ApiResponse>)]),
+                (401, "APIキーが無効", vec![Content::Json(schema::<ApiResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/batch",
+            tag: "JSON API",
+            summary: "複数のURLをまとめて処理する",
+            description: "結果は `urls` の順に並べた `results` で返す。`stream: true` の場合は完了した順にNDJSONで返す。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiBatchRequest>)),
+            responses: vec![
+                (200, "処理結果", vec![Content::Json(schema::<BatchResponse>), Content::NdJson(schema::<BatchItemResult>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ApiResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/jobs",
+            tag: "JSON API",
+            summary: "非同期ジョブを登録する",
+            description: "ジョブIDをすぐに返す。`callback_url` を指定すると完了時に結果をPOSTする（`X-Rigil-Signature: sha256=...` はAPIキーを鍵としたHMAC-SHA256）。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiJobRequest>)),
+            responses: vec![
+                (202, "登録したジョブ", vec![Content::Json(schema::<JobResponse>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ApiResponse>)]),
+                (429, "待機中・処理中のジョブ数の上限を超えた", vec![Content::Json(schema::<ApiResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/jobs/{id}",
+            tag: "JSON API",
+            summary: "ジョブの状態と結果",
+            description: "状態は `queued` / `running` / `completed` / `failed`。ジョブを登録したAPIキーからのみ参照できる。",
+            parameters: vec![path("id", "ジョブID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![
+                (200, "ジョブ", vec![Content::Json(schema::<JobResponse>)]),
+                (404, "ジョブが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        // 保存したページ
+        Operation {
+            method: "post",
+            path: "/api/v1/saved",
+            tag: "保存したページ",
+            summary: "ページを保存する",
+            description: "ページを取得して軽量化し、軽量化したHTMLとプレーンテキストを保存する。元のページが変わったり消えたりしても保存時の内容を表示できる。軽量化したページの「このページを保存」ボタン（`POST /save`）からも保存できる。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiSaveRequest>)),
+            responses: vec![
+                (201, "保存したページ", vec![Content::Json(schema::<SavedPageResponse>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+                (429, "保存数の上限を超えた", vec![Content::Json(schema::<ErrorResponse>)]),
+                (502, "上流サイトからの取得に失敗", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/saved",
+            tag: "保存したページ",
+            summary: "保存したページの一覧",
+            description: "新しいものから並べる。",
+            parameters: vec![query("api_key", true, "APIキー（X-API-Keyヘッダーでも指定できる）")],
+            request_body: None,
+            responses: vec![(200, "保存したページの一覧", vec![Content::Json(schema::<SavedPageListResponse>)])],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/saved/{id}",
+            tag: "保存したページ",
+            summary: "保存したページの内容",
+            description: "ブラウザでは `/saved/{id}?api_key=...` で表示できる。",
+            parameters: vec![
+                path("id", "保存したページのID"),
+                query("api_key", true, "APIキー"),
+                query("format", false, "`html`（既定） / `text`"),
+            ],
+            request_body: None,
+            responses: vec![
+                (200, "保存したページ", vec![Content::Json(schema::<SavedPageContentResponse>)]),
+                (404, "保存したページが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/saved/{id}",
+            tag: "保存したページ",
+            summary: "保存したページを削除する",
+            description: "",
+            parameters: vec![path("id", "保存したページのID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![
+                (200, "削除した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (404, "保存したページが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/search",
+            tag: "保存したページ",
+            summary: "保存したページを全文検索する",
+            description: "タイトルと本文を検索し、関連度の高い順に返す。スペースで区切った語はすべてを含むページに一致し、`OR` や `\"...\"`（フレーズ）も使える。日本語等は2文字ずつ索引するため、1文字だけの語では検索できない。",
+            parameters: vec![
+                query("q", true, "検索語"),
+                query("api_key", true, "APIキー（X-API-Keyヘッダーでも指定できる）"),
+                query("limit", false, "最大件数（1〜100、既定は20）"),
+            ],
+            request_body: None,
+            responses: vec![
+                (200, "検索結果", vec![Content::Json(schema::<SearchResponse>)]),
+                (400, "検索語またはlimitが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        // EPUB
+        Operation {
+            method: "post",
+            path: "/api/v1/epub",
+            tag: "EPUB",
+            summary: "ページをEPUBにする",
+            description: "1つのURL、または保存したページ（指定した順に章にする）をEPUB 3にする。目次と元のURLへのリンクを付け、軽量化したHTMLを章の本文にする。縮小画像（`images=thumb`）は取得してJPEGで埋め込み、取得できなかった画像は代替テキストにする。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiEpubRequest>)),
+            responses: vec![
+                (200, "EPUB", vec![Content::Other("application/epub+zip")]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+                (404, "保存したページが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+                (502, "上流サイトからの取得に失敗", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        // 閲覧履歴とブックマーク
+        Operation {
+            method: "get",
+            path: "/api/v1/history",
+            tag: "閲覧履歴とブックマーク",
+            summary: "閲覧履歴",
+            description: "`/proxy` 等でブラウザに表示したページを新しいものから返す。同じURLを続けて表示した場合は1件にまとめる。保持期間を過ぎた履歴は削除する。",
+            parameters: vec![
+                query("api_key", true, "APIキー（X-API-Keyヘッダーでも指定できる）"),
+                query("limit", false, "最大件数（既定は50）"),
+            ],
+            request_body: None,
+            responses: vec![
+                (200, "閲覧履歴", vec![Content::Json(schema::<HistoryResponse>)]),
+                (400, "limitが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/history",
+            tag: "閲覧履歴とブックマーク",
+            summary: "閲覧履歴を削除する",
+            description: "",
+            parameters: vec![query("api_key", true, "APIキー（X-API-Keyヘッダーでも指定できる）")],
+            request_body: None,
+            responses: vec![(200, "削除した", vec![Content::Json(schema::<SimpleResponse>)])],
+        },
+        Operation {
+            method: "patch",
+            path: "/api/v1/history/settings",
+            tag: "閲覧履歴とブックマーク",
+            summary: "閲覧履歴の記録を切り替える",
+            description: "APIキーの利用者が自分で切り替えられる（管理者キーは不要）。記録を止めるとこれまでの閲覧履歴も削除する。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiHistorySettingsRequest>)),
+            responses: vec![
+                (200, "切り替えた", vec![Content::Json(schema::<SimpleResponse>)]),
+                (400, "JSONが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/bookmarks",
+            tag: "閲覧履歴とブックマーク",
+            summary: "ブックマークを登録する",
+            description: "`title` を省略した場合は閲覧履歴のタイトル（なければURL）にする。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiBookmarkRequest>)),
+            responses: vec![
+                (201, "登録したブックマーク", vec![Content::Json(schema::<BookmarkResponse>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+                (429, "登録数の上限を超えた", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/bookmarks",
+            tag: "閲覧履歴とブックマーク",
+            summary: "ブックマークの一覧",
+            description: "フォルダなしを先頭にフォルダ名順、同じフォルダでは登録順に並べる。",
+            parameters: vec![
+                query("api_key", true, "APIキー（X-API-Keyヘッダーでも指定できる）"),
+                query("folder", false, "このフォルダのみ返す（空文字列はフォルダなし）"),
+            ],
+            request_body: None,
+            responses: vec![(200, "ブックマークの一覧", vec![Content::Json(schema::<BookmarkListResponse>)])],
+        },
+        Operation {
+            method: "patch",
+            path: "/api/v1/bookmarks/{id}",
+            tag: "閲覧履歴とブックマーク",
+            summary: "ブックマークを変更する",
+            description: "指定したフィールドのみ変更する。",
+            parameters: vec![path("id", "ブックマークID"), api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiBookmarkRequest>)),
+            responses: vec![
+                (200, "変更したブックマーク", vec![Content::Json(schema::<BookmarkResponse>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+                (404, "ブックマークが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/bookmarks/{id}",
+            tag: "閲覧履歴とブックマーク",
+            summary: "ブックマークを削除する",
+            description: "",
+            parameters: vec![path("id", "ブックマークID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![
+                (200, "削除した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (404, "ブックマークが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        // ページ監視
+        Operation {
+            method: "post",
+            path: "/api/v1/watches",
+            tag: "ページ監視",
+            summary: "監視を登録する",
+            description: "登録後すぐに最初のスナップショットを取得し、以後 `interval_minutes` ごとに取得する。スナップショットは内容が変わった場合のみ追加する。",
+            parameters: vec![api_key_header()],
+            request_body: Some(Content::Json(schema::<ApiWatchRequest>)),
+            responses: vec![
+                (201, "登録した監視", vec![Content::Json(schema::<WatchResponse>)]),
+                (400, "JSONまたはフィールドが不正", vec![Content::Json(schema::<ErrorResponse>)]),
+                (429, "登録数の上限を超えた", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/watches",
+            tag: "ページ監視",
+            summary: "監視の一覧",
+            description: "",
+            parameters: vec![query("api_key", true, "APIキー（X-API-Keyヘッダーでも指定できる）")],
+            request_body: None,
+            responses: vec![(200, "監視の一覧", vec![Content::Json(schema::<WatchListResponse>)])],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/watches/{id}",
+            tag: "ページ監視",
+            summary: "監視の状態とスナップショットの一覧",
+            description: "",
+            parameters: vec![path("id", "監視ID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![
+                (200, "監視", vec![Content::Json(schema::<WatchResponse>)]),
+                (404, "監視が見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/watches/{id}",
+            tag: "ページ監視",
+            summary: "監視を削除する",
+            description: "保存したスナップショットも削除する。",
+            parameters: vec![path("id", "監視ID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![
+                (200, "削除した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (404, "監視が見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/watches/{id}/snapshots/{snapshot_id}",
+            tag: "ページ監視",
+            summary: "スナップショットのテキスト",
+            description: "",
+            parameters: vec![path("id", "監視ID"), path("snapshot_id", "スナップショットID"), query("api_key", true, "APIキー")],
+            request_body: None,
+            responses: vec![
+                (200, "スナップショット", vec![Content::Json(schema::<SnapshotResponse>)]),
+                (404, "監視またはスナップショットが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/watches/{id}/diff",
+            tag: "ページ監視",
+            summary: "2つのスナップショットの差分",
+            description: "ブロック（見出し・段落・リスト項目等）単位で追加・削除されたものを返す。`from`・`to` を省略すると最新のスナップショットとその1つ前を比較する。同じ差分を `/watch/diff?id=...&api_key=...` で色分けしたHTMLとして表示できる。",
+            parameters: vec![
+                path("id", "監視ID"),
+                query("api_key", true, "APIキー"),
+                query("from", false, "比較元のスナップショットID（省略時は比較先の1つ前）"),
+                query("to", false, "比較先のスナップショットID（省略時は最新）"),
+            ],
+            request_body: None,
+            responses: vec![
+                (200, "差分", vec![Content::Json(schema::<WatchDiffResponse>)]),
+                (404, "監視またはスナップショットが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        // Cookieジャー
+        Operation {
+            method: "get",
+            path: "/api/v1/cookies",
+            tag: "Cookieジャー",
+            summary: "保持しているCookieの一覧",
+            description: "管理者がAPIキーごとに `cookie_jar` を有効にすると、取得時にCookieを送信し `Set-Cookie` で更新する。",
+            parameters: vec![query("api_key", true, "APIキー"), query("domain", false, "ドメインで絞り込む")],
+            request_body: None,
+            responses: vec![
+                (200, "Cookieの一覧", vec![Content::Json(schema::<CookieListResponse>)]),
+                (401, "APIキーが無効", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/cookies",
+            tag: "Cookieジャー",
+            summary: "Cookieを削除する",
+            description: "`domain` を省略するとすべて削除する。",
+            parameters: vec![query("api_key", true, "APIキー"), query("domain", false, "削除するドメイン")],
+            request_body: None,
+            responses: vec![(200, "削除した", vec![Content::Json(schema::<SimpleResponse>)])],
+        },
+        // APIキー管理
+        Operation {
+            method: "get",
+            path: "/api/v1/keys",
+            tag: "APIキー管理",
+            summary: "APIキーの一覧と使用量",
+            description: "",
+            parameters: vec![admin_key_query()],
+            request_body: None,
+            responses: vec![
+                (200, "APIキーの一覧", vec![Content::Json(schema::<UsageResponse>)]),
+                (401, "管理者キーが無効", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/keys",
+            tag: "APIキー管理",
+            summary: "APIキーを作成する",
+            description: "",
+            parameters: vec![],
+            request_body: Some(Content::Json(schema::<CreateKeyRequest>)),
+            responses: vec![
+                (201, "作成した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (409, "既に存在する", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "patch",
+            path: "/api/v1/keys/{key}",
+            tag: "APIキー管理",
+            summary: "APIキーの設定を更新する",
+            description: "指定したフィールドのみ更新する。",
+            parameters: vec![path("key", "APIキー")],
+            request_body: Some(Content::Json(schema::<ApiKeySettingsRequest>)),
+            responses: vec![
+                (200, "更新した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (404, "APIキーが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/keys/{key}",
+            tag: "APIキー管理",
+            summary: "APIキーを削除する",
+            description: "",
+            parameters: vec![path("key", "APIキー"), admin_key_query()],
+            request_body: None,
+            responses: vec![
+                (200, "削除した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (404, "APIキーが見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/keys/{key}/fingerprints",
+            tag: "APIキー管理",
+            summary: "クライアント証明書の指紋を登録する",
+            description: "登録した証明書で接続したクライアントは `api_key` を省略できる。",
+            parameters: vec![path("key", "APIキー")],
+            request_body: Some(Content::Json(schema::<FingerprintRequest>)),
+            responses: vec![
+                (200, "登録した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (409, "既に登録されている", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/keys/{key}/fingerprints/{fingerprint}",
+            tag: "APIキー管理",
+            summary: "クライアント証明書の指紋を削除する",
+            description: "",
+            parameters: vec![path("key", "APIキー"), path("fingerprint", "証明書のSHA-256指紋"), admin_key_query()],
+            request_body: None,
+            responses: vec![
+                (200, "削除した", vec![Content::Json(schema::<SimpleResponse>)]),
+                (404, "指紋が見つからない", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/statistics",
+            tag: "APIキー管理",
+            summary: "全体の統計",
+            description: "",
+            parameters: vec![admin_key_query()],
+            request_body: None,
+            responses: vec![
+                (200, "統計", vec![Content::Json(schema::<StatisticsResponse>)]),
+                (401, "管理者キーが無効", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/admin/login",
+            tag: "APIキー管理",
+            summary: "管理者キーを確認する",
+            description: "",
+            parameters: vec![],
+            request_body: Some(Content::Json(schema::<AdminLoginRequest>)),
+            responses: vec![
+                (200, "管理者キーが有効", vec![Content::Json(schema::<SimpleResponse>)]),
+                (401, "管理者キーが無効", vec![Content::Json(schema::<ErrorResponse>)]),
+            ],
+        },
+    ]
+}
+
+// ========== ドキュメントの生成 ==========
+
+// 初回呼び出し時に生成する
+pub fn openapi_document() -> &'static Value {
+    DOCUMENT.get_or_init(build_document)
+}
+
+fn build_document() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+
+    for operation in operations() {
+        let item = paths.entry(operation.path).or_insert_with(|| json!({}));
+        item[operation.method] = operation_object(&operation, &mut generator);
+    }
+
+    let schemas: Map<String, Value> = generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap_or(Value::Null)))
+        .collect();
+    let tags: Vec<Value> = TAGS
+        .iter()
+        .map(|(name, description)| json!({ "name": name, "description": description }))
+        .collect();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Rigil Proxy API",
+            "version": "1",
+            "description": "Webページを軽量化するプロキシのAPI。従来の `/api/process`、`/api/keys/create` 等のパスも引き続き使用できる。エラーはすべて `success: false` と `error`（`code`、`status`、`message`、`request_id`）の形で返し（`/api/process`、`/api/keys/*`、`/api/statistics`、`/api/admin/login` では以前の形の `error`（メッセージの文字列））、HTMLを返すエンドポイントでは同じ内容をエラーページに表示する。`request_id` はレスポンスの `X-Request-Id` ヘッダーと同じ。"
+        },
+        "tags": tags,
+        "paths": paths,
+        "components": { "schemas": schemas }
+    })
+}
+
+fn operation_object(operation: &Operation, generator: &mut SchemaGenerator) -> Value {
+    let parameters: Vec<Value> = operation
+        .parameters
+        .iter()
+        .map(|parameter| {
+            json!({
+                "name": parameter.name,
+                "in": parameter.location,
+                "required": parameter.required,
+                "description": parameter.description,
+                "schema": { "type": "string" }
+            })
+        })
+        .collect();
+
+    let mut responses = Map::new();
+    for (status, description, contents) in &operation.responses {
+        responses.insert(
+            status.to_string(),
+            json!({ "description": description, "content": content_object(contents, generator) }),
+        );
+    }
+
+    let mut object = json!({
+        "tags": [operation.tag],
+        "summary": operation.summary,
+        "parameters": parameters,
+        "responses": responses
+    });
+    if !operation.description.is_empty() {
+        object["description"] = json!(operation.description);
+    }
+    if let Some(body) = &operation.request_body {
+        object["requestBody"] = json!({
+            "required": true,
+            "content": content_object(std::slice::from_ref(body), generator)
+        });
+    }
+    object
+}
+
+fn content_object(contents: &[Content], generator: &mut SchemaGenerator) -> Value {
+    let mut object = Map::new();
+    for content in contents {
+        let (media_type, schema) = match content {
+            Content::Json(schema) => ("application/json", Some(schema(generator))),
+            Content::NdJson(schema) => ("application/x-ndjson", Some(schema(generator))),
+            Content::Other(media_type) => (*media_type, None),
+        };
+        let schema = schema.and_then(|schema| serde_json::to_value(schema).ok());
+        object.insert(media_type.to_string(), json!({ "schema": schema.unwrap_or_else(|| json!({})) }));
+    }
+    Value::Object(object)
+}
+
+// ========== HTMLドキュメント ==========
+
+// タグごとに操作を並べ、パラメータとリクエストボディのフィールドを表にする
+pub fn render_docs_html(document: &Value) -> String {
+    let mut html = String::new();
+    if let Some(description) = document["info"]["description"].as_str() {
+        html.push_str(&format!("<p>{}</p>", inline_code(description)));
+    }
+    html.push_str(&format!("<p>機械可読な定義: <a href=\"{0}\"><code>{0}</code></a>（OpenAPI 3）</p>", OPENAPI_PATH));
+
+    let empty = Map::new();
+    let paths = document["paths"].as_object().unwrap_or(&empty);
+    let tags = document["tags"].as_array().map(|tags| tags.as_slice()).unwrap_or(&[]);
+
+    for tag in tags {
+        let tag_name = tag["name"].as_str().unwrap_or("");
+        html.push_str(&format!("<h2>{}</h2>", htmlescape::encode_minimal(tag_name)));
+        if let Some(description) = tag["description"].as_str() {
+            html.push_str(&format!("<p>{}</p>", inline_code(description)));
+        }
+
+        for (path, item) in paths {
+            for method in METHODS {
+                let operation = &item[*method];
+                if operation["tags"][0].as_str() == Some(tag_name) {
+                    html.push_str(&render_operation(path, method, operation, document));
+                }
+            }
+        }
+    }
+
+    html
+}
+
+fn render_operation(path: &str, method: &str, operation: &Value, document: &Value) -> String {
+    let mut html = format!(
+        "<h3><strong>{}</strong> <code>{}</code></h3><p>{}</p>",
+        method.to_uppercase(),
+        htmlescape::encode_minimal(path),
+        htmlescape::encode_minimal(operation["summary"].as_str().unwrap_or(""))
+    );
+    if let Some(description) = operation["description"].as_str() {
+        html.push_str(&format!("<p>{}</p>", inline_code(description)));
+    }
+
+    let parameters = operation["parameters"].as_array().map(|p| p.as_slice()).unwrap_or(&[]);
+    if !parameters.is_empty() {
+        html.push_str("<table><tr><th>パラメータ</th><th>場所</th><th>必須</th><th>説明</th></tr>");
+        for parameter in parameters {
+            html.push_str(&format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(parameter["name"].as_str().unwrap_or("")),
+                parameter["in"].as_str().unwrap_or(""),
+                if parameter["required"].as_bool() == Some(true) { "○" } else { "" },
+                inline_code(parameter["description"].as_str().unwrap_or(""))
+            ));
+        }
+        html.push_str("</table>");
+    }
+
+    if let Some((media_type, content)) = operation["requestBody"]["content"].as_object().and_then(|c| c.iter().next()) {
+        html.push_str(&format!("<p>リクエストボディ（<code>{}</code>）</p>", htmlescape::encode_minimal(media_type)));
+        html.push_str(&render_fields(&content["schema"], document));
+    }
+
+    if let Some(responses) = operation["responses"].as_object() {
+        html.push_str("<ul>");
+        for (status, response) in responses {
+            let contents: Vec<String> = response["content"]
+                .as_object()
+                .map(|content| {
+                    content
+                        .iter()
+                        .map(|(media_type, body)| match schema_name(&body["schema"]) {
+                            Some(name) => format!("<code>{}</code> {}", htmlescape::encode_minimal(media_type), name),
+                            None => format!("<code>{}</code>", htmlescape::encode_minimal(media_type)),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            html.push_str(&format!(
+                "<li>{} {}: {}</li>",
+                status,
+                htmlescape::encode_minimal(response["description"].as_str().unwrap_or("")),
+                contents.join("、")
+            ));
+        }
+        html.push_str("</ul>");
+    }
+
+    html
+}
+
+// $refで参照しているスキーマのフィールドを表にする
+fn render_fields(schema: &Value, document: &Value) -> String {
+    let resolved = resolve_ref(schema, document);
+    let properties = match resolved["properties"].as_object() {
+        Some(properties) => properties,
+        None => return String::new(),
+    };
+    let required: Vec<&str> = resolved["required"]
+        .as_array()
+        .map(|names| names.iter().filter_map(|name| name.as_str()).collect())
+        .unwrap_or_default();
+
+    let mut html = String::from("<table><tr><th>フィールド</th><th>型</th><th>必須</th><th>説明</th></tr>");
+    for (name, property) in properties {
+        html.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(name),
+            type_label(property),
+            if required.contains(&name.as_str()) { "○" } else { "" },
+            inline_code(property["description"].as_str().unwrap_or(""))
+        ));
+    }
+    html.push_str("</table>");
+    html
+}
+
+fn resolve_ref<'a>(schema: &'a Value, document: &'a Value) -> &'a Value {
+    match schema_name(schema) {
+        Some(name) => &document["components"]["schemas"][name],
+        None => schema,
+    }
+}
+
+// "#/components/schemas/ApiResponse" → "ApiResponse"
+fn schema_name(schema: &Value) -> Option<&str> {
+    schema["$ref"].as_str().and_then(|reference| reference.rsplit('/').next())
+}
+
+fn type_label(property: &Value) -> String {
+    if let Some(name) = schema_name(property).or_else(|| property["allOf"][0]["$ref"].as_str().and_then(|r| r.rsplit('/').next())) {
+        return name.to_string();
+    }
+    match property["type"].as_str() {
+        Some("array") => format!("{}[]", type_label(&property["items"])),
+        Some(type_name) => type_name.to_string(),
+        None => String::new(),
+    }
+}
+
+// 説明文の `...` を<code>にする
+fn inline_code(text: &str) -> String {
+    htmlescape::encode_minimal(text)
+        .split('`')
+        .enumerate()
+        .map(|(i, part)| if i % 2 == 1 { format!("<code>{}</code>", part) } else { part.to_string() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_key::ApiKeyStore;
+    use crate::app_state::AppState;
+    use crate::bookmarks::BookmarkStore;
+    use crate::cookie_jar::CookieJarStore;
+    use crate::generated_feed::FeedLinkStore;
+    use crate::history::HistoryStore;
+    use crate::jobs::JobQueue;
+    use crate::saved::{SavedPageStore, ViewedPages};
+    use crate::search::SearchIndex;
+    use crate::watch::WatchStore;
+
+    use hyper::{Body, Request, StatusCode};
+    use std::sync::Arc;
+    use tokio::sync::{Mutex, RwLock, Semaphore};
+
+    // ファイルを読み込まない空の状態（ジョブのワーカーやストアの書き出しは起動しない）
+    fn empty_state() -> AppState {
+        let saved_pages = SavedPageStore::new();
+        AppState {
+            api_key_store: Arc::new(RwLock::new(ApiKeyStore::new())),
+            cookie_jars: Arc::new(RwLock::new(CookieJarStore::new())),
+            feed_links: Arc::new(RwLock::new(FeedLinkStore::new())),
+            jobs: Arc::new(JobQueue::new().0),
+            watches: Arc::new(RwLock::new(WatchStore::new())),
+            search_index: Arc::new(SearchIndex::build(&saved_pages)),
+            saved_pages: Arc::new(RwLock::new(saved_pages)),
+            viewed_pages: Arc::new(Mutex::new(ViewedPages::new())),
+            history: Arc::new(RwLock::new(HistoryStore::new())),
+            bookmarks: Arc::new(RwLock::new(BookmarkStore::new())),
+            batch_permits: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    // /api/v1のルーターに送り、どのルートにも一致しなかったか（APIキーがないため各ハンドラーは検証エラーを返す）
+    async fn is_unrouted(method: &str, path: &str) -> bool {
+        let request = Request::builder()
+            .method(method.to_uppercase().as_str())
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        let response = crate::handle_v1_request(request, empty_state()).await.unwrap();
+        if response.status() != StatusCode::NOT_FOUND {
+            return false;
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        value["error"]["code"] == "not_found"
+    }
+
+    #[tokio::test]
+    async fn every_documented_v1_operation_is_routed() {
+        assert!(is_unrouted("get", "/api/v1/no-such-route").await);
+
+        let operations = operations();
+        let v1_operations: Vec<&Operation> = operations.iter().filter(|operation| operation.path.starts_with("/api/v1/")).collect();
+        assert!(v1_operations.len() > 30);
+        for operation in v1_operations {
+            // パスのパラメータには適当な値を入れる
+            let path: Vec<&str> = operation
+                .path
+                .split('/')
+                .map(|segment| if segment.starts_with('{') { "test" } else { segment })
+                .collect();
+            let path = path.join("/");
+            assert!(!is_unrouted(operation.method, &path).await, "{} {} がルーターにありません", operation.method, operation.path);
+        }
+    }
+
+    #[test]
+    fn option_parameters_follow_api_process_options() {
+        let names: Vec<String> = option_parameters().into_iter().map(|parameter| parameter.name.into_owned()).collect();
+        for name in ["format", "profile", "links", "nav", "structure", "images", "forms", "ua", "encoding", "cache"] {
+            assert!(names.contains(&name.to_string()), "{}", name);
+        }
+        assert!(!names.contains(&"no_cache".to_string()));
+        assert!(option_parameters().iter().all(|parameter| !parameter.description.is_empty()));
+    }
+}
@@ -0,0 +1,179 @@
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+// 丸ごと書き直すJSONファイルの保存
+// 変更したストアは印を付けるだけにし、定期的に一時ファイルへ書いてから置き換える
+
+// ========== 定数 ==========
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// ========== JSONファイル ==========
+pub struct JsonFile {
+    path: PathBuf,
+    // 最後に書いてから内容が変わったか
+    dirty: AtomicBool,
+}
+
+impl JsonFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    // 起動時に読み込む（ファイルがない・解析できない場合はNone）
+    pub fn load<T: DeserializeOwned>(&self) -> Option<T> {
+        if !self.path.exists() {
+            return None;
+        }
+        let result = fs::read_to_string(&self.path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!("{}の読み込みエラー: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    // 次の書き出しで保存する
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::AcqRel)
+    }
+}
+
+// ========== 保存するストア ==========
+pub trait Persisted: Send + Sync + 'static {
+    fn json_file(&self) -> &JsonFile;
+    // ファイルに書く内容（ストアの読み取りロック中に呼ぶ）
+    fn to_json(&self) -> Result<String, String>;
+}
+
+// 変更されたストアを定期的に書き出すタスクを起動する
+pub fn start_flusher<S: Persisted>(store: Arc<RwLock<S>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush(&store).await;
+        }
+    });
+}
+
+// 変更があれば書き出す（ファイルへの書き込み中はロックを持たない）
+pub async fn flush<S: Persisted>(store: &RwLock<S>) {
+    let (path, json) = {
+        let store = store.read().await;
+        let file = store.json_file();
+        if !file.take_dirty() {
+            return;
+        }
+        match store.to_json() {
+            Ok(json) => (file.path.clone(), json),
+            Err(e) => {
+                eprintln!("{}の保存エラー: {}", file.path.display(), e);
+                return;
+            }
+        }
+    };
+
+    let result = run_blocking({
+        let path = path.clone();
+        move || write_atomically(&path, json.as_bytes())
+    })
+    .await
+    .and_then(|result| result.map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        eprintln!("{}の保存エラー: {}", path.display(), e);
+        // 次の書き出しで再試行する
+        store.read().await.json_file().mark_dirty();
+    }
+}
+
+// 一時ファイルに書いてから置き換える（書き込み途中で止まっても元のファイルは壊れない）
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+// ========== ヘルパー関数 ==========
+
+// 保存するレコードのID（lenバイトの乱数を16進数で表す）
+pub fn random_hex_id(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+// ファイルの読み書きやCPUを使う処理は、非同期のワーカーを止めないようブロッキング用のスレッドで行う
+pub async fn run_blocking<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Counter {
+        #[serde(skip)]
+        file: Option<JsonFile>,
+        count: u32,
+    }
+
+    impl Persisted for Counter {
+        fn json_file(&self) -> &JsonFile {
+            self.file.as_ref().unwrap()
+        }
+
+        fn to_json(&self) -> Result<String, String> {
+            serde_json::to_string(self).map_err(|e| e.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn flush_writes_only_after_a_change() {
+        let path = std::env::temp_dir().join(format!("rigil-persist-{}.json", rand::random::<u64>()));
+        let store = RwLock::new(Counter { file: Some(JsonFile::new(&path)), count: 1 });
+
+        flush(&store).await;
+        assert!(!path.exists());
+
+        {
+            let mut counter = store.write().await;
+            counter.count = 2;
+            counter.json_file().mark_dirty();
+        }
+        flush(&store).await;
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"count":2}"#);
+        assert!(!path.with_file_name(format!("{}.tmp", path.file_name().unwrap().to_string_lossy())).exists());
+
+        let loaded: Option<serde_json::Value> = store.read().await.json_file().load();
+        assert_eq!(loaded.unwrap()["count"], 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+use crate::profile::{ProcessingProfile, CODE_TAGS, TABLE_TAGS};
+use crate::upstream::{available_user_agents, find_user_agent};
+
+use encoding_rs::Encoding;
+use std::collections::HashMap;
+
+// リクエストごとの軽量化オプション（クエリパラメータから生成）
+
+// ========== リンク表示形式 ==========
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LinkStyle {
+    // 本文中に<a>を置く（従来の表示）
+    #[default]
+    Inline,
+    // 本文には番号だけを置き、ページ末尾にリンク一覧を出す
+    Footnotes,
+    // 見出しごとにリンク一覧を出す
+    SectionFootnotes,
+}
+
+impl LinkStyle {
+    fn from_param(value: &str) -> Option<Self> {
+        match value {
+            "inline" => Some(LinkStyle::Inline),
+            "footnotes" => Some(LinkStyle::Footnotes),
+            "sections" => Some(LinkStyle::SectionFootnotes),
+            _ => None,
+        }
+    }
+
+    fn as_param(&self) -> &'static str {
+        match self {
+            LinkStyle::Inline => "inline",
+            LinkStyle::Footnotes => "footnotes",
+            LinkStyle::SectionFootnotes => "sections",
+        }
+    }
+}
+
+// ========== 画像の扱い ==========
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ImageMode {
+    // 画像を除去する（従来の動作）
+    #[default]
+    None,
+    // 代替テキストを[画像: ...]として表示する
+    Alt,
+    // 代替テキストからプロキシ経由の画像へリンクする
+    Link,
+    // プロキシで縮小したサムネイルを表示する
+    Thumb,
+}
+
+impl ImageMode {
+    fn from_param(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(ImageMode::None),
+            "alt" => Some(ImageMode::Alt),
+            "link" => Some(ImageMode::Link),
+            "thumb" => Some(ImageMode::Thumb),
+            _ => None,
+        }
+    }
+
+    fn as_param(&self) -> &'static str {
+        match self {
+            ImageMode::None => "none",
+            ImageMode::Alt => "alt",
+            ImageMode::Link => "link",
+            ImageMode::Thumb => "thumb",
+        }
+    }
+}
+
+// ========== フォームの扱い ==========
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum FormMode {
+    // フォームを除去する（従来の動作）
+    #[default]
+    None,
+    // GETフォームをプロキシ経由で送信できる形で残す
+    Get,
+    // GETフォームに加え、POSTフォームもPOST /proxy経由で送信できる形で残す
+    // （APIキーでPOSTの転送が許可されている必要がある）
+    Post,
+}
+
+impl FormMode {
+    fn from_param(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(FormMode::None),
+            "get" => Some(FormMode::Get),
+            "post" => Some(FormMode::Post),
+            _ => None,
+        }
+    }
+
+    fn as_param(&self) -> &'static str {
+        match self {
+            FormMode::None => "none",
+            FormMode::Get => "get",
+            FormMode::Post => "post",
+        }
+    }
+}
+
+// ========== 出力形式 ==========
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum OutputFormat {
+    // 軽量化したHTML（従来の出力）
+    #[default]
+    Html,
+    // 見出し・段落・リスト・リンクを整えたプレーンテキスト
+    Text,
+}
+
+impl OutputFormat {
+    fn from_param(value: &str) -> Option<Self> {
+        match value {
+            "html" => Some(OutputFormat::Html),
+            "text" => Some(OutputFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+// フォームとして残すタグ
+const FORM_TAGS: &[&str] = &["form", "input", "select", "option", "textarea", "button", "label"];
+
+// ========== オプション ==========
+#[derive(Clone, Debug)]
+pub struct ProcessOptions {
+    pub profile: ProcessingProfile,
+    // profile=がリクエストで明示されたか（明示された場合のみリンクに引き継ぐ）
+    pub profile_explicit: bool,
+    pub link_style: LinkStyle,
+    // メニューやフッターのようなリンクの塊を除去する
+    pub drop_nav_links: bool,
+    // 表・整形済みテキスト・コード・引用を残す
+    pub keep_structure: bool,
+    pub images: ImageMode,
+    pub forms: FormMode,
+    // 上流へのリクエストに使うUser-Agentプロファイル名
+    pub user_agent: Option<String>,
+    // JSON APIの出力形式（リンクには引き継がない）
+    pub format: OutputFormat,
+    // 上流ページの文字コード（指定した場合はContent-Typeのcharsetより優先する）
+    pub encoding: Option<&'static Encoding>,
+    // 上流と途中のキャッシュを使わずに取得する（リンクには引き継がない）
+    pub bypass_cache: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            profile: ProcessingProfile::default_profile(),
+            profile_explicit: false,
+            link_style: LinkStyle::default(),
+            drop_nav_links: false,
+            keep_structure: false,
+            images: ImageMode::default(),
+            forms: FormMode::default(),
+            user_agent: None,
+            format: OutputFormat::default(),
+            encoding: None,
+            bypass_cache: false,
+        }
+    }
+}
+
+impl ProcessOptions {
+    // key_default_profileはAPIキーに設定された既定プロファイル
+    pub fn from_params(params: &HashMap<String, String>, key_default_profile: Option<&str>) -> Result<Self, String> {
+        let requested_profile = params.get("profile").filter(|name| !name.is_empty());
+        let profile = match requested_profile {
+            Some(name) => ProcessingProfile::find(name).ok_or_else(|| {
+                format!("不明なプロファイルです: {}（使用可能: {}）", name, ProcessingProfile::available_names().join(", "))
+            })?,
+            None => ProcessingProfile::key_default(key_default_profile),
+        };
+
+        let user_agent = match params.get("ua").filter(|name| !name.is_empty()) {
+            Some(name) if find_user_agent(name).is_none() => {
+                return Err(format!("不明なUser-Agentプロファイルです: {}（使用可能: {}）", name, available_user_agents().join(", ")));
+            }
+            other => other.cloned(),
+        };
+
+        let encoding = match params.get("encoding").filter(|label| !label.is_empty()) {
+            Some(label) => Some(Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("不明な文字コードです: {}", label))?),
+            None => None,
+        };
+
+        // 残りのパラメータも不明な値を既定値として扱わず、エラーにする
+        if let Some((name, message)) = Self::param_errors(params).into_iter().next() {
+            return Err(format!("{}: {}", name, message));
+        }
+
+        Ok(Self {
+            profile,
+            profile_explicit: requested_profile.is_some(),
+            link_style: params
+                .get("links")
+                .and_then(|v| LinkStyle::from_param(v))
+                .unwrap_or_default(),
+            drop_nav_links: params.get("nav").map(|v| v == "drop").unwrap_or(false),
+            keep_structure: params.get("structure").map(|v| v == "keep").unwrap_or(false),
+            images: params
+                .get("images")
+                .and_then(|v| ImageMode::from_param(v))
+                .unwrap_or_default(),
+            forms: params
+                .get("forms")
+                .and_then(|v| FormMode::from_param(v))
+                .unwrap_or_default(),
+            user_agent,
+            format: params
+                .get("format")
+                .and_then(|v| OutputFormat::from_param(v))
+                .unwrap_or_default(),
+            encoding,
+            bypass_cache: params.get("cache").map(|v| v == "bypass").unwrap_or(false),
+        })
+    }
+
+    // 指定されたパラメータを1つずつ検証し、不正なものを (パラメータ名, 理由) で返す
+    // （JSONの入力検証ではすべてのフィールドのエラーを返すために使う）
+    pub fn param_errors(params: &HashMap<String, String>) -> Vec<(&'static str, String)> {
+        let mut errors = Vec::new();
+        let mut check = |name: &'static str, valid: &dyn Fn(&str) -> bool, expected: String| {
+            if let Some(value) = params.get(name).filter(|v| !v.is_empty()) {
+                if !valid(value) {
+                    errors.push((name, format!("不正な値です: {}（使用可能: {}）", value, expected)));
+                }
+            }
+        };
+
+        check("profile", &|v| ProcessingProfile::find(v).is_some(), ProcessingProfile::available_names().join(", "));
+        check("links", &|v| LinkStyle::from_param(v).is_some(), "inline, footnotes, sections".to_string());
+        check("nav", &|v| v == "drop", "drop".to_string());
+        check("structure", &|v| v == "keep", "keep".to_string());
+        check("images", &|v| ImageMode::from_param(v).is_some(), "none, alt, link, thumb".to_string());
+        check("forms", &|v| FormMode::from_param(v).is_some(), "none, get, post".to_string());
+        check("ua", &|v| find_user_agent(v).is_some(), available_user_agents().join(", "));
+        check("format", &|v| OutputFormat::from_param(v).is_some(), "html, text".to_string());
+        check("encoding", &|v| Encoding::for_label(v.as_bytes()).is_some(), "utf-8, shift_jis, euc-jp 等".to_string());
+        check("cache", &|v| v == "bypass", "bypass".to_string());
+        errors
+    }
+
+    // プロファイルとオプションに応じてカリングする
+    pub fn cull(&self, html: &str) -> String {
+        let mut extra_tags: Vec<&str> = Vec::new();
+        if self.keep_structure {
+            extra_tags.extend(TABLE_TAGS);
+            extra_tags.extend(CODE_TAGS);
+            extra_tags.push("blockquote");
+        }
+        if self.images != ImageMode::None {
+            extra_tags.push("img");
+        }
+        if self.forms != FormMode::None {
+            extra_tags.extend(FORM_TAGS);
+        }
+        self.profile.cull_with_extra_tags(html, &extra_tags)
+    }
+
+    // プロキシ経由のリンクやフォームに引き継ぐパラメータ（既定値のものは含めない）
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        if self.profile_explicit {
+            pairs.push(("profile", self.profile.name.clone()));
+        }
+        if self.link_style != LinkStyle::Inline {
+            pairs.push(("links", self.link_style.as_param().to_string()));
+        }
+        if self.drop_nav_links {
+            pairs.push(("nav", "drop".to_string()));
+        }
+        if self.keep_structure {
+            pairs.push(("structure", "keep".to_string()));
+        }
+        if self.images != ImageMode::None {
+            pairs.push(("images", self.images.as_param().to_string()));
+        }
+        if self.forms != FormMode::None {
+            pairs.push(("forms", self.forms.as_param().to_string()));
+        }
+        if let Some(user_agent) = &self.user_agent {
+            pairs.push(("ua", user_agent.clone()));
+        }
+        if let Some(encoding) = self.encoding {
+            pairs.push(("encoding", encoding.name().to_lowercase()));
+        }
+        pairs
+    }
+
+    // プロキシ経由のリンクに引き継ぐクエリ文字列（先頭の&を含む、既定値なら空）
+    pub fn to_query(&self) -> String {
+        self.to_pairs()
+            .iter()
+            .map(|(name, value)| format!("&{}={}", name, urlencoding::encode(value)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn unknown_option_values_are_rejected_instead_of_falling_back() {
+        let options = ProcessOptions::from_params(&params(&[("links", "footnotes"), ("nav", "drop"), ("cache", "")]), None).unwrap();
+        assert_eq!(options.link_style, LinkStyle::Footnotes);
+        assert!(options.drop_nav_links);
+
+        for (name, value) in [("links", "endnotes"), ("images", "full"), ("forms", "put"), ("format", "pdf"), ("nav", "keep"), ("structure", "drop"), ("cache", "skip")] {
+            let error = ProcessOptions::from_params(&params(&[(name, value)]), None).unwrap_err();
+            assert!(error.starts_with(&format!("{}: ", name)), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::config::Config;
+use crate::culling::cull_html;
+
+use serde::Deserialize;
+
+// 軽量化プロファイル：残すタグ、リンクの書き換え、リンクテキストの長さを決める
+
+// ========== 定数 ==========
+pub const DEFAULT_PROFILE: &str = "article";
+
+// reader_mode_maker::cullingが残すタグ
+const ARTICLE_TAGS: &[&str] = &["title", "br", "h1", "h2", "h3", "h4", "h5", "h6", "b", "i", "ul", "li", "ol", "a"];
+const MINIMAL_TAGS: &[&str] = &["title", "br", "h1", "h2", "h3", "a"];
+pub const TABLE_TAGS: &[&str] = &["table", "caption", "thead", "tbody", "tfoot", "tr", "th", "td"];
+pub const CODE_TAGS: &[&str] = &["pre", "code"];
+
+// ========== プロファイル ==========
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProcessingProfile {
+    pub name: String,
+    pub allowed_tags: Vec<String>,
+    #[serde(default = "default_rewrite_links")]
+    pub rewrite_links: bool,
+    #[serde(default = "default_link_text_max_chars")]
+    pub link_text_max_chars: usize,
+}
+
+fn default_rewrite_links() -> bool {
+    true
+}
+
+fn default_link_text_max_chars() -> usize {
+    50
+}
+
+impl ProcessingProfile {
+    fn builtin(name: &str, tag_sets: &[&[&str]], link_text_max_chars: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            allowed_tags: tag_sets.iter().flat_map(|set| set.iter().map(|t| t.to_string())).collect(),
+            rewrite_links: true,
+            link_text_max_chars,
+        }
+    }
+
+    pub fn builtin_profiles() -> Vec<Self> {
+        vec![
+            Self::builtin("minimal", &[MINIMAL_TAGS], 30),
+            Self::builtin(DEFAULT_PROFILE, &[ARTICLE_TAGS], 50),
+            Self::builtin("tables", &[ARTICLE_TAGS, TABLE_TAGS], 50),
+            Self::builtin("code", &[ARTICLE_TAGS, CODE_TAGS], 50),
+        ]
+    }
+
+    // 設定ファイルのプロファイルを優先し、なければ組み込みプロファイルから探す
+    pub fn find(name: &str) -> Option<Self> {
+        Config::global()
+            .profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .or_else(|| Self::builtin_profiles().into_iter().find(|profile| profile.name == name))
+    }
+
+    pub fn default_profile() -> Self {
+        Self::find(DEFAULT_PROFILE).unwrap_or_else(|| Self::builtin(DEFAULT_PROFILE, &[ARTICLE_TAGS], 50))
+    }
+
+    // APIキーに保存された既定プロファイル（設定から削除されていれば警告して既定に戻す）
+    pub fn key_default(name: Option<&str>) -> Self {
+        match name {
+            Some(name) => Self::find(name).unwrap_or_else(|| {
+                eprintln!("APIキーの既定プロファイルが見つかりません（{}を使用します）: {}", DEFAULT_PROFILE, name);
+                Self::default_profile()
+            }),
+            None => Self::default_profile(),
+        }
+    }
+
+    pub fn available_names() -> Vec<String> {
+        let mut names: Vec<String> = Self::builtin_profiles().into_iter().map(|p| p.name).collect();
+        for profile in &Config::global().profiles {
+            if !names.contains(&profile.name) {
+                names.push(profile.name.clone());
+            }
+        }
+        names
+    }
+
+    pub fn cull(&self, html: &str) -> String {
+        self.cull_with_extra_tags(html, &[])
+    }
+
+    // プロファイルのタグに加えてextra_tagsも残す
+    // 残すタグが従来と同じならreader_mode_makerのカリングをそのまま使う
+    pub fn cull_with_extra_tags(&self, html: &str, extra_tags: &[&str]) -> String {
+        let mut allowed_tags = self.allowed_tags.clone();
+        for tag in extra_tags {
+            if !allowed_tags.iter().any(|t| t == tag) {
+                allowed_tags.push(tag.to_string());
+            }
+        }
+
+        let uses_article_tags = allowed_tags.len() == ARTICLE_TAGS.len()
+            && ARTICLE_TAGS.iter().all(|tag| allowed_tags.iter().any(|t| t == tag));
+
+        if uses_article_tags {
+            reader_mode_maker::culling(html)
+        } else {
+            cull_html(html, &allowed_tags)
+        }
+    }
+}
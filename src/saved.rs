@@ -0,0 +1,296 @@
+use crate::api_types::SavedPageInfo;
+use crate::fetcher::FetchedPage;
+use crate::html_parser::{SAVE_FORM_END, SAVE_FORM_START};
+use crate::persist::{random_hex_id, JsonFile, Persisted};
+use crate::process_options::ProcessOptions;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+// 後で読むために保存した軽量化ページ（元のページが変わったり消えたりしても保存時の内容を表示できる）
+
+// ========== 定数 ==========
+const SAVED_PAGES_FILE: &str = "saved_pages.json";
+// 保存ボタンのために控えておく表示したページの数と期間
+const MAX_VIEWED_PAGES: usize = 100;
+const VIEWED_PAGE_TTL: Duration = Duration::from_secs(60 * 60);
+
+// ========== データ構造 ==========
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedPage {
+    pub id: String,
+    // 保存したAPIキー（同じキーからのみ参照できる）
+    pub api_key: String,
+    pub url: String,
+    pub title: String,
+    pub saved_at: String,
+    pub original_size_bytes: u64,
+    // 軽量化したHTML
+    pub content: String,
+    // format=text用のプレーンテキスト
+    pub text: String,
+}
+
+impl SavedPage {
+    pub fn info(&self) -> SavedPageInfo {
+        SavedPageInfo {
+            id: self.id.clone(),
+            url: self.url.clone(),
+            title: self.title.clone(),
+            saved_at: self.saved_at.clone(),
+            original_size_bytes: self.original_size_bytes,
+            processed_size_bytes: self.content.len() as u64,
+        }
+    }
+}
+
+// ========== 保存ストア ==========
+pub struct SavedPageStore {
+    pages: HashMap<String, SavedPage>,
+    file: JsonFile,
+}
+
+impl SavedPageStore {
+    pub fn new() -> Self {
+        Self {
+            pages: HashMap::new(),
+            file: JsonFile::new(SAVED_PAGES_FILE),
+        }
+    }
+
+    pub fn load_from_file() -> Self {
+        let mut store = Self::new();
+        if let Some(pages) = store.file.load() {
+            store.pages = pages;
+        }
+        store
+    }
+
+    pub fn count_for(&self, api_key: &str) -> usize {
+        self.pages.values().filter(|page| page.api_key == api_key).count()
+    }
+
+    // APIキーの保存数が上限に達している場合は追加せずfalseを返す
+    pub fn add(&mut self, page: SavedPage, max_pages: usize) -> bool {
+        if self.count_for(&page.api_key) >= max_pages {
+            return false;
+        }
+        self.pages.insert(page.id.clone(), page);
+        self.file.mark_dirty();
+        true
+    }
+
+    // APIキーが保存したページのみ返す（新しいものから）
+    pub fn list(&self, api_key: &str) -> Vec<SavedPage> {
+        let mut pages: Vec<SavedPage> = self.pages.values().filter(|page| page.api_key == api_key).cloned().collect();
+        pages.sort_by(|a, b| b.saved_at.cmp(&a.saved_at));
+        pages
+    }
+
+    // すべてのAPIキーのページ（検索インデックスの作成用）
+    pub fn pages(&self) -> impl Iterator<Item = &SavedPage> {
+        self.pages.values()
+    }
+
+    pub fn get(&self, id: &str, api_key: &str) -> Option<SavedPage> {
+        self.pages.get(id).filter(|page| page.api_key == api_key).cloned()
+    }
+
+    // 見つからない場合はfalse
+    pub fn remove(&mut self, id: &str, api_key: &str) -> bool {
+        if self.get(id, api_key).is_none() {
+            return false;
+        }
+        self.pages.remove(id);
+        self.file.mark_dirty();
+        true
+    }
+}
+
+impl Persisted for SavedPageStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.pages).map_err(|e| e.to_string())
+    }
+}
+
+// ========== 表示したページの控え ==========
+// 保存ボタンで表示中の内容をそのまま保存できるよう、ブラウザで表示したページをメモリに一定期間控えておく
+// （取得し直すと内容が変わることがあり、POSTの結果は取得し直せない）
+struct ViewedPage {
+    api_key: String,
+    page: Arc<FetchedPage>,
+    options: ProcessOptions,
+    viewed_at: Instant,
+}
+
+pub struct ViewedPages {
+    pages: HashMap<String, ViewedPage>,
+}
+
+impl ViewedPages {
+    pub fn new() -> Self {
+        Self {
+            pages: HashMap::new(),
+        }
+    }
+
+    // 控えたページのIDを返す（期限切れと、上限を超えた古いものは削除する）
+    pub fn remember(&mut self, api_key: &str, page: FetchedPage, options: &ProcessOptions) -> String {
+        self.pages.retain(|_, viewed| viewed.viewed_at.elapsed() < VIEWED_PAGE_TTL);
+        while self.pages.len() >= MAX_VIEWED_PAGES {
+            let oldest = self.pages.iter().min_by_key(|(_, viewed)| viewed.viewed_at).map(|(id, _)| id.clone());
+            match oldest {
+                Some(id) => self.pages.remove(&id),
+                None => break,
+            };
+        }
+
+        let id = generate_saved_page_id();
+        self.pages.insert(
+            id.clone(),
+            ViewedPage {
+                api_key: api_key.to_string(),
+                page: Arc::new(page),
+                options: options.clone(),
+                viewed_at: Instant::now(),
+            },
+        );
+        id
+    }
+
+    // 同じAPIキーで表示したページのみ返す
+    pub fn get(&self, id: &str, api_key: &str) -> Option<(Arc<FetchedPage>, ProcessOptions)> {
+        self.pages
+            .get(id)
+            .filter(|viewed| viewed.api_key == api_key && viewed.viewed_at.elapsed() < VIEWED_PAGE_TTL)
+            .map(|viewed| (viewed.page.clone(), viewed.options.clone()))
+    }
+}
+
+// ========== HTMLの出力 ==========
+
+// 保存したページを表示する（保存ボタンを保存日時と元のページへのリンクに置き換える）
+pub fn render_saved_page(page: &SavedPage, original_link: &str, list_link: &str) -> String {
+    let banner = format!(
+        "<p class=\"rigil-links\">{}に保存したページです <a href=\"{}\">元のページ</a><a href=\"{}\">保存したページの一覧</a></p>",
+        htmlescape::encode_minimal(&page.saved_at),
+        htmlescape::encode_minimal(original_link),
+        htmlescape::encode_minimal(list_link)
+    );
+
+    let save_form = page.content.find(SAVE_FORM_START).and_then(|start| {
+        let end = start + page.content[start..].find(SAVE_FORM_END)? + SAVE_FORM_END.len();
+        Some((start, end))
+    });
+    match save_form {
+        Some((start, end)) => format!("{}{}{}", &page.content[..start], banner, &page.content[end..]),
+        // HTML以外のコンテンツには保存ボタンがないため本文の先頭に置く
+        None => page.content.replacen("<body>", &format!("<body>{}", banner), 1),
+    }
+}
+
+// 保存したページの一覧（page_linkは保存したページのIDを受け取り表示用のURLを返す）
+pub fn render_saved_list(pages: &[SavedPage], epub_link: &str, page_link: impl Fn(&str) -> String) -> String {
+    let escape = |text: &str| htmlescape::encode_minimal(text);
+    let mut html = String::from("<h1>保存したページ</h1>\n");
+    if pages.is_empty() {
+        html.push_str("<p>保存したページはありません。軽量化したページの「このページを保存」から保存できます。</p>\n");
+    } else {
+        html.push_str(&format!("<p class=\"rigil-links\"><a href=\"{}\">EPUBでダウンロード</a></p>\n", escape(epub_link)));
+    }
+    html.push_str("<ul>\n");
+    for page in pages {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a><br><small>{} {}</small></li>\n",
+            escape(&page_link(&page.id)),
+            escape(&page.title),
+            escape(&page.saved_at),
+            escape(&page.url)
+        ));
+    }
+    html.push_str("</ul>\n<p><a href=\"/\">ホーム画面に戻る</a></p>\n");
+    html
+}
+
+// ========== ヘルパー関数 ==========
+
+pub fn generate_saved_page_id() -> String {
+    random_hex_id(16)
+}
+
+// ========== 型エイリアス ==========
+pub type SharedSavedPageStore = Arc<RwLock<SavedPageStore>>;
+pub type SharedViewedPages = Arc<Mutex<ViewedPages>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::ContentKind;
+    use crate::fetcher::PageBody;
+
+    fn saved_page(api_key: &str) -> SavedPage {
+        SavedPage {
+            id: generate_saved_page_id(),
+            api_key: api_key.to_string(),
+            url: "https://example.com/".to_string(),
+            title: "例".to_string(),
+            saved_at: chrono::Utc::now().to_rfc3339(),
+            original_size_bytes: 10,
+            content: "<html><body><p>本文</p></body></html>".to_string(),
+            text: "本文".to_string(),
+        }
+    }
+
+    fn fetched_page(body: &str) -> FetchedPage {
+        FetchedPage {
+            final_url: "https://example.com/result".to_string(),
+            content_type: Some("text/html".to_string()),
+            content_length: None,
+            kind: ContentKind::Html,
+            body: PageBody::Text(body.to_string()),
+        }
+    }
+
+    #[test]
+    fn add_stops_at_the_limit_per_key() {
+        let path = std::env::temp_dir().join(format!("rigil-saved-{}.json", rand::random::<u64>()));
+        let mut store = SavedPageStore { pages: HashMap::new(), file: JsonFile::new(&path) };
+        assert!(store.add(saved_page("key1"), 2));
+        assert!(store.add(saved_page("key1"), 2));
+        assert!(!store.add(saved_page("key1"), 2));
+        assert!(store.add(saved_page("key2"), 2));
+        assert_eq!(store.count_for("key1"), 2);
+    }
+
+    #[test]
+    fn viewed_pages_are_kept_per_key_and_capped() {
+        let mut viewed = ViewedPages::new();
+        let first = viewed.remember("key1", fetched_page("<p>最初</p>"), &ProcessOptions::default());
+        let (page, _) = viewed.get(&first, "key1").unwrap();
+        assert!(matches!(&page.body, PageBody::Text(body) if body == "<p>最初</p>"));
+        assert!(viewed.get(&first, "key2").is_none());
+
+        for _ in 0..MAX_VIEWED_PAGES {
+            viewed.remember("key1", fetched_page("<p>次</p>"), &ProcessOptions::default());
+        }
+        assert_eq!(viewed.pages.len(), MAX_VIEWED_PAGES);
+        assert!(viewed.get(&first, "key1").is_none());
+    }
+
+    #[test]
+    fn saved_page_replaces_the_save_form_with_a_banner() {
+        let mut page = saved_page("key1");
+        page.content = crate::html_parser::add_save_form(&page.content, "view1");
+        assert!(page.content.contains("/save?view=view1"));
+        let html = render_saved_page(&page, "/proxy?url=x", "/saved");
+        assert!(!html.contains("<form"));
+        assert!(html.contains("元のページ"));
+    }
+}
@@ -0,0 +1,455 @@
+use crate::persist::run_blocking;
+use crate::saved::{SavedPage, SavedPageStore};
+
+use std::sync::{Arc, Mutex};
+use tantivy::collector::TopDocs;
+use tantivy::query::{BooleanQuery, BoostQuery, Occur, Query, QueryParser, RegexQuery, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, STORED, STRING};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::tokenizer::{TextAnalyzer, Token, TokenStream, Tokenizer};
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+// 保存したページの全文検索（インデックスはメモリ上に持ち、起動時にsaved_pages.jsonから作り直す）
+
+// ========== 定数 ==========
+const TOKENIZER_NAME: &str = "rigil_bigram";
+// 検索結果の件数（limitの既定値と上限）
+pub const DEFAULT_SEARCH_RESULTS: usize = 20;
+pub const MAX_SEARCH_RESULTS: usize = 100;
+// インデックス作成に使うメモリ（tantivyの下限は15MB）
+const WRITER_MEMORY_BYTES: usize = 20_000_000;
+// スニペットの最大バイト数
+const SNIPPET_MAX_BYTES: usize = 300;
+// タイトルに一致した場合のスコアの倍率
+const TITLE_BOOST: f32 = 2.0;
+
+// ========== トークナイザー ==========
+
+// 英数字は単語ごと（小文字にする）、日本語・中国語・韓国語は2文字ずつずらして区切る
+// （検索語も同じく区切り、連続した位置に一致するものを探すため語の途中からも検索できる。
+// 1文字だけの検索語はその文字を含むトークンを探す）
+#[derive(Clone)]
+struct BigramTokenizer;
+
+struct BigramTokenStream {
+    tokens: Vec<Token>,
+    // 次に返すトークンの位置+1（0は開始前）
+    index: usize,
+}
+
+impl Tokenizer for BigramTokenizer {
+    type TokenStream<'a> = BigramTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> BigramTokenStream {
+        BigramTokenStream {
+            tokens: tokenize(text),
+            index: 0,
+        }
+    }
+}
+
+impl TokenStream for BigramTokenStream {
+    fn advance(&mut self) -> bool {
+        if self.index < self.tokens.len() {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharKind {
+    Word,
+    Cjk,
+    Separator,
+}
+
+fn char_kind(c: char) -> CharKind {
+    let cjk = matches!(c,
+        '\u{3040}'..='\u{30FF}' // ひらがな・カタカナ
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}' // 漢字
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{AC00}'..='\u{D7AF}' // ハングル
+        | '\u{FF66}'..='\u{FF9F}' // 半角カタカナ
+    );
+    if cjk {
+        CharKind::Cjk
+    } else if c.is_alphanumeric() {
+        CharKind::Word
+    } else {
+        CharKind::Separator
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let push = |tokens: &mut Vec<Token>, from: usize, to: usize| {
+        let position = tokens.len();
+        tokens.push(Token {
+            offset_from: from,
+            offset_to: to,
+            position,
+            text: text[from..to].to_lowercase(),
+            position_length: 1,
+        });
+    };
+
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let end_of = |index: usize| chars.get(index).map(|(offset, _)| *offset).unwrap_or(text.len());
+    let mut start = 0;
+    while start < chars.len() {
+        let kind = char_kind(chars[start].1);
+        let mut end = start + 1;
+        while end < chars.len() && char_kind(chars[end].1) == kind {
+            end += 1;
+        }
+        match kind {
+            CharKind::Word => push(&mut tokens, chars[start].0, end_of(end)),
+            // 1文字だけの場合はそのまま、2文字以上は2文字ずつ
+            CharKind::Cjk if end - start == 1 => push(&mut tokens, chars[start].0, end_of(end)),
+            CharKind::Cjk => {
+                for pair in chars[start..end].windows(2) {
+                    push(&mut tokens, pair[0].0, pair[1].0 + pair[1].1.len_utf8());
+                }
+            }
+            CharKind::Separator => {}
+        }
+        start = end;
+    }
+    tokens
+}
+
+// ========== データ構造 ==========
+
+#[derive(Clone, Copy)]
+struct Fields {
+    id: Field,
+    api_key: Field,
+    title: Field,
+    body: Field,
+}
+
+pub struct SearchHit {
+    pub page: SavedPage,
+    pub score: f32,
+    // 一致した部分を含む本文の抜粋（HTML版は一致部分を<b>で囲む）
+    pub snippet: String,
+    pub snippet_html: String,
+}
+
+// ========== 検索インデックス ==========
+// 作成に失敗した場合は検索を無効にする（ページの保存は続けられる）
+pub struct SearchIndex {
+    engine: Option<Engine>,
+}
+
+struct Engine {
+    index: Index,
+    // 変更とコミットは一度に一つずつ行う
+    writer: Arc<Mutex<IndexWriter>>,
+    reader: IndexReader,
+    fields: Fields,
+}
+
+impl SearchIndex {
+    // 保存したページからインデックスを作る
+    pub fn build(store: &SavedPageStore) -> Self {
+        match Engine::build(store) {
+            Ok(engine) => Self { engine: Some(engine) },
+            Err(e) => {
+                eprintln!("検索インデックスの作成に失敗しました（検索は無効になります）: {}", e);
+                Self { engine: None }
+            }
+        }
+    }
+
+    // 同じIDのページは置き換える
+    pub async fn add_page(&self, page: &SavedPage) -> Result<(), String> {
+        let engine = match &self.engine {
+            Some(engine) => engine,
+            None => return Ok(()),
+        };
+        let id = Term::from_field_text(engine.fields.id, &page.id);
+        let document = engine.page_document(page);
+        engine
+            .update(move |writer| {
+                writer.delete_term(id);
+                writer.add_document(document).map(|_| ())
+            })
+            .await
+    }
+
+    pub async fn remove_page(&self, id: &str) -> Result<(), String> {
+        let engine = match &self.engine {
+            Some(engine) => engine,
+            None => return Ok(()),
+        };
+        let id = Term::from_field_text(engine.fields.id, id);
+        engine
+            .update(move |writer| {
+                writer.delete_term(id);
+                Ok(())
+            })
+            .await
+    }
+
+    // APIキーが保存したページから、スコアの高い順にlimit件返す（抜粋はstoreの本文から作る）
+    pub fn search(&self, api_key: &str, query_text: &str, limit: usize, store: &SavedPageStore) -> Result<Vec<SearchHit>, String> {
+        match &self.engine {
+            Some(engine) => engine.search(api_key, query_text, limit, store),
+            None => Err("検索インデックスを作成できなかったため、検索は無効です".to_string()),
+        }
+    }
+}
+
+impl Engine {
+    fn new() -> Result<Self, String> {
+        let indexing = TextFieldIndexing::default()
+            .set_tokenizer(TOKENIZER_NAME)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        let text_options = TextOptions::default().set_indexing_options(indexing);
+
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            id: builder.add_text_field("id", STRING | STORED),
+            api_key: builder.add_text_field("api_key", STRING),
+            title: builder.add_text_field("title", text_options.clone()),
+            body: builder.add_text_field("body", text_options),
+        };
+        let index = Index::create_in_ram(builder.build());
+        index.tokenizers().register(TOKENIZER_NAME, TextAnalyzer::from(BigramTokenizer));
+
+        let writer = index.writer_with_num_threads(1, WRITER_MEMORY_BYTES).map_err(|e| e.to_string())?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(|e: tantivy::TantivyError| e.to_string())?;
+        Ok(Self {
+            index,
+            writer: Arc::new(Mutex::new(writer)),
+            reader,
+            fields,
+        })
+    }
+
+    // 起動時に作るため、ここではそのままコミットする
+    fn build(store: &SavedPageStore) -> Result<Self, String> {
+        let engine = Self::new()?;
+        {
+            let mut writer = engine.writer.lock().map_err(|e| e.to_string())?;
+            for page in store.pages() {
+                writer.add_document(engine.page_document(page)).map_err(|e| e.to_string())?;
+            }
+            writer.commit().map_err(|e| e.to_string())?;
+        }
+        engine.reader.reload().map_err(|e| e.to_string())?;
+        Ok(engine)
+    }
+
+    // 変更してコミットし、検索に反映する
+    async fn update<F>(&self, change: F) -> Result<(), String>
+    where
+        F: FnOnce(&IndexWriter) -> tantivy::Result<()> + Send + 'static,
+    {
+        let writer = self.writer.clone();
+        let reader = self.reader.clone();
+        run_blocking(move || {
+            let mut writer = writer.lock().map_err(|e| e.to_string())?;
+            change(&writer).map_err(|e| e.to_string())?;
+            writer.commit().map_err(|e| e.to_string())?;
+            reader.reload().map_err(|e| e.to_string())
+        })
+        .await?
+    }
+
+    fn search(&self, api_key: &str, query_text: &str, limit: usize, store: &SavedPageStore) -> Result<Vec<SearchHit>, String> {
+        let key_query = TermQuery::new(Term::from_field_text(self.fields.api_key, api_key), IndexRecordOption::Basic);
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, Box::new(key_query))];
+
+        // 1文字だけの日本語等の検索語は2文字ずつのトークンに一致しないため別に探す
+        let mut words = Vec::new();
+        for word in query_text.split_whitespace() {
+            let mut chars = word.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if char_kind(c) == CharKind::Cjk => clauses.push((Occur::Must, self.single_char_query(c)?)),
+                _ => words.push(word),
+            }
+        }
+        if !words.is_empty() {
+            let mut parser = QueryParser::for_index(&self.index, vec![self.fields.title, self.fields.body]);
+            parser.set_conjunction_by_default();
+            parser.set_field_boost(self.fields.title, TITLE_BOOST);
+            // 記号等の構文エラーは無視して検索する
+            let (text_query, _) = parser.parse_query_lenient(&words.join(" "));
+            clauses.push((Occur::Must, text_query));
+        }
+        let query = BooleanQuery::new(clauses);
+
+        let searcher = self.reader.searcher();
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit)).map_err(|e| e.to_string())?;
+        let mut snippet_generator = SnippetGenerator::create(&searcher, &query, self.fields.body).map_err(|e| e.to_string())?;
+        snippet_generator.set_max_num_chars(SNIPPET_MAX_BYTES);
+
+        let mut hits = Vec::new();
+        for (score, address) in top_docs {
+            let document: TantivyDocument = searcher.doc(address).map_err(|e| e.to_string())?;
+            let id = document.get_first(self.fields.id).and_then(|value| value.as_str()).unwrap_or_default();
+            let page = match store.get(id, api_key) {
+                Some(page) => page,
+                None => continue,
+            };
+            let snippet = snippet_generator.snippet(&page.text);
+            let (snippet_text, snippet_html) = if snippet.fragment().is_empty() {
+                // タイトルのみに一致した場合等は本文の先頭
+                let head = text_head(&page.text, SNIPPET_MAX_BYTES);
+                (head.clone(), htmlescape::encode_minimal(&head))
+            } else {
+                (snippet.fragment().to_string(), snippet.to_html())
+            };
+            hits.push(SearchHit {
+                page,
+                score,
+                snippet: snippet_text,
+                snippet_html,
+            });
+        }
+        Ok(hits)
+    }
+
+    // その文字を含むトークン（1文字のトークンと、前後の文字との2文字のトークン）に一致する
+    fn single_char_query(&self, c: char) -> Result<Box<dyn Query>, String> {
+        let pattern = format!(".*{}.*", c);
+        let title = RegexQuery::from_pattern(&pattern, self.fields.title).map_err(|e| e.to_string())?;
+        let body = RegexQuery::from_pattern(&pattern, self.fields.body).map_err(|e| e.to_string())?;
+        Ok(Box::new(BooleanQuery::new(vec![
+            (Occur::Should, Box::new(BoostQuery::new(Box::new(title), TITLE_BOOST)) as Box<dyn Query>),
+            (Occur::Should, Box::new(body)),
+        ])))
+    }
+
+    fn page_document(&self, page: &SavedPage) -> TantivyDocument {
+        doc!(
+            self.fields.id => page.id.as_str(),
+            self.fields.api_key => page.api_key.as_str(),
+            self.fields.title => page.title.as_str(),
+            self.fields.body => page.text.as_str(),
+        )
+    }
+}
+
+// ========== HTMLの出力 ==========
+
+// 検索フォームと結果（page_linkは保存したページのIDを受け取り表示用のURLを返す）
+pub fn render_search_page(query: &str, api_key: &str, hits: &[SearchHit], list_link: &str, page_link: impl Fn(&str) -> String) -> String {
+    let escape = |text: &str| htmlescape::encode_minimal(text);
+    let mut html = format!(
+        "<h1>保存したページを検索</h1>\n<form method=\"get\" action=\"/search\"><input type=\"text\" name=\"q\" value=\"{}\"><input type=\"hidden\" name=\"api_key\" value=\"{}\"><button type=\"submit\">検索</button></form>\n",
+        escape(query).replace('"', "&quot;"),
+        escape(api_key).replace('"', "&quot;")
+    );
+
+    if !query.trim().is_empty() {
+        if hits.is_empty() {
+            html.push_str("<p>一致するページはありません。</p>\n");
+        }
+        html.push_str("<ol>\n");
+        for hit in hits {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a><br>{}<br><small>{} {}</small></li>\n",
+                escape(&page_link(&hit.page.id)),
+                escape(&hit.page.title),
+                hit.snippet_html,
+                escape(&hit.page.saved_at),
+                escape(&hit.page.url)
+            ));
+        }
+        html.push_str("</ol>\n");
+    }
+
+    html.push_str(&format!(
+        "<p><a href=\"{}\">保存したページの一覧</a><a href=\"/\">ホーム画面に戻る</a></p>\n",
+        escape(list_link)
+    ));
+    html
+}
+
+// ========== ヘルパー関数 ==========
+
+// 文字の途中で切らないように先頭max_bytesバイトまでを返す
+fn text_head(text: &str, max_bytes: usize) -> String {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// ========== 型エイリアス ==========
+pub type SharedSearchIndex = Arc<SearchIndex>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_texts(text: &str) -> Vec<String> {
+        tokenize(text).into_iter().map(|token| token.text).collect()
+    }
+
+    fn saved_page(id: &str, api_key: &str, title: &str, text: &str) -> SavedPage {
+        SavedPage {
+            id: id.to_string(),
+            api_key: api_key.to_string(),
+            url: format!("https://example.com/{}", id),
+            title: title.to_string(),
+            saved_at: "2024-01-01T00:00:00Z".to_string(),
+            original_size_bytes: 0,
+            content: String::new(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn words_are_lowercased_and_cjk_is_split_into_bigrams() {
+        assert_eq!(token_texts("Rust入門書、第2版"), vec!["rust", "入門", "門書", "第", "2", "版"]);
+        assert_eq!(token_texts("東 京"), vec!["東", "京"]);
+        assert_eq!(token_texts("  ---  "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn token_offsets_point_into_the_original_text() {
+        let text = "abc 日本語";
+        for token in tokenize(text) {
+            assert_eq!(text[token.offset_from..token.offset_to].to_lowercase(), token.text);
+        }
+    }
+
+    #[tokio::test]
+    async fn single_cjk_characters_match_inside_words() {
+        let mut store = SavedPageStore::new();
+        store.add(saved_page("p1", "key1", "東京の天気", "明日は晴れです"), 10);
+        store.add(saved_page("p2", "key1", "大阪", "雨が降ります"), 10);
+        store.add(saved_page("p3", "key2", "京都", "曇り"), 10);
+        let index = SearchIndex::build(&store);
+
+        let ids = |hits: Vec<SearchHit>| hits.into_iter().map(|hit| hit.page.id).collect::<Vec<_>>();
+        assert_eq!(ids(index.search("key1", "京", 10, &store).unwrap()), vec!["p1"]);
+        assert_eq!(ids(index.search("key1", "雨", 10, &store).unwrap()), vec!["p2"]);
+        assert_eq!(ids(index.search("key1", "天気 晴", 10, &store).unwrap()), vec!["p1"]);
+
+        index.remove_page("p1").await.unwrap();
+        assert!(index.search("key1", "京", 10, &store).unwrap().is_empty());
+    }
+}
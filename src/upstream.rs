@@ -0,0 +1,189 @@
+use crate::config::{Config, ProxyRule};
+use crate::fetcher::FetchContext;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_LANGUAGE, CACHE_CONTROL, PRAGMA, USER_AGENT};
+use url::Url;
+
+// 上流サイトへのリクエストヘッダー（User-Agentプロファイル、Accept-Language、ホストごとの上書き）と
+// 上流への接続経路（直接またはHTTP/SOCKS5プロキシ）
+
+// ========== 定数 ==========
+
+// 組み込みのUser-Agentプロファイル
+const BUILTIN_USER_AGENTS: &[(&str, &str)] = &[
+    (
+        "desktop",
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    ),
+    (
+        "mobile",
+        "Mozilla/5.0 (Linux; Android 10; K) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Mobile Safari/537.36",
+    ),
+    ("text-browser", "Lynx/2.9.0 libwww-FM/2.14 SSL-MM/1.4.1 OpenSSL/3.0.2"),
+];
+
+// ========== User-Agentプロファイル ==========
+
+// 設定ファイルのプロファイルを優先し、なければ組み込みプロファイルから探す
+pub fn find_user_agent(name: &str) -> Option<String> {
+    Config::global()
+        .upstream
+        .user_agents
+        .get(name)
+        .cloned()
+        .or_else(|| {
+            BUILTIN_USER_AGENTS
+                .iter()
+                .find(|(builtin_name, _)| *builtin_name == name)
+                .map(|(_, user_agent)| user_agent.to_string())
+        })
+}
+
+pub fn available_user_agents() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_USER_AGENTS.iter().map(|(name, _)| name.to_string()).collect();
+    for name in Config::global().upstream.user_agents.keys() {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+// ========== ヘッダーの適用 ==========
+
+// 優先順位: ホストごとの上書き > リクエスト・APIキーの指定 > 設定の既定値
+// （同じ名前のヘッダーは後から入れたもので置き換え、重複して送らない）
+pub fn apply_request_headers(builder: reqwest::RequestBuilder, url: &Url, context: &FetchContext) -> reqwest::RequestBuilder {
+    let upstream = &Config::global().upstream;
+    let mut headers = HeaderMap::new();
+
+    let user_agent_name = context.user_agent.as_deref().or(upstream.default_user_agent.as_deref());
+    if let Some(user_agent) = user_agent_name.and_then(find_user_agent) {
+        insert_header(&mut headers, USER_AGENT.as_str(), &user_agent);
+    }
+    if let Some(language) = &context.accept_language {
+        insert_header(&mut headers, ACCEPT_LANGUAGE.as_str(), language);
+    }
+    if context.bypass_cache {
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+    }
+
+    let host = url.host_str().unwrap_or("");
+    for rule in upstream.host_headers.iter().filter(|rule| host_matches(&rule.host, host)) {
+        for (name, value) in &rule.headers {
+            insert_header(&mut headers, name, value);
+        }
+    }
+
+    builder.headers(headers)
+}
+
+// ヘッダー名や値として使えないものは警告して送らない
+fn insert_header(headers: &mut HeaderMap, name: &str, value: &str) {
+    match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+        (Ok(name), Ok(value)) => {
+            headers.insert(name, value);
+        }
+        _ => eprintln!("送信できないヘッダーです（無視します）: {}", name),
+    }
+}
+
+// ========== 接続経路 ==========
+
+pub enum UpstreamRoute {
+    Direct,
+    // 認証情報を含むプロキシのURL
+    Proxy(Url),
+}
+
+// ログ用の表示（認証情報は伏せる）
+impl std::fmt::Display for UpstreamRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UpstreamRoute::Direct => write!(f, "直接接続"),
+            UpstreamRoute::Proxy(proxy_url) => {
+                let mut masked = proxy_url.clone();
+                if !masked.username().is_empty() {
+                    let _ = masked.set_username("***");
+                    let _ = masked.set_password(None);
+                }
+                write!(f, "プロキシ経由 ({})", masked)
+            }
+        }
+    }
+}
+
+// 送信先URLに対する接続経路を決める（no_proxyに一致すれば直接接続）
+pub fn route_for(url: &Url) -> UpstreamRoute {
+    let upstream = &Config::global().upstream;
+    let host = url.host_str().unwrap_or("");
+
+    if upstream.no_proxy.iter().any(|pattern| host_matches(pattern, host)) {
+        return UpstreamRoute::Direct;
+    }
+
+    let rule = upstream
+        .proxies
+        .iter()
+        .find(|rule| rule.hosts.is_empty() || rule.hosts.iter().any(|pattern| host_matches(pattern, host)));
+    match rule.map(proxy_url_with_auth) {
+        Some(Ok(proxy_url)) => UpstreamRoute::Proxy(proxy_url),
+        Some(Err(e)) => {
+            eprintln!("プロキシ設定エラー（直接接続します）: {}", e);
+            UpstreamRoute::Direct
+        }
+        None => UpstreamRoute::Direct,
+    }
+}
+
+// 設定したプロキシ自体のホストか（プロキシは内部ネットワークにあってもよい）
+pub fn is_proxy_host(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    Config::global()
+        .upstream
+        .proxies
+        .iter()
+        .filter_map(|rule| Url::parse(&rule.url).ok())
+        .any(|proxy_url| proxy_url.host_str().map(|proxy_host| proxy_host.to_lowercase() == host).unwrap_or(false))
+}
+
+// プロキシが設定されている場合、送信先ごとに経路を選ぶreqwestのプロキシ設定を返す
+pub fn build_proxy() -> Option<reqwest::Proxy> {
+    if Config::global().upstream.proxies.is_empty() {
+        return None;
+    }
+    Some(reqwest::Proxy::custom(|url| match route_for(url) {
+        UpstreamRoute::Proxy(proxy_url) => Some(proxy_url),
+        UpstreamRoute::Direct => None,
+    }))
+}
+
+// ========== ヘルパー関数 ==========
+
+// 設定の認証情報をURLに埋め込む（reqwestはHTTPのBasic認証にもSOCKS5の認証にも使う）
+fn proxy_url_with_auth(rule: &ProxyRule) -> Result<Url, String> {
+    let mut proxy_url = Url::parse(&rule.url).map_err(|e| format!("{}: {}", rule.url, e))?;
+    if !matches!(proxy_url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!("{}: 対応していないスキームです", rule.url));
+    }
+    if let Some(username) = &rule.username {
+        proxy_url
+            .set_username(username)
+            .map_err(|_| format!("{}: ユーザー名を設定できません", rule.url))?;
+        proxy_url
+            .set_password(Some(rule.password.as_deref().unwrap_or("")))
+            .map_err(|_| format!("{}: パスワードを設定できません", rule.url))?;
+    }
+    Ok(proxy_url)
+}
+
+// "example.com" は完全一致、"*.example.com" はサブドメインとexample.com自体に一致する
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let host = host.trim_end_matches('.').to_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => pattern == "*" || host == pattern,
+    }
+}
@@ -0,0 +1,602 @@
+use crate::api_error::{ApiError, ErrorCode};
+use crate::api_types::{SnapshotInfo, WatchInfo};
+use crate::app_state::AppState;
+use crate::config::Config;
+use crate::document::{page_document, Block, Document};
+use crate::fetcher::get_page;
+use crate::persist::{self, random_hex_id, JsonFile, Persisted};
+use crate::process_options::ProcessOptions;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{RwLock, Semaphore};
+
+// ページの変更監視（APIキーごとに登録したURLを定期的に取得し、軽量化したテキストのスナップショットを保存して比較する）
+
+// ========== 定数 ==========
+const WATCHES_FILE: &str = "watches.json";
+// 取得時期になった監視を探す間隔
+const CHECK_TICK: Duration = Duration::from_secs(60);
+// 比較表の大きさの上限（超える場合は順序を考えずに追加・削除を求める）
+const MAX_DIFF_CELLS: usize = 4_000_000;
+// HTML表示で変更の前後に残す変更のないブロックの数
+const DIFF_CONTEXT_BLOCKS: usize = 2;
+
+// ========== データ構造 ==========
+
+// 軽量化したページのテキスト（ブロックは見出し・段落・リスト項目等の1つずつ）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub id: u64,
+    pub fetched_at: String,
+    pub title: Option<String>,
+    pub blocks: Vec<String>,
+}
+
+impl Snapshot {
+    pub fn info(&self) -> SnapshotInfo {
+        SnapshotInfo {
+            id: self.id,
+            fetched_at: self.fetched_at.clone(),
+            title: self.title.clone(),
+            block_count: self.blocks.len(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Watch {
+    pub id: String,
+    // 監視を登録したAPIキー（同じキーからのみ参照できる）
+    pub api_key: String,
+    pub url: String,
+    pub interval_minutes: u64,
+    // 取得に使う軽量化オプション（クエリパラメータと同じ値）
+    pub profile: Option<String>,
+    pub user_agent: Option<String>,
+    pub encoding: Option<String>,
+    pub created_at: String,
+    pub last_checked_at: Option<String>,
+    pub last_changed_at: Option<String>,
+    // 直前の取得が失敗した場合のエラー（成功すると消える）
+    pub last_error: Option<ApiError>,
+    // 古いものから並べる（内容が変わった場合のみ追加する）
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl Watch {
+    pub fn info(&self) -> WatchInfo {
+        WatchInfo {
+            id: self.id.clone(),
+            url: self.url.clone(),
+            interval_minutes: self.interval_minutes,
+            profile: self.profile.clone(),
+            ua: self.user_agent.clone(),
+            encoding: self.encoding.clone(),
+            created_at: self.created_at.clone(),
+            last_checked_at: self.last_checked_at.clone(),
+            last_changed_at: self.last_changed_at.clone(),
+            last_error: self.last_error.clone(),
+            snapshots: self.snapshots.iter().map(Snapshot::info).collect(),
+        }
+    }
+
+    pub fn snapshot(&self, id: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.id == id)
+    }
+
+    // 指定したスナップショットの1つ前
+    pub fn previous_snapshot(&self, id: u64) -> Option<&Snapshot> {
+        let position = self.snapshots.iter().position(|snapshot| snapshot.id == id)?;
+        position.checked_sub(1).map(|previous| &self.snapshots[previous])
+    }
+
+    fn option_params(&self) -> HashMap<String, String> {
+        let fields = [("profile", &self.profile), ("ua", &self.user_agent), ("encoding", &self.encoding)];
+        fields
+            .iter()
+            .filter_map(|(name, value)| value.as_ref().map(|v| (name.to_string(), v.clone())))
+            .collect()
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.last_checked_at
+            .as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| at.with_timezone(&Utc) + chrono::Duration::minutes(self.interval_minutes as i64) <= now)
+            .unwrap_or(true)
+    }
+}
+
+// 登録時に指定する内容
+pub struct NewWatch {
+    pub url: String,
+    pub interval_minutes: u64,
+    pub profile: Option<String>,
+    pub user_agent: Option<String>,
+    pub encoding: Option<String>,
+}
+
+// ========== 監視ストア ==========
+pub struct WatchStore {
+    watches: HashMap<String, Watch>,
+    file: JsonFile,
+}
+
+impl WatchStore {
+    pub fn new() -> Self {
+        Self {
+            watches: HashMap::new(),
+            file: JsonFile::new(WATCHES_FILE),
+        }
+    }
+
+    pub fn load_from_file() -> Self {
+        let mut store = Self::new();
+        if let Some(watches) = store.file.load() {
+            store.watches = watches;
+        }
+        store
+    }
+
+    pub fn count_for(&self, api_key: &str) -> usize {
+        self.watches.values().filter(|watch| watch.api_key == api_key).count()
+    }
+
+    // 最初の取得は登録したリクエストから行うため、取得中として記録しておく
+    pub fn create(&mut self, api_key: &str, new_watch: NewWatch) -> Watch {
+        let now = Utc::now().to_rfc3339();
+        let watch = Watch {
+            id: generate_watch_id(),
+            api_key: api_key.to_string(),
+            url: new_watch.url,
+            interval_minutes: new_watch.interval_minutes,
+            profile: new_watch.profile,
+            user_agent: new_watch.user_agent,
+            encoding: new_watch.encoding,
+            created_at: now.clone(),
+            last_checked_at: Some(now),
+            last_changed_at: None,
+            last_error: None,
+            snapshots: Vec::new(),
+        };
+        self.watches.insert(watch.id.clone(), watch.clone());
+        self.file.mark_dirty();
+        watch
+    }
+
+    // APIキーが登録した監視のみ返す（登録した順）
+    pub fn list(&self, api_key: &str) -> Vec<Watch> {
+        let mut watches: Vec<Watch> = self.watches.values().filter(|watch| watch.api_key == api_key).cloned().collect();
+        watches.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        watches
+    }
+
+    pub fn get(&self, id: &str, api_key: &str) -> Option<Watch> {
+        self.watches.get(id).filter(|watch| watch.api_key == api_key).cloned()
+    }
+
+    // 見つからない場合はfalse
+    pub fn remove(&mut self, id: &str, api_key: &str) -> bool {
+        if self.get(id, api_key).is_none() {
+            return false;
+        }
+        self.watches.remove(id);
+        self.file.mark_dirty();
+        true
+    }
+
+    // 取得時期になった監視を取得中として記録し、返す
+    fn start_due_checks(&mut self) -> Vec<Watch> {
+        let now = Utc::now();
+        let due: Vec<Watch> = self
+            .watches
+            .values_mut()
+            .filter(|watch| watch.is_due(now))
+            .map(|watch| {
+                watch.last_checked_at = Some(now.to_rfc3339());
+                watch.clone()
+            })
+            .collect();
+        if !due.is_empty() {
+            self.file.mark_dirty();
+        }
+        due
+    }
+
+    // 内容が直前のスナップショットと異なる場合のみ追加する
+    fn record_check(&mut self, id: &str, result: Result<(Option<String>, Vec<String>), ApiError>) {
+        let max_snapshots = Config::global().watch.max_snapshots.max(2);
+        let watch = match self.watches.get_mut(id) {
+            Some(watch) => watch,
+            // 取得中に削除された
+            None => return,
+        };
+        let now = Utc::now().to_rfc3339();
+        watch.last_checked_at = Some(now.clone());
+
+        match result {
+            Ok((title, blocks)) => {
+                watch.last_error = None;
+                let unchanged = watch
+                    .snapshots
+                    .last()
+                    .map(|last| last.title == title && last.blocks == blocks)
+                    .unwrap_or(false);
+                if !unchanged {
+                    let id = watch.snapshots.last().map(|last| last.id + 1).unwrap_or(1);
+                    watch.snapshots.push(Snapshot {
+                        id,
+                        fetched_at: now.clone(),
+                        title,
+                        blocks,
+                    });
+                    watch.last_changed_at = Some(now);
+                    let excess = watch.snapshots.len().saturating_sub(max_snapshots);
+                    watch.snapshots.drain(..excess);
+                }
+            }
+            Err(error) => watch.last_error = Some(error),
+        }
+        self.file.mark_dirty();
+    }
+}
+
+impl Persisted for WatchStore {
+    fn json_file(&self) -> &JsonFile {
+        &self.file
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.watches).map_err(|e| e.to_string())
+    }
+}
+
+// ========== 定期取得 ==========
+
+// 取得時期になった監視ごとにタスクを起動する（同時に取得する数はcheck_concurrencyまで）
+pub fn start_checker(state: AppState) {
+    persist::start_flusher(state.watches.clone());
+
+    let permits = Arc::new(Semaphore::new(Config::global().watch.check_concurrency.max(1)));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_TICK);
+        loop {
+            ticker.tick().await;
+            let due = state.watches.write().await.start_due_checks();
+            for watch in due {
+                let permits = permits.clone();
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let _permit = match permits.acquire_owned().await {
+                        Ok(permit) => permit,
+                        Err(_) => return,
+                    };
+                    check_watch(&watch, &state).await;
+                });
+            }
+        }
+    });
+}
+
+// ページを取得してテキストのスナップショットを記録する（変更を見逃さないようキャッシュは使わない）
+pub async fn check_watch(watch: &Watch, state: &AppState) {
+    let result = fetch_snapshot(watch, state).await;
+    state.watches.write().await.record_check(&watch.id, result);
+}
+
+async fn fetch_snapshot(watch: &Watch, state: &AppState) -> Result<(Option<String>, Vec<String>), ApiError> {
+    let key_profile = {
+        let store = state.api_key_store.read().await;
+        if !store.validate_key(&watch.api_key) {
+            return Err(ApiError::new(ErrorCode::InvalidApiKey, "監視を登録したAPIキーが削除されています"));
+        }
+        store.get_default_profile(&watch.api_key)
+    };
+    let mut options = ProcessOptions::from_params(&watch.option_params(), key_profile.as_deref())
+        .map_err(|message| ApiError::new(ErrorCode::InvalidOption, message))?;
+    options.bypass_cache = true;
+
+    let context = state.fetch_context(&watch.api_key, &options, None).await;
+    let result = get_page(&watch.url, &context).await;
+    state.save_fetch_context(&context).await;
+    let page = result.map_err(|e| ApiError::from(&e))?;
+
+    let document = page_document(&page, &options.profile);
+    let blocks = snapshot_blocks(&document);
+    let processed_size: usize = blocks.iter().map(|block| block.len()).sum();
+    if let Err(e) = state.api_key_store.write().await.add_usage(&watch.api_key, page.original_size(), processed_size as u64) {
+        eprintln!("使用量記録エラー: {}", e);
+    }
+    Ok((document.title, blocks))
+}
+
+// ブロックを1行ずつのテキストにする（表記はJSON APIのformat=textと同じ）
+fn snapshot_blocks(document: &Document) -> Vec<String> {
+    document
+        .blocks
+        .iter()
+        .map(|block| match block {
+            Block::Heading(_, heading) => heading.clone(),
+            Block::Text(paragraph) => paragraph.clone(),
+            Block::ListItem(item) => format!("- {}", item),
+            Block::Preformatted(preformatted) => preformatted.clone(),
+            Block::Link { url, text } if text == url => format!("<{}>", url),
+            Block::Link { url, text } => format!("{} <{}>", text, url),
+        })
+        .filter(|block| !block.trim().is_empty())
+        .collect()
+}
+
+// ========== 差分 ==========
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockChange {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+pub struct DiffBlock {
+    pub change: BlockChange,
+    pub text: String,
+}
+
+// 比較表の計算は大きくなることがある
+pub async fn diff_snapshots(from: Option<&Snapshot>, to: &Snapshot) -> Vec<DiffBlock> {
+    let old = from.map(|snapshot| snapshot.blocks.clone()).unwrap_or_default();
+    let new = to.blocks.clone();
+    persist::run_blocking(move || diff_blocks(&old, &new))
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("差分の計算エラー: {}", e);
+            Vec::new()
+        })
+}
+
+// 最長共通部分列で比較し、新しい方の順序で並べる（削除したブロックは元の位置に置く）
+fn diff_blocks(old: &[String], new: &[String]) -> Vec<DiffBlock> {
+    let block = |change: BlockChange, text: &String| DiffBlock { change, text: text.clone() };
+
+    // 先頭と末尾の共通部分は比較表に含めない
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut blocks: Vec<DiffBlock> = old[..prefix].iter().map(|text| block(BlockChange::Unchanged, text)).collect();
+    if old_middle.len().saturating_mul(new_middle.len()) > MAX_DIFF_CELLS {
+        blocks.extend(unordered_diff(old_middle, new_middle));
+    } else {
+        blocks.extend(lcs_diff(old_middle, new_middle));
+    }
+    blocks.extend(old[old.len() - suffix..].iter().map(|text| block(BlockChange::Unchanged, text)));
+    blocks
+}
+
+fn lcs_diff(old: &[String], new: &[String]) -> Vec<DiffBlock> {
+    // lengths[i][j] = old[i..]とnew[j..]の最長共通部分列の長さ
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            blocks.push(DiffBlock { change: BlockChange::Unchanged, text: new[j].clone() });
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            blocks.push(DiffBlock { change: BlockChange::Removed, text: old[i].clone() });
+            i += 1;
+        } else {
+            blocks.push(DiffBlock { change: BlockChange::Added, text: new[j].clone() });
+            j += 1;
+        }
+    }
+    blocks.extend(old[i..].iter().map(|text| DiffBlock { change: BlockChange::Removed, text: text.clone() }));
+    blocks.extend(new[j..].iter().map(|text| DiffBlock { change: BlockChange::Added, text: text.clone() }));
+    blocks
+}
+
+// 大きなページでは出現回数だけを比べる（削除したブロックを先に並べる）
+fn unordered_diff(old: &[String], new: &[String]) -> Vec<DiffBlock> {
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for text in new {
+        *remaining.entry(text.as_str()).or_default() += 1;
+    }
+    let mut blocks = Vec::new();
+    let mut kept: HashMap<&str, usize> = HashMap::new();
+    for text in old {
+        match remaining.get_mut(text.as_str()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                *kept.entry(text.as_str()).or_default() += 1;
+            }
+            _ => blocks.push(DiffBlock { change: BlockChange::Removed, text: text.clone() }),
+        }
+    }
+    for text in new {
+        let change = match kept.get_mut(text.as_str()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                BlockChange::Unchanged
+            }
+            _ => BlockChange::Added,
+        };
+        blocks.push(DiffBlock { change, text: text.clone() });
+    }
+    blocks
+}
+
+// ========== HTMLの出力 ==========
+
+// 変更したブロックを色分けし、変更のないブロックは前後の数件だけ残す
+// （スナップショットの一覧にはsnapshot_link（比較元と比較先のIDを受け取りURLを返す）でリンクする）
+pub fn render_diff_html(
+    watch: &Watch,
+    from: Option<&Snapshot>,
+    to: &Snapshot,
+    blocks: &[DiffBlock],
+    page_link: &str,
+    snapshot_link: impl Fn(Option<u64>, u64) -> String,
+) -> String {
+    let escape = |text: &str| htmlescape::encode_minimal(text);
+    let title = to.title.clone().unwrap_or_else(|| watch.url.clone());
+    let added = blocks.iter().filter(|block| block.change == BlockChange::Added).count();
+    let removed = blocks.iter().filter(|block| block.change == BlockChange::Removed).count();
+
+    let mut html = String::from(
+        r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"><title>変更の比較</title>
+<style>
+body { font-family: sans-serif; margin: 20px; line-height: 1.5; }
+.added { background: #e6ffec; border-left: 4px solid #2da44e; padding: 2px 6px; margin: 2px 0; }
+.removed { background: #ffebe9; border-left: 4px solid #cf222e; padding: 2px 6px; margin: 2px 0; text-decoration: line-through; }
+.unchanged { color: #555; padding: 2px 10px; margin: 2px 0; }
+.omitted { color: #999; font-size: small; padding: 2px 10px; }
+</style></head><body>
+"#,
+    );
+    html.push_str(&format!("<h1>{}</h1>\n", escape(&title)));
+    html.push_str(&format!("<p><a href=\"{}\">{}</a></p>\n", escape(page_link), escape(&watch.url)));
+    let from_label = from
+        .map(|snapshot| format!("#{}（{}）", snapshot.id, escape(&snapshot.fetched_at)))
+        .unwrap_or_else(|| "なし".to_string());
+    html.push_str(&format!(
+        "<p>比較元: {} → 比較先: #{}（{}）<br>追加 {}件 / 削除 {}件</p>\n",
+        from_label,
+        to.id,
+        escape(&to.fetched_at),
+        added,
+        removed
+    ));
+
+    if added == 0 && removed == 0 {
+        html.push_str("<p>変更はありません。</p>\n");
+    }
+    let near_change = |index: usize| {
+        let start = index.saturating_sub(DIFF_CONTEXT_BLOCKS);
+        let end = (index + DIFF_CONTEXT_BLOCKS + 1).min(blocks.len());
+        blocks[start..end].iter().any(|block| block.change != BlockChange::Unchanged)
+    };
+    let mut omitted = 0;
+    for (index, block) in blocks.iter().enumerate() {
+        let class = match block.change {
+            BlockChange::Added => "added",
+            BlockChange::Removed => "removed",
+            BlockChange::Unchanged if near_change(index) => "unchanged",
+            BlockChange::Unchanged => {
+                omitted += 1;
+                continue;
+            }
+        };
+        if omitted > 0 {
+            html.push_str(&format!("<div class=\"omitted\">…（変更のない{}件）</div>\n", omitted));
+            omitted = 0;
+        }
+        html.push_str(&format!("<div class=\"{}\">{}</div>\n", class, escape(&block.text)));
+    }
+    if omitted > 0 {
+        html.push_str(&format!("<div class=\"omitted\">…（変更のない{}件）</div>\n", omitted));
+    }
+
+    html.push_str("<h2>スナップショット</h2>\n<ul>\n");
+    for snapshot in watch.snapshots.iter().rev() {
+        let previous = watch.previous_snapshot(snapshot.id).map(|previous| previous.id);
+        html.push_str(&format!(
+            "<li><a href=\"{}\">#{} {}</a></li>\n",
+            escape(&snapshot_link(previous, snapshot.id)),
+            snapshot.id,
+            escape(&snapshot.fetched_at)
+        ));
+    }
+    html.push_str("</ul>\n<p><a href=\"/\">ホーム画面に戻る</a></p>\n</body></html>");
+    html
+}
+
+// ========== ヘルパー関数 ==========
+
+fn generate_watch_id() -> String {
+    random_hex_id(16)
+}
+
+// ========== 型エイリアス ==========
+pub type SharedWatchStore = Arc<RwLock<WatchStore>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    fn changes(blocks: &[DiffBlock]) -> Vec<(BlockChange, &str)> {
+        blocks.iter().map(|block| (block.change, block.text.as_str())).collect()
+    }
+
+    #[test]
+    fn diff_keeps_removed_blocks_in_place() {
+        let old = lines(&["見出し", "古い段落", "共通の段落", "末尾"]);
+        let new = lines(&["見出し", "共通の段落", "新しい段落", "末尾"]);
+        let blocks = diff_blocks(&old, &new);
+        assert_eq!(
+            changes(&blocks),
+            vec![
+                (BlockChange::Unchanged, "見出し"),
+                (BlockChange::Removed, "古い段落"),
+                (BlockChange::Unchanged, "共通の段落"),
+                (BlockChange::Added, "新しい段落"),
+                (BlockChange::Unchanged, "末尾"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_from_nothing_adds_every_block() {
+        let new = lines(&["a", "b"]);
+        let blocks = diff_blocks(&[], &new);
+        assert_eq!(changes(&blocks), vec![(BlockChange::Added, "a"), (BlockChange::Added, "b")]);
+        assert!(diff_blocks(&new, &new).iter().all(|block| block.change == BlockChange::Unchanged));
+    }
+
+    #[test]
+    fn unordered_diff_counts_repeated_blocks() {
+        let old = lines(&["a", "b", "b"]);
+        let new = lines(&["b", "c", "a"]);
+        let blocks = unordered_diff(&old, &new);
+        assert_eq!(
+            changes(&blocks),
+            vec![
+                (BlockChange::Removed, "b"),
+                (BlockChange::Unchanged, "b"),
+                (BlockChange::Added, "c"),
+                (BlockChange::Unchanged, "a"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_diffed_off_the_executor() {
+        let snapshot = |id, blocks: &[&str]| Snapshot { id, fetched_at: String::new(), title: None, blocks: lines(blocks) };
+        let blocks = diff_snapshots(Some(&snapshot(1, &["a"])), &snapshot(2, &["a", "b"])).await;
+        assert_eq!(changes(&blocks), vec![(BlockChange::Unchanged, "a"), (BlockChange::Added, "b")]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::openapi::{openapi_document, render_docs_html};
+
 // ========== ホームページ ==========
 pub fn get_home_page_html() -> &'static str {
     r#"
@@ -95,6 +97,46 @@ pub fn get_home_page_html() -> &'static str {
             margin: 20px 0;
             font-size: 14px;
         }
+        .start-page h2 {
+            font-size: 18px;
+            margin: 25px 0 10px;
+        }
+        .start-page h3 {
+            font-size: 15px;
+            margin: 15px 0 5px;
+            color: #555;
+        }
+        .start-page ul {
+            list-style: none;
+            padding: 0;
+            margin: 0;
+        }
+        .start-page li {
+            padding: 6px 0;
+            border-bottom: 1px solid #eee;
+            font-size: 14px;
+        }
+        .start-page li small {
+            color: #6c757d;
+            display: block;
+        }
+        .start-page li button {
+            padding: 2px 8px;
+            font-size: 12px;
+            margin-left: 6px;
+        }
+        .start-page .danger {
+            background-color: #dc3545;
+        }
+        .start-page .controls {
+            font-size: 14px;
+            margin-bottom: 10px;
+        }
+        .start-page .bookmark-form input[type="text"] {
+            width: 28%;
+            margin-right: 4px;
+            padding: 6px;
+        }
     </style>
 </head>
 <body>
@@ -117,9 +159,29 @@ pub fn get_home_page_html() -> &'static str {
         <div id="result" class="result"></div>
 
         <div class="admin-link">
+            <a href="/saved" onclick="return openSavedPages()">📄 保存したページ</a>
+            <a href="/search" onclick="return openSearch()">🔍 保存したページを検索</a>
             <a href="/admin">🔧 管理画面</a>
         </div>
 
+        <div id="startPage" class="start-page" style="display: none;">
+            <h2>最近の履歴</h2>
+            <div class="controls">
+                <label><input type="checkbox" id="historyEnabled" onchange="setHistoryEnabled(this.checked)"> 履歴を記録する</label>
+                <button class="danger" onclick="clearHistory()">履歴を消去</button>
+            </div>
+            <ul id="historyList"></ul>
+
+            <h2>ブックマーク</h2>
+            <div class="bookmark-form">
+                <input type="text" id="bookmarkUrl" placeholder="URL">
+                <input type="text" id="bookmarkTitle" placeholder="タイトル（省略可）">
+                <input type="text" id="bookmarkFolder" placeholder="フォルダ（省略可）">
+                <button onclick="addBookmark(getInputValue('bookmarkUrl'), getInputValue('bookmarkTitle'), getInputValue('bookmarkFolder'))">追加</button>
+            </div>
+            <div id="bookmarkList"></div>
+        </div>
+
         <div class="api-info">
             <h3>API使用方法</h3>
             <p><strong>GET:</strong> <code>/proxy?url=https://example.com&api_key=your_key</code></p>
@@ -139,6 +201,7 @@ pub fn get_home_page_html() -> &'static str {
             if (savedApiKey) {
                 document.getElementById('apiKey').value = savedApiKey;
                 document.getElementById('deleteApiKeyBtn').style.display = 'inline-block';
+                loadStartPage();
             }
         };
 
@@ -165,6 +228,7 @@ pub fn get_home_page_html() -> &'static str {
             savedApiKey = apiKey;
             showResult('APIキーを保存しました', 'success');
             document.getElementById('deleteApiKeyBtn').style.display = 'inline-block';
+            loadStartPage();
         }
 
         function deleteApiKey() {
@@ -173,6 +237,7 @@ pub fn get_home_page_html() -> &'static str {
             document.getElementById('apiKey').value = '';
             showResult('APIキーを削除しました', 'success');
             document.getElementById('deleteApiKeyBtn').style.display = 'none';
+            document.getElementById('startPage').style.display = 'none';
         }
 
         // ========== URL処理 ==========
@@ -206,12 +271,198 @@ pub fn get_home_page_html() -> &'static str {
                         showResult('軽量化完了！ポップアップがブロックされました', 'success');
                     }
                 } else {
-                    const errorText = await response.text();
-                    showResult(`エラー: ${response.status} - ${errorText}`, 'error');
+                    // エラーページのメッセージ部分だけを表示する
+                    const errorPage = new DOMParser().parseFromString(await response.text(), 'text/html');
+                    const message = errorPage.querySelector('p')?.textContent || response.statusText;
+                    showResult(`エラー: ${response.status} - ${message}`, 'error');
+                }
+            } catch (error) {
+                showResult(`エラー: ${error.message}`, 'error');
+            }
+        }
+
+        // ========== 保存したページ ==========
+        function openSavedPages() {
+            const apiKey = getInputValue('apiKey') || savedApiKey;
+            if (!apiKey) {
+                showResult('APIキーを入力してください', 'error');
+                return false;
+            }
+            window.location.href = `/saved?api_key=${encodeURIComponent(apiKey)}`;
+            return false;
+        }
+
+        function openSearch() {
+            const apiKey = getInputValue('apiKey') || savedApiKey;
+            if (!apiKey) {
+                showResult('APIキーを入力してください', 'error');
+                return false;
+            }
+            window.location.href = `/search?api_key=${encodeURIComponent(apiKey)}`;
+            return false;
+        }
+
+        // ========== スタートページ（閲覧履歴とブックマーク） ==========
+        // APIキーを保存している場合のみ表示する
+        async function loadStartPage() {
+            document.getElementById('startPage').style.display = 'block';
+            await Promise.all([loadHistory(), loadBookmarks()]);
+        }
+
+        async function startPageRequest(url, method = 'GET', body = null) {
+            const options = { method, headers: { 'X-API-Key': savedApiKey } };
+            if (body) {
+                options.headers['Content-Type'] = 'application/json';
+                options.body = JSON.stringify(body);
+            }
+            const response = await fetch(url, options);
+            const data = await response.json();
+            if (!data.success) {
+                throw new Error(data.error.message);
+            }
+            return data;
+        }
+
+        function proxyLink(url) {
+            return `/proxy?url=${encodeURIComponent(url)}&api_key=${encodeURIComponent(savedApiKey)}`;
+        }
+
+        // ページのタイトルは任意の文字列のためtextContentで入れる
+        function pageItem(url, title, detail) {
+            const item = document.createElement('li');
+            const link = document.createElement('a');
+            link.href = proxyLink(url);
+            link.textContent = title;
+            item.appendChild(link);
+            const small = document.createElement('small');
+            small.textContent = detail;
+            item.appendChild(small);
+            return item;
+        }
+
+        function smallButton(label, onClick, className = '') {
+            const button = document.createElement('button');
+            button.textContent = label;
+            button.className = className;
+            button.onclick = onClick;
+            return button;
+        }
+
+        async function loadHistory() {
+            const list = document.getElementById('historyList');
+            list.innerHTML = '';
+            try {
+                const data = await startPageRequest('/api/history?limit=20');
+                document.getElementById('historyEnabled').checked = data.enabled;
+                if (data.entries.length === 0) {
+                    list.innerHTML = `<li>${data.enabled ? '閲覧履歴はありません' : '閲覧履歴を記録していません'}</li>`;
+                    return;
                 }
+                for (const entry of data.entries) {
+                    const visitedAt = new Date(entry.visited_at).toLocaleString();
+                    const item = pageItem(entry.url, entry.title, `${visitedAt} ${formatBytes(entry.bytes_saved)}削減`);
+                    item.insertBefore(smallButton('☆', () => addBookmark(entry.url, entry.title, '')), item.querySelector('small'));
+                    list.appendChild(item);
+                }
+            } catch (error) {
+                const item = document.createElement('li');
+                item.textContent = `閲覧履歴の取得エラー: ${error.message}`;
+                list.appendChild(item);
+            }
+        }
+
+        async function setHistoryEnabled(enabled) {
+            if (!enabled && !confirm('履歴の記録を止めると、これまでの閲覧履歴も削除されます。よろしいですか？')) {
+                document.getElementById('historyEnabled').checked = true;
+                return;
+            }
+            try {
+                const data = await startPageRequest('/api/history/settings', 'PATCH', { enabled });
+                showResult(data.message, 'success');
             } catch (error) {
                 showResult(`エラー: ${error.message}`, 'error');
             }
+            await loadHistory();
+        }
+
+        async function clearHistory() {
+            if (!confirm('閲覧履歴をすべて削除しますか？')) {
+                return;
+            }
+            try {
+                const data = await startPageRequest('/api/history', 'DELETE');
+                showResult(data.message, 'success');
+            } catch (error) {
+                showResult(`エラー: ${error.message}`, 'error');
+            }
+            await loadHistory();
+        }
+
+        async function loadBookmarks() {
+            const container = document.getElementById('bookmarkList');
+            container.innerHTML = '';
+            try {
+                const data = await startPageRequest('/api/bookmarks');
+                if (data.bookmarks.length === 0) {
+                    container.textContent = 'ブックマークはありません。履歴の☆から追加できます';
+                    return;
+                }
+                // listの結果はフォルダなし・フォルダ名順に並んでいる
+                let list = null;
+                let currentFolder = null;
+                for (const bookmark of data.bookmarks) {
+                    if (bookmark.folder !== currentFolder) {
+                        currentFolder = bookmark.folder;
+                        if (currentFolder) {
+                            const heading = document.createElement('h3');
+                            heading.textContent = `📁 ${currentFolder}`;
+                            container.appendChild(heading);
+                        }
+                        list = document.createElement('ul');
+                        container.appendChild(list);
+                    }
+                    const item = pageItem(bookmark.url, bookmark.title, bookmark.url);
+                    item.insertBefore(smallButton('削除', () => deleteBookmark(bookmark.id), 'danger'), item.querySelector('small'));
+                    list.appendChild(item);
+                }
+            } catch (error) {
+                container.textContent = `ブックマークの取得エラー: ${error.message}`;
+            }
+        }
+
+        async function addBookmark(url, title, folder) {
+            if (!url) {
+                showResult('URLを入力してください', 'error');
+                return;
+            }
+            const body = { url };
+            if (title) body.title = title;
+            if (folder) body.folder = folder;
+            try {
+                await startPageRequest('/api/bookmarks', 'POST', body);
+                showResult('ブックマークに追加しました', 'success');
+                ['bookmarkUrl', 'bookmarkTitle', 'bookmarkFolder'].forEach(id => document.getElementById(id).value = '');
+            } catch (error) {
+                showResult(`エラー: ${error.message}`, 'error');
+            }
+            await loadBookmarks();
+        }
+
+        async function deleteBookmark(id) {
+            try {
+                await startPageRequest(`/api/bookmarks/${encodeURIComponent(id)}`, 'DELETE');
+            } catch (error) {
+                showResult(`エラー: ${error.message}`, 'error');
+            }
+            await loadBookmarks();
+        }
+
+        function formatBytes(bytes) {
+            if (bytes === 0) return '0 bytes';
+            const k = 1024;
+            const sizes = ['bytes', 'KB', 'MB', 'GB', 'TB'];
+            const i = Math.floor(Math.log(bytes) / Math.log(k));
+            return parseFloat((bytes / Math.pow(k, i)).toFixed(1)) + ' ' + sizes[i];
         }
 
         // ========== キーボードイベント ==========
@@ -231,44 +482,33 @@ pub fn get_home_page_html() -> &'static str {
 }
 
 // ========== APIドキュメント ==========
-pub fn get_api_docs_html() -> &'static str {
-    r#"
+// 本文はOpenAPIドキュメントから生成する
+pub fn get_api_docs_html() -> String {
+    format!(
+        r#"
 <!DOCTYPE html>
 <html>
 <head>
     <title>Rigil Proxy API</title>
     <meta charset="UTF-8">
     <style>
-        body { font-family: 'Segoe UI', sans-serif; margin: 40px auto; max-width: 800px; line-height: 1.6; }
-        code { background: #f4f4f4; padding: 2px 6px; border-radius: 3px; }
-        pre { background: #f4f4f4; padding: 15px; border-radius: 4px; overflow-x: auto; }
-        h1 { border-bottom: 2px solid #007bff; padding-bottom: 10px; }
+        body {{ font-family: 'Segoe UI', sans-serif; margin: 40px auto; max-width: 800px; line-height: 1.6; }}
+        code {{ background: #f4f4f4; padding: 2px 6px; border-radius: 3px; }}
+        pre {{ background: #f4f4f4; padding: 15px; border-radius: 4px; overflow-x: auto; }}
+        h1 {{ border-bottom: 2px solid #007bff; padding-bottom: 10px; }}
+        table {{ border-collapse: collapse; margin: 10px 0; }}
+        th, td {{ border: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }}
     </style>
 </head>
 <body>
     <h1>Rigil Proxy API</h1>
-    
-    <h2>エンドポイント</h2>
-    
-    <h3>HTML軽量化</h3>
-    <p><strong>GET</strong> <code>/proxy?url=https://example.com&api_key=your_key</code></p>
-    <p>軽量化されたHTMLを返します。</p>
-
-    <h3>JSON API</h3>
-    <p><strong>GET</strong> <code>/api/process?url=https://example.com&api_key=your_key</code></p>
-    <p>JSON形式で結果を返します：</p>
-    <pre>{
-  "success": true,
-  "data": "&lt;html&gt;...&lt;/html&gt;",
-  "error": null,
-  "original_url": "https://example.com",
-  "processed_at": "2024-01-01T12:00:00Z"
-}</pre>
-
+    {}
     <p><a href="/">← ホームに戻る</a></p>
 </body>
 </html>
-    "#
+    "#,
+        render_docs_html(openapi_document())
+    )
 }
 
 // ========== 管理画面 ==========
@@ -625,7 +865,7 @@ pub fn get_admin_page_html() -> &'static str {
             const response = await fetch(urlWithAuth);
             const data = await response.json();
 
-            if (data.error && data.error.includes('管理者権限')) {
+            if (response.status === 401) {
                 logout();
                 return null;
             }
@@ -778,7 +1018,7 @@ pub fn get_admin_page_html() -> &'static str {
                     showResult(resultBox, `APIキー "${apiKey}" を削除しました`, 'success');
                     loadApiKeys();
                     loadStatistics();
-                } else if (data.error && data.error.includes('管理者権限')) {
+                } else if (response.status === 401) {
                     logout();
                 } else {
                     showResult(resultBox, `削除エラー: ${data.error}`, 'error');